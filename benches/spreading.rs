@@ -0,0 +1,100 @@
+//! Times `genin build` over generated clusters of growing size.
+//!
+//! ```sh
+//! cargo bench --bench spreading
+//! GENIN_BENCH_SIZES=20000x2000 cargo bench --bench spreading
+//! ```
+//! Sizes are given as `<instances>x<hosts>`, hosts are split between ten
+//! datacenters.
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+const GENIN_CMD: &str = env!("CARGO_BIN_EXE_genin");
+const DEFAULT_SIZES: &str = "1000x100,5000x500,20000x2000";
+const DATACENTERS: usize = 10;
+const RUNS: usize = 3;
+
+fn cluster(instances: usize, hosts: usize) -> String {
+    let mut config = String::from("---\ntopology:\n");
+    writeln!(
+        config,
+        "  - name: router\n    replicasets_count: {}\n    roles: [router, failover-coordinator]",
+        instances / 10
+    )
+    .unwrap();
+    writeln!(
+        config,
+        "  - name: storage\n    replicasets_count: {}\n    replication_factor: 3\n    roles: [storage]",
+        (instances - instances / 10) / 3
+    )
+    .unwrap();
+
+    config.push_str("hosts:\n");
+    (0..DATACENTERS).for_each(|dc| {
+        writeln!(config, "  - name: dc-{}\n    hosts:", dc + 1).unwrap();
+        (0..hosts / DATACENTERS).for_each(|server| {
+            let index = dc * (hosts / DATACENTERS) + server;
+            writeln!(
+                config,
+                "      - name: server-{}\n        config:\n          address: 10.{}.{}.{}",
+                index + 1,
+                dc,
+                server / 250,
+                server % 250 + 1
+            )
+            .unwrap();
+        })
+    });
+
+    config.push_str(
+        "failover:\n  mode: disabled\nvars:\n  ansible_user: ansible\n  ansible_password: ansible\n  cartridge_app_name: myapp\n  cartridge_cluster_cookie: myapp-cookie\n",
+    );
+    config
+}
+
+fn build(dir: &Path) -> Duration {
+    let start = Instant::now();
+    let output = Command::new(GENIN_CMD)
+        .arg("build")
+        .arg("-s")
+        .arg(dir.join("cluster.genin.yml"))
+        .arg("-o")
+        .arg(dir.join("inventory.yml"))
+        .arg("--state-dir")
+        .arg(dir.join(".geninstate"))
+        .arg("--recreate")
+        .arg("--force")
+        .arg("--quiet")
+        .output()
+        .expect("Failed to execute command");
+    let elapsed = start.elapsed();
+
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    elapsed
+}
+
+fn main() {
+    let sizes = std::env::var("GENIN_BENCH_SIZES").unwrap_or_else(|_| DEFAULT_SIZES.into());
+    let base_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("spreading");
+
+    for size in sizes.split(',') {
+        let (instances, hosts) = size
+            .split_once('x')
+            .and_then(|(instances, hosts)| Some((instances.parse().ok()?, hosts.parse().ok()?)))
+            .unwrap_or_else(|| panic!("invalid size {size}, expected <instances>x<hosts>"));
+
+        let dir = base_dir.join(size);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("cluster.genin.yml"), cluster(instances, hosts)).unwrap();
+
+        let best = (0..RUNS).map(|_| build(&dir)).min().unwrap();
+        println!("{instances:>6} instances on {hosts:>5} hosts: {best:?}");
+    }
+}
@@ -134,3 +134,11 @@ resource "openstack_compute_instance_v2" "genin_host" {
 
   user_data = data.cloudinit_config.cloud_init.rendered
 }
+
+output "genin_hosts" {
+  value = [for host in openstack_compute_instance_v2.genin_host : {
+    name       = host.name
+    address    = host.network[0].fixed_ip_v4
+    datacenter = host.availability_zone
+  }]
+}
@@ -14,13 +14,21 @@ use serde_yaml::{Mapping, Value};
 use std::convert::TryFrom;
 use std::error::Error;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 use std::{fmt, io};
 
 use crate::error::{GeninError, GeninErrorKind};
+use crate::task::cluster::lint::{self, Linter, Severity};
 use crate::task::cluster::ClusterError;
-use crate::task::state::State;
+use crate::task::state::backend::backend;
+use crate::task::state::diff::{self, Plan, StateDiff};
+use crate::task::state::lock::StateLock;
+use crate::task::state::retention::RetentionPolicy;
+use crate::task::state::secrets::SecretsPolicy;
+use crate::task::state::{State, StateError};
 use crate::task::{cluster::Cluster, inventory::Inventory};
+use crate::DEFAULT_CFG_NAME;
 
 const BOOL: &str = "Bool";
 const NUMBER: &str = "Number";
@@ -69,35 +77,80 @@ pub fn run_v2() -> Result<(), Box<dyn Error>> {
                 .clear_instances()
                 .write(args)?;
         }
+        Some(("build", args)) if args.get_flag("check") => {
+            let latest = if args.get_flag("recreate") {
+                None
+            } else {
+                State::latest_if_any(args)?
+            };
+            let cluster = match latest {
+                Some(state) => Cluster::merge_into_state(state, args)?.0,
+                None => Cluster::try_from(args)?,
+            };
+
+            cluster
+                .use_failure_domain_as_zone_for_instances(args)
+                .print(args)
+                .to_inventory()?
+                .check(args)?;
+        }
+        Some(("build", args)) if args.get_flag("dry-run") => {
+            let latest = if args.get_flag("recreate") {
+                None
+            } else {
+                State::latest_if_any(args)?
+            };
+            let plan = match latest {
+                Some(state) => {
+                    let latest: Cluster = state.clone().into();
+                    let (old, hosts_diff) = Cluster::merge_into_state(state, args)?;
+
+                    let old = old
+                        .use_failure_domain_as_zone_for_instances(args)
+                        .print(args);
+                    Plan::new(Some(&latest), &old, hosts_diff)
+                }
+                None => {
+                    println!("No previous state found, a new one would be created");
+                    let cluster = Cluster::try_from(args)?
+                        .use_failure_domain_as_zone_for_instances(args)
+                        .print(args);
+                    Plan::new(None, &cluster, Vec::new())
+                }
+            };
+
+            print!("{plan}");
+        }
+        Some(("verify", args)) => {
+            Inventory::verify(args.get_one::<String>("inventory").unwrap())?;
+        }
         Some(("build", args)) => {
-            if args.get_flag("recreate") {
-                State::recreate(args)?;
+            let recreate = args.get_flag("recreate");
+            // the state directory is not locked while waiting for the answer
+            if !recreate
+                && !args.get_flag("yes")
+                && State::latest_if_any(args)?.is_some()
+                && !Confirm::new()
+                    .with_prompt(
+                        style("Previous inventory state detected. Want to update it?")
+                            .red()
+                            .to_string(),
+                    )
+                    .interact()?
+            {
+                println!("To recreate the inventory, use the command: genin build --recreate");
+                return Ok(());
             }
 
-            match State::from_latest(args) {
-                Ok(state) => {
-                    if !args.get_flag("yes")
-                        && !Confirm::new()
-                            .with_prompt(
-                                style("Previous inventory state detected. Want to update it?")
-                                    .red()
-                                    .to_string(),
-                            )
-                            .interact()?
-                    {
-                        println!(
-                            "To recreate the inventory, use the command: genin build --recreate"
-                        );
-                        return Ok(());
-                    }
+            let _lock = StateLock::acquire(&state::state_dir(args))?;
 
-                    let mut old: Cluster = state.into();
-                    old.hosts.clear_view();
-
-                    let mut new =
-                        Cluster::try_from(args)?.use_failure_domain_as_zone_for_instances(args);
+            if recreate {
+                State::recreate(args)?;
+            }
 
-                    let hosts_diff = old.merge(&mut new, args.get_flag("idiomatic-merge"))?;
+            match State::latest_if_any(args)? {
+                Some(state) => {
+                    let (old, hosts_diff) = Cluster::merge_into_state(state, args)?;
 
                     old.use_failure_domain_as_zone_for_instances(args)
                         .print(args)
@@ -105,7 +158,7 @@ pub fn run_v2() -> Result<(), Box<dyn Error>> {
                         .to_inventory()?
                         .write(args)?;
                 }
-                _ => {
+                None => {
                     Cluster::try_from(args)?
                         .use_failure_domain_as_zone_for_instances(args)
                         .print(args)
@@ -115,6 +168,40 @@ pub fn run_v2() -> Result<(), Box<dyn Error>> {
                 }
             }
         }
+        Some(("lint", args)) => {
+            if args.get_flag("rules") {
+                lint::RULES.iter().for_each(|rule| println!("{rule}"));
+                return Ok(());
+            }
+
+            let findings = Linter::try_from(args)?.lint();
+            findings.iter().for_each(|finding| println!("{finding}"));
+
+            let errors = findings
+                .iter()
+                .filter(|finding| finding.severity == Severity::Error)
+                .count();
+            println!("{} errors, {} warnings", errors, findings.len() - errors);
+            if errors > 0 {
+                return Err(ClusterError::Lint(errors).into());
+            }
+        }
+        Some(("explain", args)) => {
+            let latest = if args.get_flag("fresh") {
+                None
+            } else {
+                State::latest_if_any(args)?
+            };
+            let cluster = match latest {
+                Some(state) => Cluster::merge_into_state(state, args)?.0,
+                None => Cluster::try_from(args)?,
+            };
+
+            print!(
+                "{}",
+                cluster.explain(args.get_one::<String>("instance").unwrap())?
+            );
+        }
         Some(("inspect", args)) => {
             println!("{}", Cluster::try_from(args)?);
         }
@@ -122,25 +209,32 @@ pub fn run_v2() -> Result<(), Box<dyn Error>> {
             Inventory::try_from(args)?
                 .try_into_cluster()?
                 .print(args)
+                .collapse_host_ranges()
                 .write(args)?;
         }
         Some(("upgrade", args)) => {
+            let _lock = StateLock::acquire(&state::state_dir(args))?;
+
+            let profile = args.try_get_one::<String>("profile").ok().flatten();
+            let profile = profile.map(String::as_str);
             let mut old: Cluster = if args.get_flag("from-latest-state") {
                 State::from_latest(args)?.into()
             } else {
                 match args.get_one::<String>("old") {
                     Some(old_path) if old_path.ends_with(".gz") => {
-                        State::try_from(&PathBuf::from(old_path))?.into()
+                        State::read(&PathBuf::from(old_path), &SecretsPolicy::try_from(args)?)?
+                            .into()
                     }
-                    Some(old_path) => Cluster::try_from(&PathBuf::from(old_path))?,
-                    None => Cluster::try_from(&PathBuf::from("cluster.genin.yml"))?,
+                    Some(old_path) => Cluster::read(Path::new(old_path), profile)?,
+                    None => Cluster::read(Path::new(DEFAULT_CFG_NAME), profile)?,
                 }
             };
 
             old.hosts.clear_view();
+            old.reassign_ports(args);
 
             let mut new = if let Some(new) = args.get_one::<String>("new") {
-                Cluster::try_from(&PathBuf::from(new))?
+                Cluster::read(Path::new(new), profile)?
             } else {
                 return Err(ClusterError::Io(io::Error::new(
                     io::ErrorKind::NotFound,
@@ -158,50 +252,92 @@ pub fn run_v2() -> Result<(), Box<dyn Error>> {
                 .write(args)?;
         }
         Some(("list-state", args)) => {
-            let path = match args.get_one::<String>("state-dir") {
-                Some(dir) => PathBuf::from(dir),
-                None => PathBuf::from(".geninstate"),
-            };
-
-            let dirs = match path.read_dir() {
-                Ok(dirs) => dirs,
-                Err(err) if err.kind() == io::ErrorKind::NotFound => {
-                    panic!("directory containing genin state not found or empty");
-                }
-                Err(e) => {
-                    panic!("{}", e);
-                }
-            };
-
-            let mut entries = dirs
-                .into_iter()
-                .filter_map(
-                    |entry| match entry.as_ref().map(|entry| entry.file_type()) {
-                        Ok(Ok(file_type)) if file_type.is_file() => entry.ok(),
-                        _ => None,
-                    },
-                )
-                .collect::<Vec<std::fs::DirEntry>>();
-
-            entries.sort_by_key(|entry| entry.metadata().unwrap().modified().unwrap());
-            entries.reverse();
-
-            for (id, entry) in entries
+            let number = args
+                .get_one::<String>("number")
+                .map(|num| num.parse::<usize>().unwrap_or(10))
+                .unwrap_or(10);
+            let since = args.get_one::<SystemTime>("since");
+            let until = args.get_one::<SystemTime>("until");
+            let user = args.get_one::<String>("user");
+
+            backend(args)?
+                .history()?
                 .into_iter()
-                .take(
-                    args.get_one::<String>("number")
-                        .map(|num| num.parse::<usize>().unwrap_or(10) + 1)
-                        .unwrap_or(11),
-                )
-                .enumerate()
-            {
-                if id != 1 {
-                    let state = State::try_from(&entry.path())?;
+                .filter(|(created, _)| {
+                    since.map(|since| created >= since).unwrap_or(true)
+                        && until.map(|until| created <= until).unwrap_or(true)
+                })
+                .map(|(_, state)| state)
+                .filter(|state| {
+                    user.map(|user| &state.metadata.user == user)
+                        .unwrap_or(true)
+                })
+                .take(number)
+                .for_each(|state| {
                     state.print_kind();
                     state.print_changes();
+                });
+        }
+        Some(("state", args)) => match args.subcommand() {
+            Some(("diff", args)) => {
+                let backend = backend(args)?;
+                let profile = args.try_get_one::<String>("profile").ok().flatten();
+                let snapshot = |reference: &String| {
+                    diff::snapshot(backend.as_ref(), reference, profile.map(String::as_str))
+                };
+                let old = snapshot(args.get_one::<String>("old").unwrap())?;
+                let new = snapshot(args.get_one::<String>("new").unwrap())?;
+
+                StateDiff::between(&old, &new).print(args)?;
+            }
+            Some(("checkout", args)) => {
+                let state_dir = state::state_dir(args);
+                let _lock = StateLock::acquire(&state_dir)?;
+                let state = State::checkout(
+                    backend(args)?.as_ref(),
+                    args.get_one::<String>("uid").unwrap(),
+                )?;
+                println!("Latest state is {}", state.uid());
+
+                Cluster::from(state)
+                    .use_failure_domain_as_zone_for_instances(args)
+                    .print(args)
+                    .to_inventory()?
+                    .write(args)?;
+            }
+            Some(("log", args)) => {
+                backend(args)?
+                    .log(args.get_one::<String>("uid").unwrap())?
+                    .iter()
+                    .for_each(State::print_log_entry);
+            }
+            Some(("prune", args)) => {
+                let state_dir = state::state_dir(args);
+                let _lock = StateLock::acquire(&state_dir)?;
+                let dry_run = args.get_flag("dry-run");
+                let removed = backend(args)?.prune(&RetentionPolicy::try_from(args)?, dry_run)?;
+                removed.iter().for_each(|path| {
+                    if dry_run {
+                        println!("Would remove {}", path.display());
+                    } else {
+                        println!("Removed {}", path.display());
+                    }
+                });
+            }
+            Some(("verify", args)) => {
+                let report = backend(args)?.verify()?;
+                report.iter().for_each(|entry| println!("{entry}"));
+                let broken = report.iter().filter(|entry| !entry.is_ok()).count();
+                if broken > 0 {
+                    return Err(StateError::Broken(broken).into());
                 }
             }
-        }
+            _ => {
+                return Err(
+                    GeninError::new(GeninErrorKind::ArgsError, "state subcommand missing").into(),
+                );
+            }
+        },
         _ => {
             return Err(GeninError::new(GeninErrorKind::ArgsError, "subcommand missing").into());
         }
@@ -1,6 +1,8 @@
 use crate::{APP_AUTHOR, APP_NAME, APP_VERSION};
 use clap::{Arg, ArgAction, ArgMatches, Command};
 
+use crate::task::utils::{parse_time, parse_time_until};
+
 pub(super) fn read() -> ArgMatches {
     Command::new(APP_NAME)
         .version(APP_VERSION)
@@ -8,11 +10,49 @@ pub(super) fn read() -> ArgMatches {
         .about("Quick inventory generation for tarantool apps")
         .subcommand_required(true)
         .dont_collapse_args_in_usage(true)
-        .args(&[Arg::new("verbosity")
-            .short('v')
-            .action(ArgAction::Count)
-            .global(true)
-            .help("Set logging level based on -v (debug) or -vv (trace)")])
+        .args(&[
+            Arg::new("verbosity")
+                .short('v')
+                .action(ArgAction::Count)
+                .global(true)
+                .help("Set logging level based on -v (debug) or -vv (trace)"),
+            Arg::new("state-key-file")
+                .long("state-key-file")
+                .env("GENIN_STATE_KEY_FILE")
+                .action(ArgAction::Set)
+                .global(true)
+                .help(
+                    "File with the key used to encrypt secrets in state files, \
+                    32 random bytes raw or in hex. The key itself may be passed \
+                    with GENIN_STATE_KEY",
+                ),
+            Arg::new("state-key")
+                .long("state-key")
+                .env("GENIN_STATE_KEY")
+                .hide_env_values(true)
+                .action(ArgAction::Set)
+                .global(true)
+                .hide(true)
+                .help("Key used to encrypt secrets in state files"),
+            Arg::new("profile")
+                .long("profile")
+                .env("GENIN_PROFILE")
+                .action(ArgAction::Set)
+                .global(true)
+                .help(
+                    "Merge the overlay of the profile, like cluster.prod.genin.yml, \
+                    into the cluster config and keep the states in .geninstate-<profile>",
+                ),
+            Arg::new("strip-secrets")
+                .long("strip-secrets")
+                .env("GENIN_STRIP_SECRETS")
+                .action(ArgAction::SetTrue)
+                .global(true)
+                .help(
+                    "Do not save passwords and cookies to state files, \
+                    they are taken from the cluster config on the next build",
+                ),
+        ])
         .subcommands(vec![
             Command::new("build")
                 .about("Generate inventory based on cluster.genin.yaml configuration")
@@ -80,6 +120,7 @@ pub(super) fn read() -> ArgMatches {
                         .env("GENIN_STATE_DIR")
                         .action(ArgAction::Set)
                         .help("override .geninstate directory location"),
+                    state_backend_arg(),
                     Arg::new("recreate")
                         .long("recreate")
                         .action(ArgAction::SetTrue)
@@ -89,11 +130,33 @@ pub(super) fn read() -> ArgMatches {
                         .short('I')
                         .action(ArgAction::SetTrue)
                         .help("merge replicasets with similar names like router-1 and router-1-1"),
+                    reassign_ports_arg(),
                     Arg::new("yes")
                         .short('y')
                         .action(ArgAction::SetTrue)
                         .help("Auto-confirm all prompts"),
+                    Arg::new("check")
+                        .long("check")
+                        .action(ArgAction::SetTrue)
+                        .help(
+                            "Do not write anything, fail if the output inventory \
+                            differs from the one genin would generate",
+                        ),
+                    Arg::new("dry-run")
+                        .long("dry-run")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("check")
+                        .help(
+                            "Do not write anything, only print the changes \
+                            the build would make to the latest state",
+                        ),
                 ]),
+            Command::new("verify")
+                .about("Check that the inventory was not edited after generation")
+                .args(&[Arg::new("inventory")
+                    .action(ArgAction::Set)
+                    .default_value("inventory.yml")
+                    .help("path to the generated inventory")]),
             Command::new("init")
                 .about("Init genin and create cluster.genin.yml configuration")
                 .args(&[
@@ -119,6 +182,46 @@ pub(super) fn read() -> ArgMatches {
                         .action(ArgAction::SetTrue)
                         .help("do not print table and cluster yaml"),
                 ]),
+            Command::new("lint")
+                .about("Check cluster.genin.yml for semantic mistakes")
+                .args(&[
+                    Arg::new("source")
+                        .long("source")
+                        .short('s')
+                        .action(ArgAction::Set)
+                        .default_value("cluster.genin.yml")
+                        .help("Absolute or relative path of the cluster configuration"),
+                    Arg::new("rules")
+                        .long("rules")
+                        .action(ArgAction::SetTrue)
+                        .help("Print the rule catalogue and exit"),
+                ]),
+            Command::new("explain")
+                .about("Explain why an instance was placed on its host")
+                .args(&[
+                    Arg::new("instance")
+                        .action(ArgAction::Set)
+                        .required(true)
+                        .help("name of the instance, like storage-1-1"),
+                    Arg::new("source")
+                        .long("source")
+                        .short('s')
+                        .action(ArgAction::Set)
+                        .help("Absolute or relative path of the cluster configuration"),
+                    state_dir_arg(),
+                    state_backend_arg(),
+                    Arg::new("fresh")
+                        .long("fresh")
+                        .action(ArgAction::SetTrue)
+                        .help("Explain a fresh build ignoring the latest state"),
+                    Arg::new("idiomatic-merge")
+                        .long("idiomatic-merge")
+                        .short('I')
+                        .action(ArgAction::SetTrue)
+                        .help("merge replicasets with similar names like router-1 and router-1-1"),
+                    reassign_ports_arg(),
+                    fd_as_zone_arg(),
+                ]),
             Command::new("inspect")
                 .about(
                     "Read cluster.genin.yaml configuration or inventory.yaml \
@@ -270,6 +373,7 @@ pub(super) fn read() -> ArgMatches {
                         .env("GENIN_STATE_DIR")
                         .action(ArgAction::Set)
                         .help("override .geninstate directory location"),
+                    state_backend_arg(),
                     Arg::new("from-latest-state")
                         .long("from-latest-state")
                         .action(ArgAction::SetTrue)
@@ -279,6 +383,7 @@ pub(super) fn read() -> ArgMatches {
                         .short('I')
                         .action(ArgAction::SetTrue)
                         .help("merge replicasets with similar names like router-1 and router-1-1"),
+                    reassign_ports_arg(),
                     Arg::new("yes")
                         .short('y')
                         .action(ArgAction::SetTrue)
@@ -302,6 +407,131 @@ pub(super) fn read() -> ArgMatches {
                         .short('n')
                         .action(ArgAction::Set)
                         .help("number of last states to show"),
+                    Arg::new("user")
+                        .long("user")
+                        .action(ArgAction::Set)
+                        .help("show only states created by the user"),
+                    Arg::new("since")
+                        .long("since")
+                        .action(ArgAction::Set)
+                        .value_parser(parse_time)
+                        .help("show states created since date (2023-11-14, RFC 3339 or 7d ago)"),
+                    Arg::new("until")
+                        .long("until")
+                        .action(ArgAction::Set)
+                        .value_parser(parse_time_until)
+                        .help(
+                            "show states created until date inclusive \
+                            (2023-11-14, RFC 3339 or 7d ago)",
+                        ),
+                    state_backend_arg(),
+                ]),
+            Command::new("state")
+                .about("Inspect and manage genin states")
+                .subcommand_required(true)
+                .subcommands(vec![
+                    Command::new("diff")
+                        .about(
+                            "Show field by field difference between two states. \
+                            Each state is referenced by uid, uid prefix, `latest`, \
+                            `previous`, path to a state file or path to a cluster.genin.yml",
+                        )
+                        .args(&[
+                            Arg::new("old")
+                                .action(ArgAction::Set)
+                                .default_value("previous")
+                                .help("state to compare from"),
+                            Arg::new("new")
+                                .action(ArgAction::Set)
+                                .default_value("latest")
+                                .help("state to compare with"),
+                            state_dir_arg(),
+                            Arg::new("format")
+                                .long("format")
+                                .action(ArgAction::Set)
+                                .value_parser(["text", "json"])
+                                .default_value("text")
+                                .help("output format"),
+                            state_backend_arg(),
+                        ]),
+                    Command::new("checkout")
+                        .about(
+                            "Point latest at the state with given uid \
+                            and regenerate the inventory from it",
+                        )
+                        .args(&[
+                            Arg::new("uid")
+                                .action(ArgAction::Set)
+                                .required(true)
+                                .help("uid, unique uid prefix or `previous`"),
+                            state_dir_arg(),
+                            Arg::new("output")
+                                .long("output")
+                                .short('o')
+                                .action(ArgAction::Set)
+                                .help(
+                                    "The absolute or relative path where the \
+                                    ready-made cluster inventory will be saved.",
+                                ),
+                            Arg::new("force")
+                                .long("force")
+                                .short('f')
+                                .action(ArgAction::SetTrue)
+                                .help(
+                                    "Used to overwrite the output file, whether \
+                                    or not it exists.",
+                                ),
+                            Arg::new("quiet")
+                                .long("quiet")
+                                .short('q')
+                                .action(ArgAction::SetTrue)
+                                .help("do not print table and cluster yaml"),
+                            fd_as_zone_arg(),
+                            state_backend_arg(),
+                        ]),
+                    Command::new("log")
+                        .about("Print the chain of states with their parents")
+                        .args(&[
+                            Arg::new("uid")
+                                .action(ArgAction::Set)
+                                .default_value("latest")
+                                .help("state to start the chain from"),
+                            state_dir_arg(),
+                            state_backend_arg(),
+                        ]),
+                    Command::new("prune")
+                        .about(
+                            "Remove old states. A state is kept if any of the \
+                            retention options keeps it, latest and its parent \
+                            are never removed. Not supported by the git backend",
+                        )
+                        .args(&[
+                            Arg::new("keep-last")
+                                .long("keep-last")
+                                .action(ArgAction::Set)
+                                .value_parser(clap::value_parser!(usize))
+                                .help("keep N most recent states"),
+                            Arg::new("keep-newer-than")
+                                .long("keep-newer-than")
+                                .action(ArgAction::Set)
+                                .help("keep states newer than duration (e.g. 12h, 30d, 2w)"),
+                            Arg::new("keep-builds")
+                                .long("keep-builds")
+                                .action(ArgAction::SetTrue)
+                                .help("keep all states created by build"),
+                            Arg::new("dry-run")
+                                .long("dry-run")
+                                .action(ArgAction::SetTrue)
+                                .help("only print states that would be removed"),
+                            state_dir_arg(),
+                            state_backend_arg(),
+                        ]),
+                    Command::new("verify")
+                        .about(
+                            "Check that every state can be decompressed and \
+                            deserialized and that latest points at an existing state",
+                        )
+                        .args(&[state_dir_arg(), state_backend_arg()]),
                 ]),
         ])
         .get_matches()
@@ -316,3 +546,34 @@ fn fd_as_zone_arg() -> Arg {
         .value_parser(clap::value_parser!(u8))
         .help("Used to insert 'failure_domain' field's value of instances in their 'zone' field.")
 }
+
+fn reassign_ports_arg() -> Arg {
+    Arg::new("reassign-ports")
+        .long("reassign-ports")
+        .action(ArgAction::SetTrue)
+        .help(
+            "assign ports of all instances again from the base ports \
+            instead of keeping the ports saved in the state",
+        )
+}
+
+fn state_dir_arg() -> Arg {
+    Arg::new("state-dir")
+        .long("state-dir")
+        .env("GENIN_STATE_DIR")
+        .action(ArgAction::Set)
+        .help("override .geninstate directory location")
+}
+
+fn state_backend_arg() -> Arg {
+    Arg::new("state-backend")
+        .long("state-backend")
+        .env("GENIN_STATE_BACKEND")
+        .action(ArgAction::Set)
+        .value_parser(["gzip", "git"])
+        .default_value("gzip")
+        .help(
+            "where to keep the state history: gzip files named by uid \
+            or commits of state.json in a git repository",
+        )
+}
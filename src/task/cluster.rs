@@ -1,7 +1,10 @@
 pub mod fs;
 pub mod host;
+pub mod include;
 pub mod instance;
+pub mod lint;
 pub mod name;
+pub mod profile;
 pub mod topology;
 
 use clap::ArgMatches;
@@ -13,17 +16,24 @@ use serde::{Deserialize, Serialize};
 use serde_yaml::Value;
 use std::collections::HashMap;
 use std::fmt::{Debug, Display};
-use std::fs::File;
 use std::io::{self, Write};
 use std::path::{Path, PathBuf};
 use thiserror::Error;
 
+use crate::error::GeninError;
 use crate::task::cluster::host::hst::{Host, HostConfig, WithHosts};
+use crate::task::cluster::host::import::resolve_imports;
+use crate::task::cluster::host::placement::{Explanation, Placement};
+use crate::task::cluster::host::ports::{PortBase, PortConflict};
+use crate::task::cluster::host::range::{collapse, expand_address, Range};
+use crate::task::cluster::host::strategy::Strategy;
 use crate::task::cluster::host::view::View;
 use crate::task::cluster::instance::ins::{Instance, InstanceConfig, Instances};
+use crate::task::cluster::instance::overrides::InstanceOverrides;
 use crate::task::cluster::instance::Role;
 use crate::task::cluster::name::Name;
-use crate::task::cluster::topology::{InvalidTopologySet, Topology};
+use crate::task::cluster::profile::read_with_profile;
+use crate::task::cluster::topology::{resolve_extends, InvalidTopologySet, Topology};
 use crate::task::flv::Failover;
 use crate::task::inventory::{Child, HostVars, Inventory};
 use crate::task::vars::Vars;
@@ -35,6 +45,7 @@ use self::host::hst::InvalidHost;
 
 use crate::task::flv::{FailoverError, InvalidFailover};
 use crate::task::inventory::InventoryError;
+use crate::task::state::backend::backend;
 use crate::task::state::{State, StateError};
 use crate::task::utils::create_file_or_copy;
 use crate::task::vars::InvalidVars;
@@ -114,6 +125,8 @@ pub struct Cluster {
     pub hosts: Host,
     pub failover: Failover,
     pub vars: Vars,
+    /// Settings of single instances from the `instances` section
+    pub overrides: InstanceOverrides,
     pub metadata: ClusterMetadata,
 }
 
@@ -122,6 +135,7 @@ impl Default for Cluster {
         let mut cluster = Cluster {
             metadata: ClusterMetadata {
                 paths: vec![DEFAULT_CFG_NAME.into()],
+                ..ClusterMetadata::default()
             },
             ..serde_yaml::from_reader(DEFAULT_CFG).expect("cluster is yaml format")
         };
@@ -130,9 +144,11 @@ impl Default for Cluster {
     }
 }
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Debug, Default)]
 pub struct ClusterMetadata {
     pub paths: Vec<PathBuf>,
+    /// Uid of the state this cluster was restored from
+    pub parent: Option<String>,
 }
 
 impl Display for Cluster {
@@ -150,13 +166,8 @@ impl<'a> TryFrom<&'a ArgMatches> for Cluster {
                 let source = String::from(DEFAULT_CFG_NAME);
                 let path = path.unwrap_or(&source);
 
-                let file = File::open(path)?;
-                let mut cluster = Cluster {
-                    metadata: ClusterMetadata {
-                        paths: vec![path.into()],
-                    },
-                    ..serde_yaml::from_reader(file)?
-                };
+                let profile = args.try_get_one::<String>("profile").ok().flatten();
+                let mut cluster = Cluster::read(Path::new(path), profile.map(String::as_str))?;
 
                 cluster.vars = cluster.vars.with_failover(cluster.failover.clone());
                 Ok(cluster)
@@ -177,20 +188,6 @@ impl<'a> TryFrom<&'a ArgMatches> for Cluster {
     }
 }
 
-impl<'a> TryFrom<&'a PathBuf> for Cluster {
-    type Error = ClusterError;
-
-    fn try_from(path: &'a PathBuf) -> Result<Self, Self::Error> {
-        let file = File::open(path)?;
-        Ok(Cluster {
-            metadata: ClusterMetadata {
-                paths: vec![path.into()],
-            },
-            ..serde_yaml::from_reader(file)?
-        })
-    }
-}
-
 impl<'a> TryFrom<&'a Inventory> for Cluster {
     type Error = ClusterError;
 
@@ -291,6 +288,8 @@ impl<'a> TryFrom<&'a Inventory> for Cluster {
                                                 ),
                                                 vars: instance.vars.clone(),
                                                 view: View::default(),
+                                                spread_strategy: Strategy::default(),
+                                                placement: Placement::default(),
                                             })
                                         } else {
                                             None
@@ -314,15 +313,15 @@ impl<'a> TryFrom<&'a Inventory> for Cluster {
                     )
                 })?,
             vars: inventory.all.vars.clone(),
-            metadata: ClusterMetadata {
-                paths: Default::default(),
-            },
+            overrides: InstanceOverrides::default(),
+            metadata: ClusterMetadata::default(),
         })
     }
 }
 
 impl From<State> for Cluster {
     fn from(state: State) -> Self {
+        let parent = Some(state.uid().to_string());
         let mut hosts = state.hosts;
         hosts.add_queue = hosts
             .collect_instances()
@@ -335,8 +334,10 @@ impl From<State> for Cluster {
             vars: state.vars.with_failover(state.failover.clone()),
             failover: state.failover,
             topology: state.topology,
+            overrides: InstanceOverrides::default(),
             metadata: ClusterMetadata {
                 paths: vec![PathBuf::from(state.path)],
+                parent,
             },
         }
     }
@@ -352,42 +353,66 @@ impl<'de> Deserialize<'de> for Cluster {
         enum ClusterHelper {
             Cluster {
                 topology: Topology,
-                hosts: Vec<Host>,
+                hosts: Vec<HostHelper>,
                 #[serde(default)]
                 failover: Failover,
                 vars: Box<Vars>,
+                #[serde(default)]
+                role_ports: Box<IndexMap<String, PortBase>>,
+                #[serde(default)]
+                spread_strategy: Option<Strategy>,
+                #[serde(default)]
+                instances: Box<InstanceOverrides>,
             },
             InvalidCluster(Value),
         }
 
-        ClusterHelper::deserialize(deserializer).and_then(|cluster| match cluster {
-            ClusterHelper::Cluster {
-                topology,
-                hosts,
-                failover,
-                vars,
-            } => Ok(Cluster {
-                hosts: Host::from("cluster")
-                    .with_hosts(hosts)
-                    .with_http_port(DEFAULT_HTTP_PORT)
-                    .with_binary_port(DEFAULT_BINARY_PORT),
-                topology: topology.check_unique().map_err(serde::de::Error::custom)?,
-                failover,
-                vars: *vars,
-                metadata: ClusterMetadata {
-                    paths: Default::default(),
-                },
-            }
-            .spread()),
-            ClusterHelper::InvalidCluster(value) => {
-                println!(
-                    "Cluster configuration contains errors: {:?}",
-                    serde_yaml::from_value::<InvalidCluster>(value)
-                        .expect("can't fail because it was already parsed into the similiar type")
-                );
-                Err(serde::de::Error::custom("Invalid cluster configuration"))
-            }
-        })
+        let config =
+            resolve_config(Value::deserialize(deserializer)?).map_err(serde::de::Error::custom)?;
+
+        ClusterHelper::deserialize(config)
+            .map_err(serde::de::Error::custom)
+            .and_then(|cluster| match cluster {
+                ClusterHelper::Cluster {
+                    topology,
+                    hosts,
+                    failover,
+                    vars,
+                    role_ports,
+                    spread_strategy,
+                    instances,
+                } => {
+                    let hosts = Host::try_from(HostHelper::root(hosts))
+                        .map_err(serde::de::Error::custom)?
+                        .with_http_port(DEFAULT_HTTP_PORT)
+                        .with_binary_port(DEFAULT_BINARY_PORT);
+                    Ok(Cluster {
+                        topology: topology
+                            .check_unique()
+                            .and_then(|topology| topology.with_placement(&hosts))
+                            .map_err(serde::de::Error::custom)?
+                            .with_role_ports(&role_ports)
+                            .with_spread_strategy(spread_strategy),
+                        hosts,
+                        failover,
+                        vars: *vars,
+                        overrides: *instances,
+                        metadata: ClusterMetadata::default(),
+                    }
+                    .spread()
+                    .and_then(Cluster::check_ports)
+                    .map_err(serde::de::Error::custom)?)
+                }
+                ClusterHelper::InvalidCluster(value) => {
+                    println!(
+                        "Cluster configuration contains errors: {:?}",
+                        serde_yaml::from_value::<InvalidCluster>(value).expect(
+                            "can't fail because it was already parsed into the similiar type"
+                        )
+                    );
+                    Err(serde::de::Error::custom("Invalid cluster configuration"))
+                }
+            })
     }
 }
 
@@ -396,8 +421,11 @@ impl Serialize for Cluster {
     where
         S: serde::Serializer,
     {
-        let mut state = serializer.serialize_struct("Cluster", 4)?;
+        let mut state = serializer.serialize_struct("Cluster", 5)?;
         state.serialize_field("topology", &self.topology)?;
+        if !self.overrides.is_empty() {
+            state.serialize_field("instances", &self.overrides)?;
+        }
         state.serialize_field("hosts", &self.hosts.hosts)?;
         state.serialize_field("failover", &self.failover)?;
 
@@ -414,10 +442,17 @@ impl Validate for Cluster {
     type Error = serde_yaml::Error;
 
     fn validate(bytes: &[u8]) -> Result<Self::Type, Self::Error> {
-        serde_yaml::from_str(&check_placeholders(bytes)?)
+        let config = serde_yaml::from_str(&check_placeholders(bytes)?)?;
+        serde_yaml::from_value(resolve_config(config).map_err(serde::de::Error::custom)?)
     }
 }
 
+/// Resolves what the config refers to before anything else looks at it, the
+/// templates of the topology and the hosts imported from files.
+pub fn resolve_config(config: Value) -> Result<Value, String> {
+    resolve_extends(config).and_then(resolve_imports)
+}
+
 pub fn check_placeholders(slice: &[u8]) -> Result<String, serde_yaml::Error> {
     let text = String::from_utf8_lossy(slice).to_string();
     let reg = RegexBuilder::new(r"(?P<key>^.+:) +(<<.*>>) *(?:# *([^#:]+)$)*")
@@ -458,10 +493,25 @@ pub fn check_placeholders(slice: &[u8]) -> Result<String, serde_yaml::Error> {
 }
 
 impl Cluster {
-    pub fn spread(self) -> Self {
+    /// Reads the cluster config with the overlay of the profile, every
+    /// cluster config genin reads goes through here so that none of them
+    /// misses the profile.
+    pub fn read(path: &Path, profile: Option<&str>) -> Result<Self, ClusterError> {
+        let (config, paths) = read_with_profile(path, profile)?;
+        Ok(Cluster {
+            metadata: ClusterMetadata {
+                paths,
+                ..ClusterMetadata::default()
+            },
+            ..serde_yaml::from_value(config)?
+        })
+    }
+
+    pub fn spread(self) -> Result<Self, ClusterError> {
         let instances = Instances::from(&self.topology);
         let mut hosts = self.hosts.with_instances(instances);
         hosts.with_stateboard(&self.failover);
+        self.overrides.apply(&mut hosts)?;
         let mut hosts = hosts
             .clone()
             .with_add_queue(
@@ -479,8 +529,8 @@ impl Cluster {
                     .collect(),
             );
 
-        hosts.spread();
-        Self { hosts, ..self }
+        hosts.spread(&self.topology.affinity()?)?;
+        Ok(Self { hosts, ..self })
     }
 
     pub fn merge(
@@ -488,14 +538,36 @@ impl Cluster {
         new: &mut Cluster,
         idiomatic: bool,
     ) -> Result<Vec<Change>, ClusterError> {
+        // the stateboard is added again from the failover of the new config,
+        // but keeps its ports as long as it stays on the same host
+        let stateboard = self
+            .hosts
+            .find_instance("stateboard")
+            .filter(|(_, instance)| instance.is_stateboard())
+            .and_then(|(path, instance)| Some((path.last()?.clone(), instance.config.clone())));
         self.hosts.delete_stateboard();
 
         std::mem::swap(&mut self.failover, &mut new.failover);
         std::mem::swap(&mut self.vars, &mut new.vars);
         std::mem::swap(&mut self.topology, &mut new.topology);
+        std::mem::swap(&mut self.overrides, &mut new.overrides);
+
+        // instances pinned to another host than the one they kept in the old
+        // cluster are spread again, as configured in the new cluster
+        let misplaced = self.overrides.misplaced(&self.hosts);
+        let moved = new
+            .hosts
+            .add_queue
+            .iter()
+            .filter(|(name, _)| misplaced.contains(&name.to_string()))
+            .map(|(name, instance)| (name.clone(), instance.clone()))
+            .collect::<IndexMap<Name, Instance>>();
 
         let hosts_diff = Host::merge(&mut self.hosts, &mut new.hosts, idiomatic);
 
+        self.hosts.remove_instances(&moved);
+        self.hosts.add_queue.extend(moved);
+
         debug!(
             "Instances to Add: {}",
             self.hosts
@@ -517,14 +589,74 @@ impl Cluster {
 
         self.hosts.add_diff();
         self.hosts.with_stateboard(&self.failover);
-        self.hosts.spread();
+        if let Some((host, config)) = stateboard {
+            self.hosts
+                .instances
+                .iter_mut()
+                .filter(|instance| instance.is_stateboard())
+                .filter(|instance| {
+                    Vec::from(instance.failure_domains.clone()) == vec![host.clone()]
+                })
+                .for_each(|instance| {
+                    instance.config.http_port = config.http_port;
+                    instance.config.binary_port = config.binary_port;
+                });
+        }
+        self.hosts.spread(&self.topology.affinity()?)?;
 
         self.hosts.remove_diff();
         self.metadata.paths.extend_from_slice(&new.metadata.paths);
 
+        report_port_conflicts(self.hosts.port_conflicts())?;
+
         Ok(hosts_diff)
     }
 
+    /// Fails if the generated ports of any leaf host collide, hit a
+    /// reserved port or leave the port ranges of the host.
+    pub fn check_ports(self) -> Result<Self, ClusterError> {
+        report_port_conflicts(self.hosts.port_conflicts())?;
+        Ok(self)
+    }
+
+    /// Cluster of the state with the cluster config of the arguments merged
+    /// into it, along with the hosts changes. This is what `build` writes
+    /// when there is a previous state, so the dry run, the check and the
+    /// explanation use it too.
+    pub fn merge_into_state(
+        state: State,
+        args: &ArgMatches,
+    ) -> Result<(Self, Vec<Change>), ClusterError> {
+        let mut old: Cluster = state.into();
+        old.hosts.clear_view();
+        old.reassign_ports(args);
+
+        let mut new = Cluster::try_from(args)?.use_failure_domain_as_zone_for_instances(args);
+        let hosts_diff = old.merge(&mut new, args.get_flag("idiomatic-merge"))?;
+
+        Ok((old, hosts_diff))
+    }
+
+    /// Instances keep the ports saved in the state across merges, with
+    /// `--reassign-ports` they are assigned again from the base ports.
+    pub fn reassign_ports(&mut self, args: &ArgMatches) {
+        if let Ok(Some(true)) = args.try_get_one::<bool>("reassign-ports") {
+            self.hosts.clean_ports();
+        }
+    }
+
+    /// Explains the placement of the instance by the last spreading.
+    pub fn explain(&self, name: &str) -> Result<Explanation, ClusterError> {
+        self.hosts
+            .find_instance(name)
+            .map(|(path, instance)| Explanation {
+                instance: instance.name.clone(),
+                path,
+                placement: instance.placement.clone(),
+            })
+            .ok_or_else(|| ClusterError::Other(format!("instance {name} not found")))
+    }
+
     /// It will traverse the cluster, replacing every instance's zone with its `failure_domain`.
     ///
     /// Note that method is intended to be called after cluster is spread
@@ -695,25 +827,26 @@ impl Cluster {
     }
 
     pub fn write_build_state(self, args: &ArgMatches) -> Result<Self, ClusterError> {
-        let state_dir = args
-            .get_one::<String>("state-dir")
-            .cloned()
-            .unwrap_or(".geninstate".into());
-        let latest_path = Path::new(&state_dir).join("latest.gz");
-        let latest_path = latest_path.to_str().unwrap();
+        let backend = backend(args)?;
 
         let mut state = State::builder()
             .uid(self.metadata.paths.clone())?
+            .sources(&self.metadata.paths)
+            .inventory(
+                self.to_inventory()
+                    .and_then(|inventory| inventory.content_hash())
+                    .map_err(|err| err.to_string())?,
+            )
             .make_build_state()
-            .path(latest_path)
+            .parent(self.metadata.parent.clone())
+            .path(&backend.latest_path())
             .hosts(&self.hosts)
             .vars(&self.vars)
             .failover(&self.failover)
             .topology(&self.topology)
             .build()?;
 
-        state.dump_by_uid(&state_dir)?;
-        state.symlink_latest(latest_path)?;
+        backend.save(&mut state)?;
 
         Ok(self)
     }
@@ -723,41 +856,30 @@ impl Cluster {
         args: &ArgMatches,
         hosts_diff: Vec<Change>,
     ) -> Result<Self, ClusterError> {
-        let instances_diff: Vec<Change> = self
-            .hosts
-            .add_queue
-            .iter()
-            .map(|(name, _)| Change::Added(name.to_string()))
-            .chain(
-                self.hosts
-                    .delete_queue
-                    .iter()
-                    .map(|(name, _)| Change::Removed(name.to_string())),
-            )
-            .collect();
+        let instances_diff = self.instances_changes();
 
         if hosts_diff.is_empty() && instances_diff.is_empty() {
             return Ok(self);
         }
 
-        // if args != export-state -> try open latest
-        // if .geninstate not exists -> create dir
-        // if latest not exists -> create latest
-        // if write state
-        let state_dir = args
-            .get_one::<String>("state-dir")
-            .cloned()
-            .unwrap_or(".geninstate".into());
-
+        // exported state is written to the given path and does not move latest
+        let backend = backend(args)?;
         let path: String = if let Ok(Some(path)) = args.try_get_one::<String>("export-state") {
             path.into()
         } else {
-            format!("{state_dir}/latest.gz")
+            backend.latest_path()
         };
 
         let mut state = State::builder()
             .uid(self.metadata.paths.clone())?
+            .sources(&self.metadata.paths)
+            .inventory(
+                self.to_inventory()
+                    .and_then(|inventory| inventory.content_hash())
+                    .map_err(|err| err.to_string())?,
+            )
             .make_upgrade_state()
+            .parent(self.metadata.parent.clone())
             .path(&path)
             .instances_changes(instances_diff)
             .hosts_changes(hosts_diff)
@@ -767,12 +889,26 @@ impl Cluster {
             .topology(&self.topology)
             .build()?;
 
-        state.dump_by_uid(&state_dir)?;
-        state.dump_by_path(&path)?;
+        backend.save(&mut state)?;
 
         Ok(self)
     }
 
+    /// Instances added and removed by the last merge.
+    pub fn instances_changes(&self) -> Vec<Change> {
+        self.hosts
+            .add_queue
+            .iter()
+            .map(|(name, _)| Change::Added(name.to_string()))
+            .chain(
+                self.hosts
+                    .delete_queue
+                    .iter()
+                    .map(|(name, _)| Change::Removed(name.to_string())),
+            )
+            .collect()
+    }
+
     pub fn to_inventory(&self) -> Result<Inventory, InventoryError> {
         Inventory::try_from(self)
     }
@@ -781,6 +917,25 @@ impl Cluster {
         self.hosts.clear_instances();
         self
     }
+
+    /// Writes consecutive hosts as ranges, like `srv-[01:60]`. The collapsed
+    /// hosts lose their instances, so this is the last step before writing.
+    pub fn collapse_host_ranges(mut self) -> Self {
+        self.hosts.hosts = collapse(&self.hosts.hosts);
+        self
+    }
+}
+
+fn report_port_conflicts(conflicts: Vec<PortConflict>) -> Result<(), ClusterError> {
+    if conflicts.is_empty() {
+        return Ok(());
+    }
+
+    println!("Port conflicts:");
+    conflicts
+        .iter()
+        .for_each(|conflict| println!("  {conflict}"));
+    Err(ClusterError::PortConflicts(conflicts.len()))
 }
 
 #[derive(Error, Debug)]
@@ -793,6 +948,12 @@ pub enum ClusterError {
     Failover(#[from] FailoverError),
     #[error("state error {0}")]
     State(#[from] StateError),
+    #[error("{0} port conflicts")]
+    PortConflicts(usize),
+    #[error("lint found {0} errors")]
+    Lint(usize),
+    #[error("{0}")]
+    Spreading(#[from] GeninError),
     #[error("other error {0}")]
     Other(String),
 }
@@ -803,50 +964,116 @@ impl From<String> for ClusterError {
     }
 }
 
-#[derive(Deserialize)]
+/// Host as written in the cluster config. A name with a range or a `count`
+/// stands for several hosts, which are generated when the hosts tree is built.
+/// ```yaml
+/// hosts:
+///   - name: srv-[01:60]
+///     config:
+///       address: 10.1.0.[11:70]
+///   - name: cache-{}
+///     count: 3
+/// ```
+#[derive(Deserialize, Clone)]
 struct HostHelper {
     name: String,
     #[serde(default)]
+    count: Option<usize>,
+    #[serde(default)]
     config: HostConfig,
     #[serde(default)]
     hosts: Vec<HostHelper>,
 }
 
-impl From<HostHelper> for Host {
-    fn from(helper: HostHelper) -> Self {
+impl TryFrom<HostHelper> for Host {
+    type Error = String;
+
+    fn try_from(helper: HostHelper) -> Result<Self, Self::Error> {
         let name = Name::from(helper.name.as_str());
         helper.into_host_v2(name)
     }
 }
 
 impl HostHelper {
-    fn into_host_v2(self, name: Name) -> Host {
+    fn root(hosts: Vec<HostHelper>) -> Self {
+        Self {
+            name: String::from("cluster"),
+            count: None,
+            config: HostConfig::default(),
+            hosts,
+        }
+    }
+
+    /// Hosts generated from the name range or the count, `{}` in the name is
+    /// replaced with the number of the host, otherwise the number is appended.
+    fn expand(self) -> Result<Vec<HostHelper>, String> {
+        let names = match (Range::parse(&self.name)?, self.count) {
+            (Some(_), Some(_)) => {
+                return Err(format!("{} has both a name range and a count", self.name))
+            }
+            (Some(range), None) => range.iter().collect(),
+            (None, Some(0)) => return Err(format!("count of {} must be positive", self.name)),
+            (None, Some(count)) => (1..=count)
+                .map(|index| {
+                    if self.name.contains("{}") {
+                        self.name.replace("{}", &index.to_string())
+                    } else {
+                        format!("{}-{index}", self.name)
+                    }
+                })
+                .collect(),
+            (None, None) => vec![self.name.clone()],
+        };
+        let addresses = expand_address(&self.config.address, &self.name, names.len())?;
+        let ansible_hosts = expand_address(&self.config.ansible_host, &self.name, names.len())?;
+
+        Ok(names
+            .into_iter()
+            .zip(addresses.into_iter().zip(ansible_hosts))
+            .map(|(name, (address, ansible_host))| HostHelper {
+                name,
+                count: None,
+                config: HostConfig {
+                    address,
+                    ansible_host,
+                    ..self.config.clone()
+                },
+                hosts: self.hosts.clone(),
+            })
+            .collect())
+    }
+
+    fn into_host_v2(self, name: Name) -> Result<Host, String> {
         if self.hosts.is_empty() {
-            return Host {
+            return Ok(Host {
                 name,
                 config: self.config,
                 hosts: Vec::default(),
                 add_queue: IndexMap::default(),
                 delete_queue: IndexMap::default(),
                 instances: Instances::default(),
-            };
+            });
         }
 
-        Host {
+        Ok(Host {
             hosts: self
                 .hosts
                 .into_iter()
+                .map(HostHelper::expand)
+                .collect::<Result<Vec<_>, _>>()?
+                .into_iter()
+                .flatten()
                 .map(|host| {
                     let children_name = name.clone_with_raw_index(host.name.clone());
                     host.into_host_v2(children_name)
                 })
-                .collect(),
+                .collect::<Result<_, _>>()?,
             name,
             config: self.config,
             add_queue: IndexMap::default(),
             delete_queue: IndexMap::default(),
             instances: Instances::default(),
-        }
+        })
     }
 }
 
@@ -0,0 +1,166 @@
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+
+use crate::task::cluster::host::hst::Host;
+use crate::task::cluster::instance::ins::Instance;
+
+/// Reference to another topology member, either by its name alone or with
+/// the level of the hosts tree the rule applies to. Level 1 is the top level
+/// hosts, without a level the rule applies to the leaf hosts.
+/// ```yaml
+/// - name: cache
+///   colocate_with: [api]
+/// - name: storage
+///   avoid:
+///     - name: router
+///       level: 1
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AffinityRule {
+    pub name: String,
+    pub level: Option<usize>,
+}
+
+impl<'de> Deserialize<'de> for AffinityRule {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum AffinityRuleHelper {
+            Name(String),
+            Rule {
+                name: String,
+                #[serde(default)]
+                level: Option<usize>,
+            },
+        }
+
+        match AffinityRuleHelper::deserialize(deserializer)? {
+            AffinityRuleHelper::Name(name) => Ok(Self { name, level: None }),
+            AffinityRuleHelper::Rule { name, level } => Ok(Self { name, level }),
+        }
+    }
+}
+
+impl Serialize for AffinityRule {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        #[derive(Serialize)]
+        struct Rule<'a> {
+            name: &'a str,
+            level: usize,
+        }
+
+        match self.level {
+            None => serializer.serialize_str(&self.name),
+            Some(level) => Rule {
+                name: &self.name,
+                level,
+            }
+            .serialize(serializer),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AffinityKind {
+    Colocate,
+    Avoid,
+}
+
+/// Affinity rules of the topology members by member name.
+#[derive(Clone, Debug, Default)]
+pub struct Affinity(IndexMap<String, Vec<(AffinityKind, AffinityRule)>>);
+
+impl Affinity {
+    pub fn push(&mut self, member: &str, kind: AffinityKind, rule: AffinityRule) {
+        self.0
+            .entry(member.to_string())
+            .or_default()
+            .push((kind, rule))
+    }
+
+    /// Rules of the member, together with the `avoid` rules of the other
+    /// members towards it, as avoiding works both ways.
+    fn rules<'a>(
+        &'a self,
+        member: &'a str,
+    ) -> impl Iterator<Item = (AffinityKind, AffinityRule)> + 'a {
+        let own = self.0.get(member).into_iter().flatten().cloned();
+        let avoided_by = self
+            .0
+            .iter()
+            .filter(move |(other, _)| other.as_str() != member)
+            .flat_map(move |(other, rules)| {
+                rules
+                    .iter()
+                    .filter(move |(kind, rule)| *kind == AffinityKind::Avoid && rule.name == member)
+                    .map(move |(kind, rule)| {
+                        (
+                            *kind,
+                            AffinityRule {
+                                name: other.clone(),
+                                level: rule.level,
+                            },
+                        )
+                    })
+            });
+        own.chain(avoided_by)
+    }
+
+    /// Members are spread after the members they are colocated with, so that
+    /// there is something to colocate with.
+    pub fn rank(&self, member: &str) -> usize {
+        self.rank_with_limit(member, self.0.len())
+    }
+
+    fn rank_with_limit(&self, member: &str, limit: usize) -> usize {
+        if limit == 0 {
+            return 0;
+        }
+        self.0
+            .get(member)
+            .into_iter()
+            .flatten()
+            .filter(|(kind, _)| *kind == AffinityKind::Colocate)
+            .map(|(_, rule)| self.rank_with_limit(&rule.name, limit - 1) + 1)
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// Reason why the instance may not go to the child host, which is at the
+    /// given depth of the hosts tree. `contains` tells whether the child or
+    /// its nested hosts already have an instance of a member.
+    pub fn violation(
+        &self,
+        instance: &Instance,
+        child: &Host,
+        depth: usize,
+        contains: impl Fn(&str) -> bool,
+    ) -> Option<String> {
+        self.rules(instance.name.get_ancestor_str())
+            .find_map(|(kind, rule)| {
+                let (judged, above) = match rule.level {
+                    Some(level) => (depth == level, depth < level),
+                    None => (child.hosts.is_empty(), !child.hosts.is_empty()),
+                };
+                let contains = contains(&rule.name);
+                match kind {
+                    AffinityKind::Colocate if (judged || above) && !contains => {
+                        Some(format!("no {} to colocate with", rule.name))
+                    }
+                    AffinityKind::Avoid if judged && contains => {
+                        Some(format!("avoids {}", rule.name))
+                    }
+                    _ => None,
+                }
+            })
+    }
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,113 @@
+use std::collections::HashSet;
+
+use crate::task::cluster::Cluster;
+
+const CLUSTER: &str = r#"---
+topology:
+  - name: cache
+    replicasets_count: 2
+    colocate_with: [api]
+    roles:
+      - cache
+  - name: router
+    replicasets_count: 2
+    roles:
+      - router
+  - name: storage
+    replicasets_count: 2
+    replication_factor: 2
+    avoid: [router]
+    roles:
+      - storage
+  - name: api
+    replicasets_count: 1
+    failure_domains: [server-4]
+    roles:
+      - api
+hosts:
+  - name: dc-1
+    hosts:
+      - name: server-1
+        config:
+          address: 192.168.16.11
+      - name: server-2
+        config:
+          address: 192.168.16.12
+  - name: dc-2
+    hosts:
+      - name: server-3
+        config:
+          address: 192.168.16.13
+      - name: server-4
+        config:
+          address: 192.168.16.14
+      - name: server-5
+        config:
+          address: 192.168.16.15
+failover:
+  mode: disabled
+vars:
+  ansible_user: ansible
+  ansible_password: ansible
+  cartridge_app_name: myapp
+  cartridge_cluster_cookie: myapp-cookie
+"#;
+
+fn hosts_of(cluster: &Cluster, member: &str) -> HashSet<String> {
+    cluster
+        .hosts
+        .lower_level_hosts()
+        .into_iter()
+        .filter(|host| host.contains_member(member))
+        .map(|host| host.name.to_string())
+        .collect()
+}
+
+#[test]
+fn colocate_and_avoid() {
+    let cluster: Cluster = serde_yaml::from_str(CLUSTER).unwrap();
+
+    assert!(hosts_of(&cluster, "router").is_disjoint(&hosts_of(&cluster, "storage")));
+    assert_eq!(
+        hosts_of(&cluster, "cache"),
+        HashSet::from(["server-4".to_string()])
+    );
+    assert!(cluster
+        .explain("storage-1-1")
+        .unwrap()
+        .to_string()
+        .contains(", avoids router"));
+}
+
+#[test]
+fn avoid_on_top_level() {
+    let config = CLUSTER.replace("avoid: [router]", "avoid: [{name: router, level: 1}]");
+    let err = serde_yaml::from_str::<Cluster>(&config).unwrap_err();
+    assert!(err
+        .to_string()
+        .starts_with("SpreadingError placement rules of storage-"));
+
+    let config = config.replace(
+        "  - name: router\n    replicasets_count: 2\n",
+        "  - name: router\n    replicasets_count: 2\n    failure_domains: [dc-1]\n",
+    );
+    let cluster: Cluster = serde_yaml::from_str(&config).unwrap();
+    assert_eq!(
+        hosts_of(&cluster, "storage"),
+        HashSet::from([
+            "server-3".to_string(),
+            "server-4".to_string(),
+            "server-5".to_string()
+        ])
+    );
+}
+
+#[test]
+fn unknown_affinity_member() {
+    let config = CLUSTER.replace("colocate_with: [api]", "colocate_with: [apy]");
+    let err = serde_yaml::from_str::<Cluster>(&config).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "other error cache references unknown topology member apy"
+    );
+}
@@ -2,14 +2,21 @@ use indexmap::IndexMap;
 use log::debug;
 use serde::{Deserialize, Serialize};
 use serde_yaml::{Number, Value};
+use std::collections::HashSet;
 use std::{borrow::Cow, cell::RefCell, cmp::Ordering, fmt::Display, net::IpAddr};
 use std::{fmt, mem};
 use tabled::papergrid::AnsiColor;
 use tabled::{builder::Builder, merge::Merge, Alignment, Tabled};
 
+use crate::task::cluster::host::affinity::Affinity;
+use crate::task::cluster::host::placement::{Decision, Placement};
+use crate::task::cluster::host::ports::{self, HostPorts, PortConflict, PortRange};
+use crate::task::cluster::host::selector::Selector;
+use crate::task::cluster::host::strategy::{Loads, Strategy};
 use crate::task::cluster::host::view::BG_BLACK;
 use crate::task::cluster::host::{merge_index_maps, view::View, IP};
 use crate::task::cluster::instance::ins::{FailureDomains, Instances};
+use crate::task::cluster::instance::Role;
 use crate::task::flv::{Failover, FailoverVariants};
 use crate::task::state::Change;
 use crate::task::{AsError, ErrConfMapping, TypeError, DICT, LIST, NUMBER, STRING};
@@ -142,11 +149,15 @@ impl Host {
         Self { config, ..self }
     }
 
-    pub fn spread(&mut self) {
-        self.inner_spread();
+    /// Spreads the instances over the hosts tree, honouring the failure
+    /// domains of the instances and the affinity rules between members.
+    pub fn spread(&mut self, affinity: &Affinity) -> Result<(), GeninError> {
+        self.inner_spread(affinity, 0)
     }
 
-    pub fn inner_spread(&mut self) {
+    pub fn inner_spread(&mut self, affinity: &Affinity, depth: usize) -> Result<(), GeninError> {
+        self.instances
+            .sort_by_key(|instance| affinity.rank(instance.name.get_ancestor_str()));
         self.instances.reverse();
 
         debug!(
@@ -159,32 +170,33 @@ impl Host {
                 .join(" ")
         );
 
-        //TODO: error propagation
         let mut instances = mem::take(&mut self.instances);
 
+        // Hosts stay sorted by name, and the loads give the least filled host
+        // first, so that we push to it without sorting hosts for every instance.
+        self.hosts.sort_by(|left, right| left.name.cmp(&right.name));
+        let mut loads = Loads::new(&self.hosts, depth);
+
         while let Some(instance) = instances.pop() {
-            // We sort here, as default hosts sort order is from least instances filled to most filled.
-            // And of course we'd like to push to least filled firstly.
-            self.hosts.sort();
             if instance.failure_domains.in_progress() {
                 debug!(
                     "start pushing instance {} with failure domain",
                     instance.name
                 );
-                self.push_to_failure_domain(instance).unwrap();
+                self.push_to_failure_domain(instance, &mut loads, affinity, depth)?;
             } else {
                 debug!("instance {} is either finished its failure domains processing, or doesn't have one", instance.name);
-                self.push(instance).unwrap();
+                self.push(instance, &mut loads, affinity, depth)?;
             }
         }
 
-        self.hosts.sort_by(|left, right| left.name.cmp(&right.name));
-        self.hosts.iter_mut().for_each(|host| {
+        self.hosts.iter_mut().try_for_each(|host| {
             host.config = host.config.clone().merge(self.config.clone());
-            host.inner_spread();
-        });
+            host.inner_spread(affinity, depth + 1)
+        })?;
 
         self.finish_host_spread();
+        Ok(())
     }
 
     fn finish_host_spread(&mut self) {
@@ -206,11 +218,12 @@ impl Host {
 
         let mut instances = mem::take(&mut self.instances);
 
-        for (index, instance) in instances.iter_mut().enumerate() {
+        let mut ports = HostPorts::new(&self.config, &instances);
+        for instance in instances.iter_mut() {
             instance.config = instance
                 .config
                 .clone()
-                .merge_and_up_ports(self.config.clone(), index as u16);
+                .merge_and_take_ports(self.config.clone(), &mut ports);
             debug!(
                 "host: {} instance: {} config: {:?}",
                 self.name, instance.name, instance.config
@@ -220,20 +233,43 @@ impl Host {
         self.instances = instances
     }
 
-    fn push(&mut self, instance: Instance) -> Result<(), GeninError> {
-        let host = if let Some(host) = self.hosts.first_mut() {
-            host
-        } else {
+    fn push(
+        &mut self,
+        mut instance: Instance,
+        loads: &mut Loads,
+        affinity: &Affinity,
+        depth: usize,
+    ) -> Result<(), GeninError> {
+        let host = if self.hosts.is_empty() {
             self
+        } else {
+            let decision = Decision::choose(self, &instance, loads, |loads, index| {
+                let host = &self.hosts[index];
+                loads
+                    .role_restriction(host, index, &instance, &self.config)
+                    .or_else(|| {
+                        affinity.violation(&instance, host, depth + 1, |member| {
+                            loads.contains_member(index, member)
+                        })
+                    })
+            });
+            let Some(index) = decision.chosen_index() else {
+                return Err(self.unsatisfiable(&instance, &decision));
+            };
+            instance.placement.push(decision);
+            &mut self.hosts[index]
         };
-        host.instances.push(instance.clone());
-        host.add_queue
-            .insert(instance.name.clone(), instance.clone());
-        host.delete_queue.insert(instance.name.clone(), instance);
+        host.instances.push(instance);
         Ok(())
     }
 
-    fn push_to_failure_domain(&mut self, mut instance: Instance) -> Result<(), GeninError> {
+    fn push_to_failure_domain(
+        &mut self,
+        mut instance: Instance,
+        loads: &mut Loads,
+        affinity: &Affinity,
+        depth: usize,
+    ) -> Result<(), GeninError> {
         debug!(
             "trying to find reqested failure_domains inside host {} for instance {}",
             self.name, instance.name,
@@ -247,20 +283,34 @@ impl Host {
                 "host {} is final failure domain for instance {}",
                 self.name, instance.name
             );
-            return self.push(instance);
+            return self.push(instance, loads, affinity, depth);
         }
 
+        let domains: Vec<String> = instance.failure_domains.clone().into();
+        let decision = Decision::choose(self, &instance, loads, |loads, index| {
+            let host = &self.hosts[index];
+            if !host.contains_failure_domains(&domains) {
+                return Some(String::from("no failure domain"));
+            }
+            loads
+                .role_restriction(host, index, &instance, &self.config)
+                .or_else(|| {
+                    affinity.violation(&instance, host, depth + 1, |member| {
+                        loads.contains_member(index, member)
+                    })
+                })
+        });
+        let chosen = decision.chosen_index();
+        instance.placement.push(decision);
+
         let failure_domains = instance.failure_domains.try_get_queue()?;
 
         // retain only hosts that contains one of failure domain members
         // failure_domains: ["dc-1"] -> vec!["dc-1"]
-        let mut failure_domain_hosts: Vec<&mut Host> = self
+        let failure_domain_hosts: Vec<&Host> = self
             .hosts
-            .iter_mut()
-            .filter_map(|host| {
-                host.contains_failure_domains(failure_domains)
-                    .then_some(host)
-            })
+            .iter()
+            .filter(|host| host.contains_failure_domains(failure_domains))
             .collect();
         if !failure_domain_hosts.is_empty() {
             debug!(
@@ -272,12 +322,13 @@ impl Host {
                     .join(" "),
                 failure_domains.join(" "),
             );
-            failure_domain_hosts.sort();
-            if let Some(host) = failure_domain_hosts.first_mut() {
-                host.instances.push(instance.clone());
-                host.add_queue
-                    .insert(instance.name.clone(), instance.clone());
-                host.add_queue.insert(instance.name.clone(), instance);
+            // hosts are sorted from the least loaded, so the first eligible one wins
+            let Some(index) = chosen else {
+                let decision = instance.placement.last().cloned().unwrap();
+                return Err(self.unsatisfiable(&instance, &decision));
+            };
+            if let Some(host) = self.hosts.get_mut(index) {
+                host.instances.push(instance);
                 return Ok(());
             };
         }
@@ -296,6 +347,97 @@ impl Host {
         ))
     }
 
+    fn unsatisfiable(&self, instance: &Instance, decision: &Decision) -> GeninError {
+        GeninError::new(
+            GeninErrorKind::SpreadingError,
+            format!(
+                "placement rules of {} can not be satisfied by any of the hosts [{}] of {}",
+                instance.name,
+                decision
+                    .candidates
+                    .iter()
+                    .map(|candidate| format!(
+                        "{}: {}",
+                        candidate.name,
+                        candidate.excluded.clone().unwrap_or_default()
+                    ))
+                    .collect::<Vec<String>>()
+                    .join(", "),
+                self.name,
+            ),
+        )
+    }
+
+    /// Reason why none of the leaf hosts under the host takes the instance
+    /// because of its roles, `allowed` and `denied` are the role restrictions
+    /// the host inherits.
+    pub fn role_restriction(
+        &self,
+        instance: &Instance,
+        allowed: &[String],
+        denied: &[String],
+    ) -> Option<String> {
+        let allowed = if self.config.allowed_roles.is_empty() {
+            allowed
+        } else {
+            &self.config.allowed_roles
+        };
+        let denied = if self.config.denied_roles.is_empty() {
+            denied
+        } else {
+            &self.config.denied_roles
+        };
+        if self.hosts.is_empty() {
+            return role_restriction(allowed, denied, &instance.roles);
+        }
+        let mut restriction = None;
+        for host in &self.hosts {
+            match host.role_restriction(instance, allowed, denied) {
+                None => return None,
+                Some(reason) => {
+                    restriction.get_or_insert(reason);
+                }
+            }
+        }
+        restriction
+    }
+
+    /// Weight of the host for the weighted spreading strategy, hosts without
+    /// their own weight weigh as much as their nested hosts together, and
+    /// leaf hosts weigh 1.
+    pub fn weight(&self) -> usize {
+        self.config.weight.unwrap_or_else(|| {
+            if self.hosts.is_empty() {
+                1
+            } else {
+                self.hosts.iter().map(Host::weight).sum()
+            }
+        })
+    }
+
+    /// Instances the host takes with the `pack` strategy, which is its own
+    /// weight, or the capacity of its nested hosts together when all of them
+    /// have one. Leaf hosts without a weight have no limit.
+    pub fn capacity(&self) -> Option<usize> {
+        self.config.weight.or_else(|| {
+            if self.hosts.is_empty() {
+                None
+            } else {
+                self.hosts.iter().map(Host::capacity).sum()
+            }
+        })
+    }
+
+    /// Whether the host or any of its nested hosts has an instance of the
+    /// topology member.
+    #[cfg(test)]
+    pub fn contains_member(&self, member: &str) -> bool {
+        self.instances
+            .iter()
+            .any(|instance| instance.name.get_ancestor_str() == member)
+            || self.hosts.iter().any(|host| host.contains_member(member))
+    }
+
     fn advertise_as_failure_domain(&mut self, instance: &mut Instance) -> Result<(), GeninError> {
         let failure_domains = instance.failure_domains.try_get_queue()?;
         let failure_domain_index = failure_domains
@@ -446,6 +588,19 @@ impl Host {
         }
     }
 
+    /// Whether the host itself or any of its nested hosts has the name.
+    pub fn contains_host(&self, name: &str) -> bool {
+        self.contains_failure_domains(&vec![name.to_string()])
+    }
+
+    /// The host itself or the nested host with the name.
+    pub fn find_host(&self, name: &str) -> Option<&Host> {
+        if self.name.to_string() == name {
+            return Some(self);
+        }
+        self.hosts.iter().find_map(|host| host.find_host(name))
+    }
+
     fn contains_failure_domains(&self, failure_domais: &Vec<String>) -> bool {
         if failure_domais.contains(&self.name.to_string()) {
             return true;
@@ -531,6 +686,8 @@ impl Host {
                     alignment: Alignment::center(),
                     color: FG_BRIGHT_BLACK,
                 },
+                spread_strategy: Strategy::default(),
+                placement: Placement::default(),
             });
         }
     }
@@ -580,7 +737,31 @@ impl Host {
     /// right [server-1, server-2]
     /// -> left [server-1, server-2]
     pub fn merge(left: &mut Host, right: &mut Host, idiomatic: bool) -> Vec<Change> {
+        // the address and the ports of the host stay as they were in the
+        // state, everything else is taken from the new config
         std::mem::swap(&mut left.config.distance, &mut right.config.distance);
+        std::mem::swap(&mut left.config.weight, &mut right.config.weight);
+        std::mem::swap(
+            &mut left.config.http_port_range,
+            &mut right.config.http_port_range,
+        );
+        std::mem::swap(
+            &mut left.config.binary_port_range,
+            &mut right.config.binary_port_range,
+        );
+        std::mem::swap(
+            &mut left.config.reserved_ports,
+            &mut right.config.reserved_ports,
+        );
+        std::mem::swap(&mut left.config.labels, &mut right.config.labels);
+        std::mem::swap(
+            &mut left.config.allowed_roles,
+            &mut right.config.allowed_roles,
+        );
+        std::mem::swap(
+            &mut left.config.denied_roles,
+            &mut right.config.denied_roles,
+        );
         std::mem::swap(
             &mut left.config.additional_config,
             &mut right.config.additional_config,
@@ -604,6 +785,14 @@ impl Host {
                     &mut right.config.vshard_group,
                 );
                 std::mem::swap(&mut left.config.all_rw, &mut right.config.all_rw);
+                std::mem::swap(
+                    &mut left.config.http_port_base,
+                    &mut right.config.http_port_base,
+                );
+                std::mem::swap(
+                    &mut left.config.binary_port_base,
+                    &mut right.config.binary_port_base,
+                );
                 std::mem::swap(
                     &mut left.config.additional_config,
                     &mut right.config.additional_config,
@@ -628,9 +817,12 @@ impl Host {
             });
         }
 
-        let mut similar_instances = left.add_queue.clone();
-        similar_instances
-            .retain(|name, _| right.add_queue.contains_key(&name.clone().with_index(1)));
+        let similar_instances = left
+            .add_queue
+            .keys()
+            .filter(|name| right.add_queue.contains_key(&(*name).clone().with_index(1)))
+            .cloned()
+            .collect::<HashSet<Name>>();
 
         right.add_queue.retain(|name, _| {
             if !idiomatic && name.len() == 3 && name.get_parent_name().with_index(1).eq(name) {
@@ -644,7 +836,7 @@ impl Host {
         std::mem::swap(&mut left.add_queue, &mut right.add_queue);
 
         left.delete_queue.retain(|name, _| {
-            !right.delete_queue.contains_key(name) && !similar_instances.contains_key(name)
+            !right.delete_queue.contains_key(name) && !similar_instances.contains(name)
         });
 
         right.hosts.iter_mut().for_each(|right_host| {
@@ -713,19 +905,28 @@ impl Host {
         let mut instances_for_spreading = self
             .add_queue
             .iter()
-            .map(|(_, instance)| instance.clone())
+            .map(|(_, instance)| Instance {
+                // placement of the instance is traced again from scratch
+                placement: Placement::default(),
+                ..instance.clone()
+            })
             .collect::<Vec<Instance>>();
         instances_for_spreading.sort();
         self.instances = Instances::from(instances_for_spreading);
     }
 
     pub fn remove_diff(&mut self) {
+        let delete_queue = mem::take(&mut self.delete_queue);
+        self.remove_instances(&delete_queue);
+        self.delete_queue = delete_queue;
+    }
+
+    pub fn remove_instances(&mut self, delete_queue: &IndexMap<Name, Instance>) {
         self.instances
-            .retain(|instance| !self.delete_queue.contains_key(&instance.name));
-        self.hosts.iter_mut().for_each(|host| {
-            host.delete_queue = self.delete_queue.clone();
-            host.remove_diff()
-        })
+            .retain(|instance| !delete_queue.contains_key(&instance.name));
+        self.hosts
+            .iter_mut()
+            .for_each(|host| host.remove_instances(delete_queue))
     }
 
     pub fn collect_instances(&mut self) -> Instances {
@@ -738,6 +939,66 @@ impl Host {
         instances
     }
 
+    /// Names of the leaf hosts matching the selector, with the labels
+    /// inherited from the parent hosts.
+    pub fn select(&self, selector: &Selector) -> Vec<String> {
+        self.select_with_labels(selector, &IndexMap::new())
+    }
+
+    fn select_with_labels(
+        &self,
+        selector: &Selector,
+        inherited: &IndexMap<String, Value>,
+    ) -> Vec<String> {
+        let labels = merge_index_maps(self.config.labels.clone(), inherited.clone());
+        if self.hosts.is_empty() {
+            return if selector.matches(&labels) {
+                vec![self.name.to_string()]
+            } else {
+                Vec::new()
+            };
+        }
+        self.hosts
+            .iter()
+            .flat_map(|host| host.select_with_labels(selector, &labels))
+            .collect()
+    }
+
+    /// Instance with the given name and the path of hosts down to the leaf
+    /// host it is placed on.
+    pub fn find_instance(&self, name: &str) -> Option<(Vec<String>, &Instance)> {
+        self.instances
+            .iter()
+            .find(|instance| instance.name.to_string() == name)
+            .map(|instance| (Vec::new(), instance))
+            .or_else(|| self.hosts.iter().find_map(|host| host.find_instance(name)))
+            .map(|(mut path, instance)| {
+                path.insert(0, self.name.to_string());
+                (path, instance)
+            })
+    }
+
+    /// Port conflicts of every leaf host, see [`ports::conflicts`].
+    pub fn port_conflicts(&self) -> Vec<PortConflict> {
+        if self.hosts.is_empty() {
+            return ports::conflicts(&self.name.to_string(), &self.config, &self.instances);
+        }
+        self.hosts
+            .iter()
+            .flat_map(|host| host.port_conflicts())
+            .collect()
+    }
+
+    /// Forgets the ports of every instance, so that the next spread
+    /// assigns them again from the base ports.
+    pub fn clean_ports(&mut self) {
+        self.instances.iter_mut().for_each(|instance| {
+            instance.config = instance.config.clone().clean_ports();
+        });
+
+        self.hosts.iter_mut().for_each(|host| host.clean_ports());
+    }
+
     pub fn clear_view(&mut self) {
         self.instances.iter_mut().for_each(|instance| {
             instance.view.color = FG_WHITE;
@@ -759,6 +1020,27 @@ pub struct HostConfig {
     pub ansible_host: Address,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub distance: Option<usize>,
+    /// Share of the instances the host takes with the weighted spreading
+    /// strategy, not inherited by the nested hosts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weight: Option<usize>,
+    /// Ports the instances of the host must stay within
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_port_range: Option<PortRange>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub binary_port_range: Option<PortRange>,
+    /// Ports taken by other services, inherited by the nested hosts
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reserved_ports: Vec<PortRange>,
+    /// Arbitrary labels for placement selectors, inherited by the nested hosts
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub labels: IndexMap<String, Value>,
+    /// Only instances with one of these roles are placed on the host
+    #[serde(default, alias = "dedicated_to", skip_serializing_if = "Vec::is_empty")]
+    pub allowed_roles: Vec<String>,
+    /// Instances with any of these roles are never placed on the host
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub denied_roles: Vec<String>,
     #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
     pub additional_config: IndexMap<String, Value>,
 }
@@ -814,6 +1096,13 @@ impl<'a> From<&'a InvHostConfig> for HostConfig {
                 address: advertise_uri.address.clone(),
                 ansible_host: Default::default(),
                 distance: None,
+                http_port_range: None,
+                binary_port_range: None,
+                reserved_ports: Vec::new(),
+                labels: IndexMap::new(),
+                weight: None,
+                allowed_roles: Vec::new(),
+                denied_roles: Vec::new(),
                 additional_config: additional_config.clone(),
             },
             InvHostConfig::Stateboard(additional_config) => Self {
@@ -829,6 +1118,13 @@ impl<'a> From<&'a InvHostConfig> for HostConfig {
                     .unwrap(),
                 ansible_host: Default::default(),
                 distance: None,
+                http_port_range: None,
+                binary_port_range: None,
+                reserved_ports: Vec::new(),
+                labels: IndexMap::new(),
+                weight: None,
+                allowed_roles: Vec::new(),
+                denied_roles: Vec::new(),
                 additional_config: additional_config.clone(),
             },
         }
@@ -852,11 +1148,31 @@ impl From<IndexMap<String, Value>> for HostConfig {
     }
 }
 
+/// Reason why a host with these role restrictions does not take instances
+/// with these roles.
+fn role_restriction(allowed: &[String], denied: &[String], roles: &[Role]) -> Option<String> {
+    let named = |names: &[String], role: &Role| names.iter().any(|name| role.is_named(name));
+    if let Some(role) = roles.iter().find(|role| named(denied, role)) {
+        return Some(format!("denies {role}"));
+    }
+    if !allowed.is_empty() && !roles.iter().any(|role| named(allowed, role)) {
+        return Some(format!("dedicated to {}", allowed.join(", ")));
+    }
+    None
+}
+
 impl HostConfig {
     pub fn is_none(&self) -> bool {
         self.http_port.is_none()
             && self.binary_port.is_none()
             && self.address.is_none()
+            && self.labels.is_empty()
+            && self.weight.is_none()
+            && self.http_port_range.is_none()
+            && self.binary_port_range.is_none()
+            && self.reserved_ports.is_empty()
+            && self.allowed_roles.is_empty()
+            && self.denied_roles.is_empty()
             && self.additional_config.is_empty()
     }
 
@@ -895,12 +1211,44 @@ impl HostConfig {
     }
 
     pub fn merge(self, other: HostConfig) -> Self {
+        // a host with its own range starts counting from the range
+        // rather than from the port of the parent
+        let http_port_range = self.http_port_range.or(other.http_port_range);
+        let binary_port_range = self.binary_port_range.or(other.binary_port_range);
+        let mut reserved_ports = self.reserved_ports;
+        other.reserved_ports.into_iter().for_each(|range| {
+            if !reserved_ports.contains(&range) {
+                reserved_ports.push(range);
+            }
+        });
+
         Self {
-            http_port: self.http_port.or(other.http_port),
-            binary_port: self.binary_port.or(other.binary_port),
+            http_port: self
+                .http_port
+                .or(self.http_port_range.map(|range| range.start))
+                .or(other.http_port),
+            binary_port: self
+                .binary_port
+                .or(self.binary_port_range.map(|range| range.start))
+                .or(other.binary_port),
             address: self.address.or(other.address),
             ansible_host: self.ansible_host.or(other.ansible_host),
             distance: self.distance.or(other.distance),
+            http_port_range,
+            binary_port_range,
+            reserved_ports,
+            labels: merge_index_maps(self.labels, other.labels),
+            weight: self.weight,
+            allowed_roles: if self.allowed_roles.is_empty() {
+                other.allowed_roles
+            } else {
+                self.allowed_roles
+            },
+            denied_roles: if self.denied_roles.is_empty() {
+                other.denied_roles
+            } else {
+                self.denied_roles
+            },
             additional_config: merge_index_maps(self.additional_config, other.additional_config),
         }
     }
@@ -0,0 +1,367 @@
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+use indexmap::IndexMap;
+use serde::Deserialize;
+use serde_yaml::{Mapping, Value};
+
+/// Leaf hosts loaded from a local file in place of the `hosts` entry. The
+/// `datacenter` and `region` columns build the levels above the hosts.
+/// ```yaml
+/// hosts:
+///   - import: servers.csv
+///     columns:
+///       name: hostname
+///       address: ip
+///       datacenter: dc
+///       labels: [rack]
+///     config:
+///       http_port: 8081
+/// ```
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct HostImport {
+    import: PathBuf,
+    #[serde(default)]
+    format: Option<ImportFormat>,
+    /// Terraform output holding the hosts, may be omitted if there is only one
+    #[serde(default)]
+    output: Option<String>,
+    #[serde(default)]
+    columns: Columns,
+    /// Config shared by all imported hosts
+    #[serde(default)]
+    config: Mapping,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportFormat {
+    /// Comma separated values with a header
+    Csv,
+    /// `/etc/hosts` style lines of an address and a name
+    Hosts,
+    /// `terraform output -json`
+    Terraform,
+}
+
+/// Columns of the file, or keys of the terraform objects, the host settings
+/// are taken from.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Columns {
+    name: String,
+    address: String,
+    ansible_host: String,
+    datacenter: String,
+    region: String,
+    labels: Vec<String>,
+}
+
+impl Default for Columns {
+    fn default() -> Self {
+        Self {
+            name: "name".into(),
+            address: "address".into(),
+            ansible_host: "ansible_host".into(),
+            datacenter: "datacenter".into(),
+            region: "region".into(),
+            labels: Vec::new(),
+        }
+    }
+}
+
+pub type Row = IndexMap<String, String>;
+
+impl ImportFormat {
+    fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("csv") => Self::Csv,
+            Some("json") => Self::Terraform,
+            _ => Self::Hosts,
+        }
+    }
+
+    pub fn rows(&self, text: &str, output: Option<&str>) -> Result<Vec<Row>, String> {
+        match self {
+            Self::Csv => csv_rows(text),
+            Self::Hosts => Ok(hosts_rows(text)),
+            Self::Terraform => terraform_rows(text, output),
+        }
+    }
+}
+
+fn csv_fields(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                fields.last_mut().unwrap().push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            c => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+        .into_iter()
+        .map(|field| field.trim().to_string())
+        .collect()
+}
+
+fn csv_rows(text: &str) -> Result<Vec<Row>, String> {
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty());
+    let Some((_, header)) = lines.next() else {
+        return Ok(Vec::new());
+    };
+    let header = csv_fields(header);
+
+    lines
+        .map(|(number, line)| {
+            let fields = csv_fields(line);
+            if fields.len() != header.len() {
+                return Err(format!(
+                    "line {} has {} fields while the header has {}",
+                    number + 1,
+                    fields.len(),
+                    header.len()
+                ));
+            }
+            Ok(header.iter().cloned().zip(fields).collect())
+        })
+        .collect()
+}
+
+/// Loopback, multicast and `ip6-` names found in every `/etc/hosts` are not
+/// cluster hosts.
+fn hosts_rows(text: &str) -> Vec<Row> {
+    text.lines()
+        .filter_map(|line| {
+            let mut fields = line.split('#').next()?.split_whitespace();
+            let (address, name) = (fields.next()?, fields.next()?);
+            let local = address
+                .parse::<IpAddr>()
+                .is_ok_and(|ip| ip.is_loopback() || ip.is_multicast());
+            (!local && !name.starts_with("ip6-")).then(|| {
+                Row::from([
+                    ("name".to_string(), name.to_string()),
+                    ("address".to_string(), address.to_string()),
+                ])
+            })
+        })
+        .collect()
+}
+
+fn scalar(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::String(value) => Some(value.clone()),
+        serde_json::Value::Number(value) => Some(value.to_string()),
+        serde_json::Value::Bool(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+/// The output is either a list of objects or an object of hosts by name,
+/// where a host is an object or just an address.
+fn terraform_rows(text: &str, output: Option<&str>) -> Result<Vec<Row>, String> {
+    let outputs: IndexMap<String, serde_json::Value> =
+        serde_json::from_str(text).map_err(|error| error.to_string())?;
+    let value = match output {
+        Some(output) => outputs
+            .get(output)
+            .ok_or_else(|| format!("there is no terraform output {output}"))?,
+        None if outputs.len() == 1 => outputs.values().next().unwrap(),
+        None => {
+            return Err(format!(
+                "there are several terraform outputs, choose one of {} with output",
+                outputs.keys().cloned().collect::<Vec<_>>().join(", ")
+            ))
+        }
+    };
+    let value = value.get("value").unwrap_or(value);
+
+    let object_row = |object: &serde_json::Map<String, serde_json::Value>| -> Row {
+        object
+            .iter()
+            .filter_map(|(key, value)| Some((key.clone(), scalar(value)?)))
+            .collect()
+    };
+    match value {
+        serde_json::Value::Array(hosts) => hosts
+            .iter()
+            .map(|host| {
+                host.as_object()
+                    .map(object_row)
+                    .ok_or_else(|| format!("terraform host {host} is not an object"))
+            })
+            .collect(),
+        serde_json::Value::Object(hosts) => Ok(hosts
+            .iter()
+            .map(|(name, host)| {
+                let mut row = match host {
+                    serde_json::Value::Object(host) => object_row(host),
+                    host => {
+                        Row::from_iter(scalar(host).map(|address| ("address".to_string(), address)))
+                    }
+                };
+                row.entry("name".into()).or_insert_with(|| name.clone());
+                row
+            })
+            .collect()),
+        _ => Err("terraform output is neither a list nor an object of hosts".into()),
+    }
+}
+
+impl HostImport {
+    /// Hosts of the file with the names of the region and datacenter they
+    /// belong to.
+    pub fn hosts(&self) -> Result<Vec<(Vec<String>, Value)>, String> {
+        let context =
+            |error: String| format!("failed to import {}: {error}", self.import.display());
+        let text =
+            std::fs::read_to_string(&self.import).map_err(|error| context(error.to_string()))?;
+        let rows = self
+            .format
+            .unwrap_or_else(|| ImportFormat::from_path(&self.import))
+            .rows(&text, self.output.as_deref())
+            .map_err(context)?;
+
+        rows.into_iter()
+            .enumerate()
+            .map(|(index, mut row)| {
+                let mut column = |name: &str| row.remove(name).filter(|value| !value.is_empty());
+                let name = column(&self.columns.name).ok_or_else(|| {
+                    context(format!("host {} has no {}", index + 1, self.columns.name))
+                })?;
+
+                let mut config = self.config.clone();
+                [
+                    ("address", column(&self.columns.address)),
+                    ("ansible_host", column(&self.columns.ansible_host)),
+                ]
+                .into_iter()
+                .for_each(|(key, value)| {
+                    if let Some(value) = value {
+                        config.insert(key.into(), Value::from(value));
+                    }
+                });
+                let labels = self
+                    .columns
+                    .labels
+                    .iter()
+                    .filter_map(|label| {
+                        Some((Value::from(label.as_str()), Value::from(column(label)?)))
+                    })
+                    .collect::<Mapping>();
+                if !labels.is_empty() {
+                    config.insert("labels".into(), Value::Mapping(labels));
+                }
+
+                let path = [
+                    column(&self.columns.region),
+                    column(&self.columns.datacenter),
+                ]
+                .into_iter()
+                .flatten()
+                .collect();
+                let mut host = Mapping::new();
+                host.insert("name".into(), Value::from(name));
+                if !config.is_empty() {
+                    host.insert("config".into(), Value::Mapping(config));
+                }
+                Ok((path, Value::Mapping(host)))
+            })
+            .collect()
+    }
+}
+
+/// Puts the host under the levels of the path, the levels are shared with
+/// the hosts listed before.
+fn insert(hosts: &mut Vec<Value>, path: &[String], host: Value) {
+    let Some((level, path)) = path.split_first() else {
+        hosts.push(host);
+        return;
+    };
+    let name = Value::from(level.as_str());
+    let index = hosts
+        .iter()
+        .position(|host| host.get("name") == Some(&name))
+        .unwrap_or_else(|| {
+            let mut host = Mapping::new();
+            host.insert("name".into(), name);
+            hosts.push(Value::Mapping(host));
+            hosts.len() - 1
+        });
+    let Value::Mapping(parent) = &mut hosts[index] else {
+        return;
+    };
+    let children = parent
+        .entry("hosts".into())
+        .or_insert_with(|| Value::Sequence(Vec::new()));
+    if let Value::Sequence(children) = children {
+        insert(children, path, host);
+    }
+}
+
+fn import_hosts(hosts: Vec<Value>) -> Result<Vec<Value>, String> {
+    let mut result = Vec::with_capacity(hosts.len());
+    for mut host in hosts {
+        if host.get("import").is_some() {
+            let import: HostImport =
+                serde_yaml::from_value(host).map_err(|error| error.to_string())?;
+            import
+                .hosts()?
+                .into_iter()
+                .for_each(|(path, host)| insert(&mut result, &path, host));
+            continue;
+        }
+        if let Some(Value::Sequence(children)) = host.get_mut("hosts") {
+            *children = import_hosts(std::mem::take(children))?;
+        }
+        result.push(host);
+    }
+    Ok(result)
+}
+
+/// Makes the `import` paths of the hosts at any level of the config read from
+/// `path` start at that file rather than at the working directory, so that
+/// the hosts of an included file import what lies next to it. Returns the
+/// paths of the imported files.
+pub fn anchor_imports(config: &mut Value, path: &Path) -> Vec<PathBuf> {
+    let mut imported = Vec::new();
+    if let Some(Value::Sequence(hosts)) = config.get_mut("hosts") {
+        let dir = path.parent().unwrap_or(Path::new(""));
+        anchor_hosts(hosts, dir, &mut imported);
+    }
+    imported
+}
+
+fn anchor_hosts(hosts: &mut [Value], dir: &Path, imported: &mut Vec<PathBuf>) {
+    hosts.iter_mut().for_each(|host| {
+        if let Some(Value::String(import)) = host.get_mut("import") {
+            let path = dir.join(&*import);
+            *import = path.display().to_string();
+            imported.push(path);
+        } else if let Some(Value::Sequence(children)) = host.get_mut("hosts") {
+            anchor_hosts(children, dir, imported);
+        }
+    });
+}
+
+/// Replaces the `import` entries of the hosts at any level with the hosts
+/// loaded from the files.
+pub fn resolve_imports(mut config: Value) -> Result<Value, String> {
+    if let Some(Value::Sequence(hosts)) = config.get_mut("hosts") {
+        *hosts = import_hosts(std::mem::take(hosts))?;
+    }
+    Ok(config)
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,164 @@
+use std::fs;
+
+use crate::task::cluster::{
+    host::{
+        hst::Address,
+        import::{ImportFormat, Row},
+    },
+    Cluster,
+};
+
+fn row(fields: &[(&str, &str)]) -> Row {
+    fields
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+#[test]
+fn import_formats() {
+    let csv = "name,address,rack\nsrv-1, 10.0.0.1 ,\"r1, left\"\n\nsrv-2,10.0.0.2,r2\n";
+    assert_eq!(
+        ImportFormat::Csv.rows(csv, None).unwrap(),
+        vec![
+            row(&[
+                ("name", "srv-1"),
+                ("address", "10.0.0.1"),
+                ("rack", "r1, left")
+            ]),
+            row(&[("name", "srv-2"), ("address", "10.0.0.2"), ("rack", "r2")]),
+        ]
+    );
+    assert_eq!(
+        ImportFormat::Csv
+            .rows("name,address\nsrv-1\n", None)
+            .unwrap_err(),
+        "line 2 has 1 fields while the header has 2"
+    );
+
+    let hosts = "127.0.0.1 localhost\n::1 ip6-localhost ip6-loopback\n\
+                 10.0.0.1 srv-1 srv-1.local # app\n# 10.0.0.3 srv-3\n";
+    assert_eq!(
+        ImportFormat::Hosts.rows(hosts, None).unwrap(),
+        vec![row(&[("name", "srv-1"), ("address", "10.0.0.1")])]
+    );
+
+    let terraform = r#"{
+        "genin_hosts": {
+            "sensitive": false,
+            "value": [{"name": "srv-1", "address": "10.0.0.1", "tags": ["app"]}]
+        },
+        "addresses": {"value": {"srv-2": "10.0.0.2"}}
+    }"#;
+    assert_eq!(
+        ImportFormat::Terraform
+            .rows(terraform, Some("genin_hosts"))
+            .unwrap(),
+        vec![row(&[("name", "srv-1"), ("address", "10.0.0.1")])]
+    );
+    assert_eq!(
+        ImportFormat::Terraform
+            .rows(terraform, Some("addresses"))
+            .unwrap(),
+        vec![row(&[("address", "10.0.0.2"), ("name", "srv-2")])]
+    );
+    assert_eq!(
+        ImportFormat::Terraform.rows(terraform, None).unwrap_err(),
+        "there are several terraform outputs, choose one of genin_hosts, addresses with output"
+    );
+}
+
+#[test]
+fn hosts_imported_from_csv() {
+    let path = std::env::temp_dir().join(format!("genin-import-{}.csv", std::process::id()));
+    fs::write(
+        &path,
+        "hostname,ip,dc,rack\n\
+         srv-1,10.0.0.1,dc-1,r1\n\
+         srv-2,10.0.0.2,dc-2,r1\n\
+         srv-3,10.0.0.3,dc-1,r2\n",
+    )
+    .unwrap();
+
+    let config = format!(
+        r#"---
+topology:
+  - name: storage
+    replicasets_count: 2
+    replication_factor: 2
+    roles:
+      - storage
+hosts:
+  - name: dc-1
+    hosts:
+      - name: srv-0
+        config:
+          address: 10.0.0.10
+  - import: {}
+    columns:
+      name: hostname
+      address: ip
+      datacenter: dc
+      labels: [rack]
+    config:
+      http_port: 9081
+failover:
+  mode: disabled
+vars:
+  ansible_user: ansible
+  ansible_password: ansible
+  cartridge_app_name: myapp
+  cartridge_cluster_cookie: myapp-cookie
+"#,
+        path.display()
+    );
+    let cluster: Result<Cluster, _> = serde_yaml::from_str(&config);
+    fs::remove_file(&path).unwrap();
+    let cluster = cluster.unwrap();
+
+    let hosts = cluster
+        .hosts
+        .hosts
+        .iter()
+        .map(|dc| {
+            (
+                dc.name.to_string(),
+                dc.hosts
+                    .iter()
+                    .map(|host| host.name.to_string())
+                    .collect::<Vec<_>>(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        hosts,
+        vec![
+            (
+                "dc-1".into(),
+                vec!["srv-0".into(), "srv-1".into(), "srv-3".into()]
+            ),
+            ("dc-2".into(), vec!["srv-2".into()]),
+        ]
+    );
+
+    let srv_3 = cluster
+        .hosts
+        .lower_level_hosts()
+        .into_iter()
+        .find(|host| host.name.to_string() == "srv-3")
+        .unwrap();
+    assert_eq!(srv_3.config.address, Address::from([10, 0, 0, 3]));
+    assert_eq!(srv_3.config.http_port, Some(9081));
+    assert_eq!(srv_3.config.labels.get("rack"), Some(&"r2".into()));
+}
+
+#[test]
+fn import_of_missing_file() {
+    let config = "hosts:\n  - import: /nonexistent/hosts.csv\n";
+    let error =
+        crate::task::cluster::resolve_config(serde_yaml::from_str(config).unwrap()).unwrap_err();
+    assert!(
+        error.starts_with("failed to import /nonexistent/hosts.csv: "),
+        "{error}"
+    );
+}
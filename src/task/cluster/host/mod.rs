@@ -1,4 +1,11 @@
+pub mod affinity;
 pub mod hst;
+pub mod import;
+pub mod placement;
+pub mod ports;
+pub mod range;
+pub mod selector;
+pub mod strategy;
 pub mod view;
 use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
@@ -0,0 +1,224 @@
+use std::fmt::{Debug, Display};
+
+use crate::task::cluster::host::hst::Host;
+use crate::task::cluster::host::strategy::Loads;
+use crate::task::cluster::instance::ins::{FailureDomains, Instance};
+use crate::task::cluster::name::Name;
+
+/// Decisions made by the spreading for one instance, from the top level host
+/// down to the leaf host. Instances restored from the state keep their place
+/// and have no decisions.
+#[derive(Clone, Default)]
+pub struct Placement(Vec<Decision>);
+
+/// Placement is only a trace of the spreading and never makes instances differ.
+impl PartialEq for Placement {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Placement {}
+
+impl Debug for Placement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|decision| &decision.chosen))
+            .finish()
+    }
+}
+
+impl Placement {
+    pub fn push(&mut self, decision: Decision) {
+        self.0.push(decision)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn last(&self) -> Option<&Decision> {
+        self.0.last()
+    }
+}
+
+/// Choice of a child host at one level of the hosts tree.
+#[derive(Clone, Debug)]
+pub struct Decision {
+    pub host: String,
+    pub constraint: Constraint,
+    /// How the spreading strategy of the instance picks a child
+    pub choice: &'static str,
+    /// Children of the host looked at, from the least loaded one up to the
+    /// chosen one and the child that would have been next
+    pub candidates: Vec<Candidate>,
+    pub chosen: String,
+    index: Option<usize>,
+}
+
+impl Decision {
+    /// Chooses the first child in the order of the spreading strategy of the
+    /// instance that is not excluded, and counts the instance into its load.
+    /// Children after the chosen one are never looked at, except for the
+    /// next one. `exclude` gets the loads to look up what the child contains.
+    pub fn choose(
+        host: &Host,
+        instance: &Instance,
+        loads: &mut Loads,
+        exclude: impl Fn(&mut Loads, usize) -> Option<String>,
+    ) -> Self {
+        let strategy = instance.spread_strategy;
+        let candidate = |loads: &mut Loads, index: usize| Candidate {
+            name: host.hosts[index].name.to_string(),
+            load: loads.load(index),
+            excluded: exclude(loads, index),
+        };
+
+        let mut candidates = Vec::new();
+        let mut skipped = Vec::new();
+        let mut chosen = None;
+        while let Some(index) = loads.pop(strategy) {
+            candidates.push(candidate(loads, index));
+            if candidates[candidates.len() - 1].excluded.is_none() {
+                chosen = Some(index);
+                break;
+            }
+            skipped.push(index);
+        }
+        if chosen.is_some() {
+            if let Some(next) = loads.peek(strategy) {
+                candidates.push(candidate(loads, next));
+            }
+        }
+
+        skipped
+            .into_iter()
+            .for_each(|index| loads.restore(strategy, index));
+        if let Some(index) = chosen {
+            loads.add(index, instance.name.get_ancestor_str());
+        }
+
+        Self {
+            host: host.name.to_string(),
+            constraint: Constraint::from(&instance.failure_domains),
+            choice: loads.describe(strategy),
+            candidates,
+            chosen: chosen
+                .map(|index| host.hosts[index].name.to_string())
+                .unwrap_or_default(),
+            index: chosen,
+        }
+    }
+
+    /// Index of the chosen child among the children of the host.
+    pub fn chosen_index(&self) -> Option<usize> {
+        self.index
+    }
+
+    fn chosen_load(&self) -> Option<usize> {
+        self.candidates
+            .iter()
+            .find(|candidate| candidate.name == self.chosen)
+            .map(|candidate| candidate.load)
+    }
+}
+
+/// Failure domain constraint that applied to the decision.
+#[derive(Clone, Debug)]
+pub enum Constraint {
+    None,
+    /// The child must contain one of the failure domains
+    FailureDomains(Vec<String>),
+    /// The failure domain was already reached, any child of it will do
+    Within(String),
+}
+
+impl<'a> From<&'a FailureDomains> for Constraint {
+    fn from(failure_domains: &'a FailureDomains) -> Self {
+        match failure_domains {
+            FailureDomains::NotProvided(_) => Self::None,
+            FailureDomains::InProgress(domains) => Self::FailureDomains(domains.clone()),
+            FailureDomains::Finished(domain) => Self::Within(domain.clone()),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Candidate {
+    pub name: String,
+    /// Instances pushed to the host before the decision
+    pub load: usize,
+    /// Why the host could not take the instance
+    pub excluded: Option<String>,
+}
+
+/// Explanation of where the instance ended up and why, see `genin explain`.
+pub struct Explanation {
+    pub instance: Name,
+    /// Hosts from the top level host down to the leaf host of the instance
+    pub path: Vec<String>,
+    pub placement: Placement,
+}
+
+impl Display for Explanation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} is placed on {}", self.instance, self.path.join("/"))?;
+        if self.placement.is_empty() {
+            return writeln!(f, "it kept its host from the previous state");
+        }
+
+        self.placement.0.iter().try_for_each(|decision| {
+            let choice = decision.choice;
+            let reason = match &decision.constraint {
+                Constraint::None => choice.to_string(),
+                Constraint::FailureDomains(domains) => format!(
+                    "{choice} containing failure domains [{}]",
+                    domains.join(", ")
+                ),
+                Constraint::Within(domain) => {
+                    format!("{choice} inside failure domain {domain}")
+                }
+            };
+            let tie = decision.candidates.iter().any(|candidate| {
+                candidate.excluded.is_none()
+                    && candidate.name != decision.chosen
+                    && Some(candidate.load) == decision.chosen_load()
+            });
+            writeln!(
+                f,
+                "{}: {} chosen as {reason}{}",
+                decision.host,
+                decision.chosen,
+                if tie { ", ties are broken by name" } else { "" }
+            )?;
+
+            let width = decision
+                .candidates
+                .iter()
+                .map(|candidate| candidate.name.len())
+                .max()
+                .unwrap_or_default();
+            decision.candidates.iter().try_for_each(|candidate| {
+                writeln!(
+                    f,
+                    "  {} {:width$}  load {}{}",
+                    if candidate.name == decision.chosen {
+                        "+"
+                    } else {
+                        " "
+                    },
+                    candidate.name,
+                    candidate.load,
+                    candidate
+                        .excluded
+                        .as_ref()
+                        .map(|reason| format!(", {reason}"))
+                        .unwrap_or_default(),
+                )
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod test;
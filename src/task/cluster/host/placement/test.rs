@@ -0,0 +1,57 @@
+use crate::task::cluster::Cluster;
+
+const CLUSTER: &str = r#"---
+topology:
+  - name: router
+    replicasets_count: 2
+    roles:
+      - router
+  - name: storage
+    replicasets_count: 2
+    replication_factor: 2
+    failure_domains: [dc-2]
+    roles:
+      - storage
+hosts:
+  - name: dc-1
+    config:
+      http_port: 8081
+      binary_port: 3031
+    hosts:
+      - name: server-1
+        config:
+          address: 192.168.16.11
+      - name: server-2
+        config:
+          address: 192.168.16.12
+  - name: dc-2
+    config:
+      http_port: 8081
+      binary_port: 3031
+    hosts:
+      - name: server-3
+        config:
+          address: 192.168.16.13
+      - name: server-4
+        config:
+          address: 192.168.16.14
+failover:
+  mode: disabled
+vars:
+  ansible_user: ansible
+  ansible_password: ansible
+  cartridge_app_name: myapp
+  cartridge_cluster_cookie: myapp-cookie
+"#;
+
+#[test]
+fn explain_instance_placement() {
+    let cluster: Cluster = serde_yaml::from_str(CLUSTER).unwrap();
+
+    insta::assert_display_snapshot!(["router-2", "storage-2-1"]
+        .into_iter()
+        .map(|name| cluster.explain(name).unwrap().to_string())
+        .collect::<Vec<String>>()
+        .join("\n"));
+    assert!(cluster.explain("storage-3-1").is_err());
+}
@@ -0,0 +1,363 @@
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+
+use crate::task::cluster::host::hst::HostConfig;
+use crate::task::cluster::instance::ins::{Instance, Instances};
+use crate::task::flv::Uri;
+
+/// Inclusive range of ports, written as `8081-8099` or as a single port.
+/// ```yaml
+/// http_port_range: 8081-8099
+/// reserved_ports: [8085, 9100-9199]
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PortRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl PortRange {
+    pub fn contains(&self, port: u16) -> bool {
+        self.start <= port && port <= self.end
+    }
+}
+
+impl Display for PortRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.start == self.end {
+            write!(f, "{}", self.start)
+        } else {
+            write!(f, "{}-{}", self.start, self.end)
+        }
+    }
+}
+
+impl FromStr for PortRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |port: &str| {
+            port.trim()
+                .parse::<u16>()
+                .map_err(|_| format!("invalid port {port} in range {s}"))
+        };
+        let range = match s.split_once('-') {
+            Some((start, end)) => Self {
+                start: parse(start)?,
+                end: parse(end)?,
+            },
+            None => {
+                let port = parse(s)?;
+                Self {
+                    start: port,
+                    end: port,
+                }
+            }
+        };
+
+        if range.start > range.end {
+            return Err(format!("port range {s} starts after it ends"));
+        }
+        Ok(range)
+    }
+}
+
+impl<'de> Deserialize<'de> for PortRange {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum PortRangeHelper {
+            Port(u16),
+            Range(String),
+        }
+
+        match PortRangeHelper::deserialize(deserializer)? {
+            PortRangeHelper::Port(port) => Ok(Self {
+                start: port,
+                end: port,
+            }),
+            PortRangeHelper::Range(range) => range.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+impl Serialize for PortRange {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        if self.start == self.end {
+            serializer.serialize_u16(self.start)
+        } else {
+            serializer.serialize_str(&self.to_string())
+        }
+    }
+}
+
+/// Base ports of a role, instances with this role count their ports from
+/// here instead of the base ports of the host.
+/// ```yaml
+/// role_ports:
+///   router:
+///     http_port: 8081
+///     binary_port: 3301
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PortBase {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_port: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub binary_port: Option<u16>,
+}
+
+/// Ports in use on a leaf host. Instances keep the ports they were given
+/// once, new instances take the lowest free port from the base port of the
+/// host, skipping the reserved ones.
+#[derive(Debug, Default)]
+pub struct HostPorts {
+    taken: HashSet<u16>,
+    reserved: Vec<PortRange>,
+}
+
+impl HostPorts {
+    pub fn new(config: &HostConfig, instances: &Instances) -> Self {
+        let mut taken = HashSet::new();
+        instances.iter().for_each(|instance| {
+            taken.extend(instance.config.http_port);
+            taken.extend(instance.config.binary_port);
+            taken.extend(stateboard_port(instance));
+        });
+
+        Self {
+            taken,
+            reserved: config.reserved_ports.clone(),
+        }
+    }
+
+    /// Takes the lowest free port starting from `base`, `None` if every
+    /// port up to 65535 is in use.
+    pub fn take(&mut self, base: u16) -> Option<u16> {
+        let port = (base..=u16::MAX).find(|port| {
+            !self.taken.contains(port) && !self.reserved.iter().any(|range| range.contains(*port))
+        })?;
+        self.taken.insert(port);
+        Some(port)
+    }
+}
+
+fn stateboard_port(instance: &Instance) -> Option<u16> {
+    instance
+        .is_stateboard()
+        .then(|| instance.config.additional_config.get("listen"))
+        .flatten()
+        .and_then(|listen| serde_yaml::from_value::<Uri>(listen.clone()).ok())
+        .map(|listen| listen.port)
+}
+
+/// Port of a leaf host that cannot be used as generated.
+#[derive(Debug, PartialEq, Eq)]
+pub enum PortConflict {
+    /// Several instances listen on the same port
+    Duplicate {
+        host: String,
+        port: u16,
+        owners: Vec<String>,
+    },
+    Reserved {
+        host: String,
+        port: u16,
+        owner: String,
+    },
+    OutOfRange {
+        host: String,
+        port: u16,
+        owner: String,
+        range: PortRange,
+    },
+    /// No port was left for the instance: every port from the base port up
+    /// to 65535 is taken by other instances or reserved
+    Overflow { host: String, owner: String },
+    /// Ports counted from different bases run into each other
+    PoolOverlap {
+        host: String,
+        first: PortPool,
+        second: PortPool,
+    },
+}
+
+/// Ports of one kind counted from the same base on a leaf host.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PortPool {
+    pub kind: &'static str,
+    pub range: PortRange,
+}
+
+impl Display for PortPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ports {}", self.kind, self.range)
+    }
+}
+
+impl Display for PortConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PortConflict::Duplicate { host, port, owners } => {
+                write!(f, "{host}: port {port} is used by {}", owners.join(", "))
+            }
+            PortConflict::Reserved { host, port, owner } => {
+                write!(f, "{host}: port {port} of {owner} is reserved")
+            }
+            PortConflict::OutOfRange {
+                host,
+                port,
+                owner,
+                range,
+            } => write!(f, "{host}: port {port} of {owner} is outside of {range}"),
+            PortConflict::Overflow { host, owner } => {
+                write!(
+                    f,
+                    "{host} ran out of free ports for {owner}, every port from the base port up to 65535 is taken or reserved"
+                )
+            }
+            PortConflict::PoolOverlap {
+                host,
+                first,
+                second,
+            } => write!(f, "{host}: {first} overlap with {second}"),
+        }
+    }
+}
+
+/// Checks the final ports of the instances placed on a leaf host against
+/// each other, the reserved ports and the port ranges of the host. Pools of
+/// ports counted from different bases must not overlap either.
+pub fn conflicts(host: &str, config: &HostConfig, instances: &Instances) -> Vec<PortConflict> {
+    let mut conflicts = Vec::new();
+    let mut owners: IndexMap<u16, Vec<String>> = IndexMap::new();
+
+    let mut check = |port: Option<u16>, owner: String, range: Option<PortRange>| {
+        let Some(port) = port else {
+            conflicts.push(PortConflict::Overflow {
+                host: host.to_string(),
+                owner,
+            });
+            return;
+        };
+        if config
+            .reserved_ports
+            .iter()
+            .any(|range| range.contains(port))
+        {
+            conflicts.push(PortConflict::Reserved {
+                host: host.to_string(),
+                port,
+                owner: owner.clone(),
+            });
+        }
+        if let Some(range) = range.filter(|range| !range.contains(port)) {
+            conflicts.push(PortConflict::OutOfRange {
+                host: host.to_string(),
+                port,
+                owner: owner.clone(),
+                range,
+            });
+        }
+        owners.entry(port).or_default().push(owner);
+    };
+
+    instances.iter().for_each(|instance| {
+        if instance.is_stateboard() {
+            if let Some(port) = stateboard_port(instance) {
+                check(Some(port), format!("{} listen", instance.name), None);
+            }
+        } else {
+            check(
+                instance.config.http_port,
+                format!("{} http", instance.name),
+                config.http_port_range,
+            );
+            check(
+                instance.config.binary_port,
+                format!("{} binary", instance.name),
+                config.binary_port_range,
+            );
+        }
+    });
+
+    conflicts.extend(pool_overlaps(host, config, instances));
+    conflicts.extend(
+        owners
+            .into_iter()
+            .filter(|(_, owners)| owners.len() > 1)
+            .map(|(port, owners)| PortConflict::Duplicate {
+                host: host.to_string(),
+                port,
+                owners,
+            }),
+    );
+    conflicts
+}
+
+/// Every pool spans from its base to the highest port taken from it.
+fn pool_overlaps(host: &str, config: &HostConfig, instances: &Instances) -> Vec<PortConflict> {
+    let mut pools: IndexMap<(&'static str, u16), PortRange> = IndexMap::new();
+    let mut add = |kind: &'static str, base: Option<u16>, port: Option<u16>| {
+        if let (Some(base), Some(port)) = (base, port) {
+            let range = pools.entry((kind, base)).or_insert(PortRange {
+                start: base,
+                end: base,
+            });
+            range.start = range.start.min(port);
+            range.end = range.end.max(port);
+        }
+    };
+
+    instances
+        .iter()
+        .filter(|instance| !instance.is_stateboard())
+        .for_each(|instance| {
+            add(
+                "http",
+                instance.config.http_port_base.or(config.http_port),
+                instance.config.http_port,
+            );
+            add(
+                "binary",
+                instance.config.binary_port_base.or(config.binary_port),
+                instance.config.binary_port,
+            );
+        });
+
+    let pools = pools
+        .into_iter()
+        .map(|((kind, _), range)| PortPool { kind, range })
+        .collect::<Vec<_>>();
+
+    pools
+        .iter()
+        .enumerate()
+        .flat_map(|(index, first)| {
+            pools[index + 1..]
+                .iter()
+                .filter(|second| {
+                    first.range.start <= second.range.end && second.range.start <= first.range.end
+                })
+                .map(move |second| (first, second))
+        })
+        .map(|(first, second)| PortConflict::PoolOverlap {
+            host: host.to_string(),
+            first: *first,
+            second: *second,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test;
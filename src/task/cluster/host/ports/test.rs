@@ -0,0 +1,302 @@
+use crate::task::cluster::host::affinity::Affinity;
+use crate::task::cluster::host::hst::{Host, HostConfig, WithHosts};
+use crate::task::cluster::host::ports::PortRange;
+use crate::task::cluster::instance::ins::Instances;
+use crate::task::cluster::topology::Topology;
+use crate::task::cluster::Cluster;
+
+fn spread(hosts: &str, topology: &str) -> Host {
+    spread_with_role_ports(hosts, topology, "{}")
+}
+
+fn spread_with_role_ports(hosts: &str, topology: &str, role_ports: &str) -> Host {
+    let hosts: Vec<Host> = serde_yaml::from_str(hosts).unwrap();
+    let topology: Topology = serde_yaml::from_str(topology).unwrap();
+    let topology = topology.with_role_ports(&serde_yaml::from_str(role_ports).unwrap());
+
+    let mut host = Host::from("cluster")
+        .with_hosts(hosts)
+        .with_config(HostConfig::from((8081, 3031)))
+        .with_instances(Instances::from(&topology));
+    host.spread(&Affinity::default()).unwrap();
+    host
+}
+
+#[test]
+fn port_range_parse() {
+    assert_eq!(
+        "8081-8099".parse::<PortRange>(),
+        Ok(PortRange {
+            start: 8081,
+            end: 8099
+        })
+    );
+    assert_eq!(
+        serde_yaml::from_str::<Vec<PortRange>>("[9100, 9200-9299]").unwrap(),
+        vec![
+            PortRange {
+                start: 9100,
+                end: 9100
+            },
+            PortRange {
+                start: 9200,
+                end: 9299
+            }
+        ]
+    );
+    assert!("8099-8081".parse::<PortRange>().is_err());
+    assert!("8081-99999".parse::<PortRange>().is_err());
+    assert_eq!(
+        serde_yaml::to_string(&vec!["9100".parse::<PortRange>().unwrap()]).unwrap(),
+        "---\n- 9100\n"
+    );
+}
+
+#[test]
+fn port_settings_alone_are_host_config() {
+    let hosts = "- name: server-1\n  config:\n    http_port_range: 8081-8099\n\
+                 - name: server-2\n  config:\n    binary_port_range: 3031-3099\n\
+                 - name: server-3\n  config:\n    reserved_ports: [8085]\n";
+    let hosts: Vec<Host> = serde_yaml::from_str(hosts).unwrap();
+    hosts.iter().for_each(|host| {
+        assert!(!host.config.is_none(), "{}", host.name);
+        let serialized = serde_yaml::to_string(host).unwrap();
+        assert_eq!(serde_yaml::from_str::<Host>(&serialized).unwrap(), *host);
+    });
+}
+
+#[test]
+fn port_conflicts_on_leaf_hosts() {
+    let host = spread(
+        r#"---
+- name: server-1
+  config:
+    http_port_range: 8081-8082
+- name: server-2
+  config:
+    reserved_ports: [9000]
+"#,
+        r#"---
+- name: storage
+  replicasets_count: 3
+  replication_factor: 2
+  roles:
+    - storage
+- name: api
+  replicasets_count: 2
+  failure_domains: [server-2]
+  roles:
+    - api
+  config:
+    http_port: 9000
+"#,
+    );
+
+    insta::assert_snapshot!(host
+        .port_conflicts()
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<String>>()
+        .join("\n"));
+}
+
+#[test]
+fn ports_skip_taken_and_reserved() {
+    let mut host = spread(
+        r#"---
+- name: server-1
+  config:
+    binary_port: 8082
+    reserved_ports: [8084]
+"#,
+        r#"---
+- name: router
+  replicasets_count: 3
+  roles:
+    - router
+"#,
+    );
+
+    // interleaved http and binary ports are allowed, but reported as overlapping pools
+    assert_eq!(
+        host.port_conflicts()
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<String>>(),
+        vec!["server-1: http ports 8081-8086 overlap with binary ports 8082-8087"]
+    );
+    let ports = |host: &Host| {
+        host.hosts[0]
+            .instances
+            .iter()
+            .map(|instance| (instance.config.http_port, instance.config.binary_port))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        ports(&host),
+        vec![
+            (Some(8081), Some(8082)),
+            (Some(8083), Some(8085)),
+            (Some(8086), Some(8087))
+        ]
+    );
+
+    // ports are kept on the next spread and only cleaned explicitly
+    host.spread(&Affinity::default()).unwrap();
+    assert_eq!(ports(&host)[1], (Some(8083), Some(8085)));
+    host.clean_ports();
+    assert_eq!(ports(&host)[1], (None, None));
+}
+
+#[test]
+fn port_range_sets_base_port() {
+    let host = spread(
+        r#"---
+- name: server-1
+  config:
+    http_port_range: 9000-9009
+    binary_port_range: 4000-4009
+"#,
+        r#"---
+- name: router
+  replicasets_count: 3
+  roles:
+    - router
+"#,
+    );
+
+    assert!(host.port_conflicts().is_empty());
+    let ports = host.hosts[0]
+        .instances
+        .iter()
+        .map(|instance| (instance.config.http_port, instance.config.binary_port))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        ports,
+        vec![
+            (Some(9000), Some(4000)),
+            (Some(9001), Some(4001)),
+            (Some(9002), Some(4002))
+        ]
+    );
+}
+
+#[test]
+fn port_overflow() {
+    let host = spread(
+        r#"---
+- name: server-1
+  config:
+    http_port: 65534
+"#,
+        r#"---
+- name: router
+  replicasets_count: 3
+  roles:
+    - router
+"#,
+    );
+
+    assert_eq!(
+        host.port_conflicts()
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<String>>(),
+        vec!["server-1 ran out of free ports for router-3 http, every port from the base port up to 65535 is taken or reserved"]
+    );
+}
+
+#[test]
+fn cluster_with_port_conflicts() {
+    let config = std::fs::read_to_string("tests/resources/cluster.genin.yml").unwrap();
+    assert!(serde_yaml::from_str::<Cluster>(&config).is_ok());
+
+    let config = config.replacen(
+        "      binary_port: 3031\n",
+        "      binary_port: 3031\n      binary_port_range: 3031-3032\n",
+        1,
+    );
+    let err = serde_yaml::from_str::<Cluster>(&config).unwrap_err();
+    assert_eq!(err.to_string(), "2 port conflicts");
+}
+
+const ROLE_PORTS_TOPOLOGY: &str = r#"---
+- name: router
+  replicasets_count: 2
+  roles:
+    - router
+- name: storage
+  replicasets_count: 2
+  replication_factor: 2
+  roles:
+    - app.role.storage
+- name: api
+  replicasets_count: 1
+  roles:
+    - api
+  config:
+    http_port_base: 7081
+"#;
+
+#[test]
+fn ports_counted_per_role() {
+    let host = spread_with_role_ports(
+        "[name: server-1]",
+        ROLE_PORTS_TOPOLOGY,
+        r#"---
+router:
+  http_port: 8081
+  binary_port: 3301
+storage:
+  http_port: 9081
+  binary_port: 4301
+api:
+  http_port: 6081
+"#,
+    );
+
+    assert!(host.port_conflicts().is_empty());
+    assert_eq!(
+        host.hosts[0]
+            .instances
+            .iter()
+            .map(|instance| (
+                instance.name.to_string(),
+                instance.config.http_port,
+                instance.config.binary_port
+            ))
+            .collect::<Vec<_>>(),
+        vec![
+            ("router-1".into(), Some(8081), Some(3301)),
+            ("router-2".into(), Some(8082), Some(3302)),
+            ("storage-1-1".into(), Some(9081), Some(4301)),
+            ("storage-1-2".into(), Some(9082), Some(4302)),
+            ("storage-2-1".into(), Some(9083), Some(4303)),
+            ("storage-2-2".into(), Some(9084), Some(4304)),
+            // the base in the topology member wins over the role one
+            ("api-1".into(), Some(7081), Some(3031)),
+        ]
+    );
+}
+
+#[test]
+fn overlapping_port_pools() {
+    let host = spread_with_role_ports(
+        "[name: server-1]",
+        ROLE_PORTS_TOPOLOGY,
+        r#"---
+router:
+  http_port: 8081
+storage:
+  http_port: 8082
+"#,
+    );
+
+    assert_eq!(
+        host.port_conflicts()
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<String>>(),
+        vec!["server-1: http ports 8081-8082 overlap with http ports 8082-8086"]
+    );
+}
@@ -0,0 +1,207 @@
+use crate::task::cluster::host::hst::{Address, Host, HostConfig};
+use crate::task::cluster::name::Name;
+
+/// Numeric range inside a host name or address, `srv-[01:60]` stands for the
+/// names from `srv-01` to `srv-60`. Leading zeros of the start set the width
+/// of the numbers.
+/// ```yaml
+/// hosts:
+///   - name: srv-[01:60]
+///     config:
+///       address: 10.1.0.[11:70]
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Range {
+    prefix: String,
+    start: usize,
+    end: usize,
+    width: usize,
+    suffix: String,
+}
+
+impl Range {
+    /// Finds the range in the pattern, brackets that do not hold two numbers
+    /// separated by a colon are not a range.
+    pub fn parse(pattern: &str) -> Result<Option<Self>, String> {
+        let Some((prefix, start, end, suffix)) = split_range(pattern) else {
+            return Ok(None);
+        };
+        if split_range(suffix).is_some() {
+            return Err(format!("{pattern} has more than one range"));
+        }
+
+        let range = Self {
+            prefix: prefix.to_string(),
+            start: start
+                .parse()
+                .map_err(|_| format!("{pattern} has a range start out of bounds"))?,
+            end: end
+                .parse()
+                .map_err(|_| format!("{pattern} has a range end out of bounds"))?,
+            width: width(start),
+            suffix: suffix.to_string(),
+        };
+        if range.start > range.end {
+            return Err(format!("{pattern} has a range that ends before it starts"));
+        }
+
+        Ok(Some(range))
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start + 1
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = String> + '_ {
+        (self.start..=self.end).map(|number| {
+            format!(
+                "{}{:0width$}{}",
+                self.prefix,
+                number,
+                self.suffix,
+                width = self.width
+            )
+        })
+    }
+}
+
+fn split_range(pattern: &str) -> Option<(&str, &str, &str, &str)> {
+    pattern.match_indices('[').find_map(|(open, _)| {
+        let close = open + pattern[open..].find(']')?;
+        let (start, end) = pattern[open + 1..close].split_once(':')?;
+        let is_number = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+        (is_number(start) && is_number(end))
+            .then(|| (&pattern[..open], start, end, &pattern[close + 1..]))
+    })
+}
+
+/// Numbers starting with zero are padded to their length.
+fn width(digits: &str) -> usize {
+    if digits.len() > 1 && digits.starts_with('0') {
+        digits.len()
+    } else {
+        0
+    }
+}
+
+/// Addresses of the hosts generated from one entry of the config, the address
+/// is either a range with an address for every host or shared by all of them.
+pub fn expand_address(address: &Address, host: &str, count: usize) -> Result<Vec<Address>, String> {
+    let Address::Uri(uri) = address else {
+        return Ok(vec![address.clone(); count]);
+    };
+    match Range::parse(uri)? {
+        Some(range) if range.len() != count => Err(format!(
+            "address {uri} of {host} has {} addresses for {count} hosts",
+            range.len()
+        )),
+        Some(range) => Ok(range
+            .iter()
+            .map(|uri| Address::from(uri.as_str()))
+            .collect()),
+        None => Ok(vec![address.clone(); count]),
+    }
+}
+
+/// Collapses runs of at least three sibling hosts which differ only in the
+/// trailing number of the name and address back into ranges.
+pub fn collapse(hosts: &[Host]) -> Vec<Host> {
+    let mut runs: Vec<Vec<&Host>> = Vec::new();
+    hosts.iter().for_each(|host| match runs.last_mut() {
+        Some(run) if follows(run.last().unwrap(), host) => run.push(host),
+        _ => runs.push(vec![host]),
+    });
+
+    runs.into_iter()
+        .flat_map(|run| {
+            if run.len() < 3 {
+                return run
+                    .into_iter()
+                    .map(|host| Host {
+                        hosts: collapse(&host.hosts),
+                        ..host.clone()
+                    })
+                    .collect();
+            }
+            let (first, last) = (run[0], run[run.len() - 1]);
+            vec![Host {
+                name: Name::from(join(&first.name.to_string(), &last.name.to_string())),
+                config: HostConfig {
+                    address: join_address(&first.config.address, &last.config.address),
+                    ansible_host: join_address(
+                        &first.config.ansible_host,
+                        &last.config.ansible_host,
+                    ),
+                    ..first.config.clone()
+                },
+                ..Host::from("")
+            }]
+        })
+        .collect()
+}
+
+fn follows(prev: &Host, next: &Host) -> bool {
+    let without_addresses = |config: &HostConfig| HostConfig {
+        address: Address::None,
+        ansible_host: Address::None,
+        ..config.clone()
+    };
+    prev.hosts.is_empty()
+        && next.hosts.is_empty()
+        && without_addresses(&prev.config) == without_addresses(&next.config)
+        && follows_str(&prev.name.to_string(), &next.name.to_string())
+        && follows_address(&prev.config.address, &next.config.address)
+        && follows_address(&prev.config.ansible_host, &next.config.ansible_host)
+}
+
+fn follows_address(prev: &Address, next: &Address) -> bool {
+    match (prev, next) {
+        (Address::None, Address::None) => true,
+        (Address::Ip(_) | Address::Uri(_), Address::Ip(_) | Address::Uri(_)) => {
+            follows_str(&prev.to_string(), &next.to_string())
+        }
+        _ => false,
+    }
+}
+
+/// Splits the string at its last number.
+fn split_number(s: &str) -> Option<(&str, &str, &str)> {
+    let end = s.rfind(|c: char| c.is_ascii_digit())? + 1;
+    let start = s[..end]
+        .rfind(|c: char| !c.is_ascii_digit())
+        .map(|index| index + 1)
+        .unwrap_or_default();
+    Some((&s[..start], &s[start..end], &s[end..]))
+}
+
+fn follows_str(prev: &str, next: &str) -> bool {
+    match (split_number(prev), split_number(next)) {
+        (Some((prev_prefix, prev_digits, prev_suffix)), Some((prefix, digits, suffix))) => {
+            prev_prefix == prefix
+                && prev_suffix == suffix
+                && prev_digits.parse::<usize>().is_ok_and(|number| {
+                    format!("{:0width$}", number + 1, width = width(prev_digits)) == digits
+                })
+        }
+        _ => false,
+    }
+}
+
+fn join(first: &str, last: &str) -> String {
+    match (split_number(first), split_number(last)) {
+        (Some((prefix, start, suffix)), Some((_, end, _))) => {
+            format!("{prefix}[{start}:{end}]{suffix}")
+        }
+        _ => first.to_string(),
+    }
+}
+
+fn join_address(first: &Address, last: &Address) -> Address {
+    match first {
+        Address::None => Address::None,
+        _ => Address::Uri(join(&first.to_string(), &last.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,144 @@
+use crate::task::cluster::{
+    host::{
+        hst::{Address, Host, HostConfig, WithHosts},
+        range::{collapse, Range},
+    },
+    Cluster,
+};
+
+const CLUSTER: &str = r#"---
+topology:
+  - name: storage
+    replicasets_count: 3
+    replication_factor: 2
+    roles:
+      - storage
+hosts:
+  - name: dc-1
+    hosts:
+      - name: srv-[08:10]
+        config:
+          address: 10.1.0.[11:13]
+  - name: dc-2
+    hosts:
+      - name: cache-{}
+        count: 2
+        config:
+          address: 10.2.0.1
+      - name: db
+        count: 2
+failover:
+  mode: disabled
+vars:
+  ansible_user: ansible
+  ansible_password: ansible
+  cartridge_app_name: myapp
+  cartridge_cluster_cookie: myapp-cookie
+"#;
+
+fn addresses(host: &Host) -> Vec<(String, Address)> {
+    host.lower_level_hosts()
+        .into_iter()
+        .map(|host| (host.name.to_string(), host.config.address.clone()))
+        .collect()
+}
+
+#[test]
+fn hosts_generated_from_ranges() {
+    let cluster: Cluster = serde_yaml::from_str(CLUSTER).unwrap();
+
+    assert_eq!(
+        addresses(&cluster.hosts),
+        vec![
+            ("srv-08".into(), Address::from([10, 1, 0, 11])),
+            ("srv-09".into(), Address::from([10, 1, 0, 12])),
+            ("srv-10".into(), Address::from([10, 1, 0, 13])),
+            ("cache-1".into(), Address::from([10, 2, 0, 1])),
+            ("cache-2".into(), Address::from([10, 2, 0, 1])),
+            ("db-1".into(), Address::None),
+            ("db-2".into(), Address::None),
+        ]
+    );
+    // generated hosts take instances as any other host
+    assert_eq!(
+        cluster
+            .hosts
+            .lower_level_hosts()
+            .iter()
+            .map(|host| host.instances.len())
+            .sum::<usize>(),
+        6
+    );
+}
+
+#[test]
+fn host_range_errors() {
+    let error = |from: &str, to: &str| {
+        serde_yaml::from_str::<Cluster>(&CLUSTER.replace(from, to))
+            .unwrap_err()
+            .to_string()
+    };
+
+    assert_eq!(
+        error("10.1.0.[11:13]", "10.1.0.[11:14]"),
+        "address 10.1.0.[11:14] of srv-[08:10] has 4 addresses for 3 hosts"
+    );
+    assert_eq!(
+        error("name: db", "name: db-[1:2]"),
+        "db-[1:2] has both a name range and a count"
+    );
+    assert_eq!(
+        error("count: 2\n        config", "count: 0\n        config"),
+        "count of cache-{} must be positive"
+    );
+    assert_eq!(
+        error("srv-[08:10]", "srv-[10:08]"),
+        "srv-[10:08] has a range that ends before it starts"
+    );
+    assert_eq!(
+        error("srv-[08:10]", "srv-[1:3]-[1:3]"),
+        "srv-[1:3]-[1:3] has more than one range"
+    );
+}
+
+#[test]
+fn range_parse() {
+    let range = Range::parse("srv-[098:101].local").unwrap().unwrap();
+    assert_eq!(range.len(), 4);
+    assert_eq!(
+        range.iter().collect::<Vec<_>>(),
+        vec![
+            "srv-098.local",
+            "srv-099.local",
+            "srv-100.local",
+            "srv-101.local"
+        ]
+    );
+    // brackets without numbers are left as they are
+    assert_eq!(Range::parse("[::1]:3301").unwrap(), None);
+}
+
+#[test]
+fn hosts_collapsed_to_ranges() {
+    let server = |name: &str, address: [u8; 4]| {
+        Host::from(name).with_config(HostConfig {
+            address: Address::from(address),
+            ansible_host: Address::from(address),
+            ..HostConfig::from((8081, 3031))
+        })
+    };
+    let hosts = vec![
+        server("srv-08", [10, 1, 0, 11]),
+        server("srv-09", [10, 1, 0, 12]),
+        server("srv-10", [10, 1, 0, 13]),
+        server("srv-11", [10, 1, 0, 15]),
+        server("srv-12", [10, 1, 0, 16]),
+        Host::from("dc-2").with_hosts(vec![
+            server("db-1", [10, 2, 0, 1]),
+            server("db-2", [10, 2, 0, 2]),
+            server("db-3", [10, 2, 0, 3]),
+        ]),
+    ];
+
+    insta::assert_yaml_snapshot!(collapse(&hosts));
+}
@@ -0,0 +1,112 @@
+use std::fmt::Display;
+
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+
+/// Selects hosts by their labels. Every `match_labels` pair and every
+/// expression has to match.
+/// ```yaml
+/// placement:
+///   match_labels:
+///     ssd: true
+///   match_expressions:
+///     - key: rack
+///       operator: In
+///       values: [r12, r13]
+///     - key: maintenance
+///       operator: DoesNotExist
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Selector {
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub match_labels: IndexMap<String, Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub match_expressions: Vec<Expression>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Expression {
+    pub key: String,
+    pub operator: Operator,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub values: Vec<Value>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operator {
+    In,
+    NotIn,
+    Exists,
+    DoesNotExist,
+}
+
+impl Selector {
+    pub fn matches(&self, labels: &IndexMap<String, Value>) -> bool {
+        self.match_labels
+            .iter()
+            .all(|(key, value)| labels.get(key) == Some(value))
+            && self
+                .match_expressions
+                .iter()
+                .all(|expression| expression.matches(labels))
+    }
+
+    /// Checks that set-based operators come with values and the others
+    /// come without them.
+    pub fn validate(&self) -> Result<(), String> {
+        self.match_expressions
+            .iter()
+            .try_for_each(|expression| match expression.operator {
+                Operator::In | Operator::NotIn if expression.values.is_empty() => Err(format!(
+                    "{:?} expression for label {} needs values",
+                    expression.operator, expression.key
+                )),
+                Operator::Exists | Operator::DoesNotExist if !expression.values.is_empty() => {
+                    Err(format!(
+                        "{:?} expression for label {} takes no values",
+                        expression.operator, expression.key
+                    ))
+                }
+                _ => Ok(()),
+            })
+    }
+}
+
+impl Expression {
+    fn matches(&self, labels: &IndexMap<String, Value>) -> bool {
+        let value = labels.get(&self.key);
+        match self.operator {
+            Operator::In => matches!(value, Some(value) if self.values.contains(value)),
+            Operator::NotIn => !matches!(value, Some(value) if self.values.contains(value)),
+            Operator::Exists => value.is_some(),
+            Operator::DoesNotExist => value.is_none(),
+        }
+    }
+}
+
+impl Display for Selector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let labels = self.match_labels.iter().map(|(key, value)| {
+            format!(
+                "{key}={}",
+                serde_yaml::to_string(value)
+                    .unwrap_or_default()
+                    .trim_start_matches("---")
+                    .trim()
+            )
+        });
+        let expressions = self
+            .match_expressions
+            .iter()
+            .map(|expression| format!("{} {:?}", expression.key, expression.operator));
+        write!(
+            f,
+            "{}",
+            labels.chain(expressions).collect::<Vec<_>>().join(", ")
+        )
+    }
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,150 @@
+use indexmap::IndexMap;
+use serde_yaml::Value;
+
+use crate::task::cluster::host::selector::Selector;
+use crate::task::cluster::Cluster;
+
+fn labels(yaml: &str) -> IndexMap<String, Value> {
+    serde_yaml::from_str(yaml).unwrap()
+}
+
+#[test]
+fn selector_matches_labels() {
+    let selector: Selector = serde_yaml::from_str(
+        r#"---
+match_labels:
+  ssd: true
+match_expressions:
+  - key: rack
+    operator: In
+    values: [r12, r13]
+  - key: maintenance
+    operator: DoesNotExist
+"#,
+    )
+    .unwrap();
+
+    assert!(selector.validate().is_ok());
+    assert!(selector.matches(&labels("{ssd: true, rack: r12}")));
+    assert!(!selector.matches(&labels("{ssd: false, rack: r12}")));
+    assert!(!selector.matches(&labels("{ssd: true, rack: r14}")));
+    assert!(!selector.matches(&labels("{ssd: true, rack: r13, maintenance: true}")));
+    assert!(!selector.matches(&labels("{}")));
+    assert_eq!(
+        selector.to_string(),
+        "ssd=true, rack In, maintenance DoesNotExist"
+    );
+
+    let selector: Selector = serde_yaml::from_str(
+        r#"---
+match_expressions:
+  - key: env
+    operator: NotIn
+    values: [dev]
+"#,
+    )
+    .unwrap();
+    assert!(selector.matches(&labels("{}")));
+    assert!(selector.matches(&labels("{env: prod}")));
+    assert!(!selector.matches(&labels("{env: dev}")));
+
+    let selector: Selector = serde_yaml::from_str(
+        r#"---
+match_expressions:
+  - key: env
+    operator: In
+"#,
+    )
+    .unwrap();
+    assert_eq!(
+        selector.validate(),
+        Err("In expression for label env needs values".into())
+    );
+}
+
+const CLUSTER: &str = r#"---
+topology:
+  - name: router
+    replicasets_count: 1
+    roles:
+      - router
+  - name: storage
+    replicasets_count: 2
+    replication_factor: 2
+    placement:
+      match_labels:
+        ssd: true
+    roles:
+      - storage
+hosts:
+  - name: dc-1
+    config:
+      labels:
+        ssd: true
+    hosts:
+      - name: server-1
+        config:
+          address: 192.168.16.11
+      - name: server-2
+        config:
+          address: 192.168.16.12
+          labels:
+            ssd: false
+  - name: dc-2
+    hosts:
+      - name: server-3
+        config:
+          address: 192.168.16.13
+failover:
+  mode: disabled
+vars:
+  ansible_user: ansible
+  ansible_password: ansible
+  cartridge_app_name: myapp
+  cartridge_cluster_cookie: myapp-cookie
+"#;
+
+fn storages_by_host(cluster: &Cluster) -> Vec<(String, String)> {
+    cluster
+        .hosts
+        .lower_level_hosts()
+        .into_iter()
+        .flat_map(|host| {
+            host.instances
+                .iter()
+                .filter(|instance| instance.name.to_string().starts_with("storage"))
+                .map(|instance| (instance.name.to_string(), host.name.to_string()))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+#[test]
+fn placement_by_labels() {
+    // server-2 overrides the label inherited from dc-1
+    let cluster: Cluster = serde_yaml::from_str(CLUSTER).unwrap();
+    assert!(storages_by_host(&cluster)
+        .iter()
+        .all(|(_, host)| host == "server-1"));
+
+    // a new labeled server is picked up without touching the topology
+    let cluster: Cluster = serde_yaml::from_str(&CLUSTER.replace(
+        "          address: 192.168.16.13\n",
+        "          address: 192.168.16.13\n          labels:\n            ssd: true\n",
+    ))
+    .unwrap();
+    let hosts = storages_by_host(&cluster)
+        .into_iter()
+        .map(|(_, host)| host)
+        .collect::<std::collections::HashSet<_>>();
+    assert_eq!(hosts.len(), 2);
+    assert!(hosts.contains("server-1") && hosts.contains("server-3"));
+
+    let err = serde_yaml::from_str::<Cluster>(
+        &CLUSTER.replace("ssd: true\n    roles", "nvme: true\n    roles"),
+    )
+    .unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("placement [nvme=true] of storage matches no host"));
+}
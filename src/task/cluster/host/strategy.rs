@@ -0,0 +1,306 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fmt::Display;
+
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+
+use crate::task::cluster::host::hst::{Host, HostConfig};
+use crate::task::cluster::instance::ins::Instance;
+
+/// Load of a child host as seen by the spreading strategies.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ChildLoad {
+    /// Instances on the child, including the ones it had before the spreading
+    pub load: usize,
+    /// Instances pushed to the child by the spreading so far
+    pub pushed: usize,
+    pub weight: usize,
+    /// Instances the child takes before the `pack` strategy moves on to the
+    /// next child, see `Host::capacity`
+    pub capacity: Option<usize>,
+}
+
+/// Decides which child host of the host being spread gets the next instance.
+/// The child with the lowest priority is tried first and ties are broken by
+/// name. `depth` is the depth of the host being spread, the top level hosts
+/// are chosen at depth 0.
+pub trait SpreadStrategy {
+    fn priority(&self, child: &ChildLoad, depth: usize) -> u64;
+
+    /// How the chosen child is described by `genin explain`.
+    fn describe(&self, depth: usize) -> &'static str;
+}
+
+/// Fills the least loaded host first.
+pub struct Balanced;
+
+/// Fills the hosts in order, every instance goes to the first host that can
+/// take it and is not full. Hosts are full once they run as many instances as
+/// their `weight`, hosts without a weight are never full. When every host is
+/// full the rest is spread by weight. Meant for development clusters.
+pub struct Pack;
+
+/// Interleaves the top level hosts strictly, one instance after another,
+/// regardless of what they already run. Nested hosts are balanced.
+pub struct RoundRobinByDomain;
+
+/// Fills the host with the least load for its `weight`.
+pub struct Weighted;
+
+impl SpreadStrategy for Balanced {
+    fn priority(&self, child: &ChildLoad, _: usize) -> u64 {
+        child.load as u64
+    }
+
+    fn describe(&self, _: usize) -> &'static str {
+        "least loaded host"
+    }
+}
+
+impl SpreadStrategy for Pack {
+    fn priority(&self, child: &ChildLoad, depth: usize) -> u64 {
+        match child.capacity {
+            Some(capacity) if child.load >= capacity => 1 + Weighted.priority(child, depth),
+            _ => 0,
+        }
+    }
+
+    fn describe(&self, _: usize) -> &'static str {
+        "first host in order that is not full"
+    }
+}
+
+impl SpreadStrategy for RoundRobinByDomain {
+    fn priority(&self, child: &ChildLoad, depth: usize) -> u64 {
+        if depth == 0 {
+            child.pushed as u64
+        } else {
+            Balanced.priority(child, depth)
+        }
+    }
+
+    fn describe(&self, depth: usize) -> &'static str {
+        if depth == 0 {
+            "next host in turn"
+        } else {
+            Balanced.describe(depth)
+        }
+    }
+}
+
+impl SpreadStrategy for Weighted {
+    fn priority(&self, child: &ChildLoad, _: usize) -> u64 {
+        ((child.load as u64 + 1) << 32) / child.weight.max(1) as u64
+    }
+
+    fn describe(&self, _: usize) -> &'static str {
+        "least loaded host for its weight"
+    }
+}
+
+/// Spreading strategy as written in the cluster config and the state.
+/// ```yaml
+/// spread_strategy: round_robin_by_domain
+/// topology:
+///   - name: api
+///     spread_strategy: pack
+/// ```
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Strategy {
+    #[default]
+    Balanced,
+    Pack,
+    RoundRobinByDomain,
+    Weighted,
+}
+
+impl Strategy {
+    pub fn is_balanced(&self) -> bool {
+        *self == Self::Balanced
+    }
+
+    pub fn spreader(&self) -> &'static dyn SpreadStrategy {
+        match self {
+            Self::Balanced => &Balanced,
+            Self::Pack => &Pack,
+            Self::RoundRobinByDomain => &RoundRobinByDomain,
+            Self::Weighted => &Weighted,
+        }
+    }
+}
+
+impl Display for Strategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Balanced => write!(f, "balanced"),
+            Self::Pack => write!(f, "pack"),
+            Self::RoundRobinByDomain => write!(f, "round_robin_by_domain"),
+            Self::Weighted => write!(f, "weighted"),
+        }
+    }
+}
+
+/// Queue entry, the child with the lowest priority and then the lowest index
+/// comes first. Entries made before the child got another instance are stale.
+type Entry = Reverse<(u64, usize, usize)>;
+
+/// Children of a host ordered by every strategy in use. Children are referred
+/// to by their index, so the children have to be sorted by name while the
+/// loads are in use. The loads also count the instances of every topology
+/// member under each child and remember the role restrictions of the children,
+/// so that placing an instance does not walk the subtrees of the children.
+#[derive(Debug, Default)]
+pub struct Loads {
+    depth: usize,
+    children: Vec<ChildLoad>,
+    queues: IndexMap<Strategy, BinaryHeap<Entry>>,
+    members: Vec<HashMap<String, usize>>,
+    restrictions: Vec<HashMap<String, Option<String>>>,
+}
+
+impl Loads {
+    pub fn new(hosts: &[Host], depth: usize) -> Self {
+        Self {
+            depth,
+            children: hosts
+                .iter()
+                .map(|host| ChildLoad {
+                    load: host.instances.len(),
+                    pushed: 0,
+                    weight: host.weight(),
+                    capacity: host.capacity(),
+                })
+                .collect(),
+            queues: IndexMap::new(),
+            members: hosts
+                .iter()
+                .map(|host| {
+                    let mut members = HashMap::new();
+                    count_members(host, &mut members);
+                    members
+                })
+                .collect(),
+            restrictions: vec![HashMap::new(); hosts.len()],
+        }
+    }
+
+    pub fn load(&self, index: usize) -> usize {
+        self.children[index].load
+    }
+
+    pub fn describe(&self, strategy: Strategy) -> &'static str {
+        strategy.spreader().describe(self.depth)
+    }
+
+    fn entry(&self, strategy: Strategy, index: usize) -> Entry {
+        let child = &self.children[index];
+        Reverse((
+            strategy.spreader().priority(child, self.depth),
+            index,
+            child.pushed,
+        ))
+    }
+
+    fn queue(&mut self, strategy: Strategy) -> &mut BinaryHeap<Entry> {
+        if !self.queues.contains_key(&strategy) {
+            let queue = (0..self.children.len())
+                .map(|index| self.entry(strategy, index))
+                .collect();
+            self.queues.insert(strategy, queue);
+        }
+        self.queues.get_mut(&strategy).unwrap()
+    }
+
+    fn drop_stale(&mut self, strategy: Strategy) {
+        self.queue(strategy);
+        let queue = self.queues.get_mut(&strategy).unwrap();
+        while matches!(queue.peek(), Some(Reverse((_, index, pushed))) if self.children[*index].pushed != *pushed)
+        {
+            queue.pop();
+        }
+    }
+
+    /// Takes the next child out of the queue of the strategy.
+    pub fn pop(&mut self, strategy: Strategy) -> Option<usize> {
+        self.drop_stale(strategy);
+        self.queue(strategy)
+            .pop()
+            .map(|Reverse((_, index, _))| index)
+    }
+
+    pub fn peek(&mut self, strategy: Strategy) -> Option<usize> {
+        self.drop_stale(strategy);
+        self.queue(strategy)
+            .peek()
+            .map(|Reverse((_, index, _))| *index)
+    }
+
+    /// Puts a child taken with `pop` back into the queue of the strategy.
+    pub fn restore(&mut self, strategy: Strategy, index: usize) {
+        let entry = self.entry(strategy, index);
+        self.queue(strategy).push(entry);
+    }
+
+    /// Whether the child or any of its nested hosts has an instance of the
+    /// topology member.
+    pub fn contains_member(&self, index: usize, member: &str) -> bool {
+        self.members[index].contains_key(member)
+    }
+
+    /// Reason why none of the leaf hosts under the child takes the instance
+    /// because of its roles, `parent` is the config of the host being spread.
+    /// Instances of a topology member share their roles, so the restriction
+    /// is looked up once per member and child.
+    pub fn role_restriction(
+        &mut self,
+        child: &Host,
+        index: usize,
+        instance: &Instance,
+        parent: &HostConfig,
+    ) -> Option<String> {
+        if instance.is_stateboard() {
+            return None;
+        }
+        let member = instance.name.get_ancestor_str();
+        if let Some(restriction) = self.restrictions[index].get(member) {
+            return restriction.clone();
+        }
+        let restriction =
+            child.role_restriction(instance, &parent.allowed_roles, &parent.denied_roles);
+        self.restrictions[index].insert(member.to_string(), restriction.clone());
+        restriction
+    }
+
+    /// Counts another instance of the topology member into the load of the
+    /// child.
+    pub fn add(&mut self, index: usize, member: &str) {
+        self.children[index].load += 1;
+        self.children[index].pushed += 1;
+        if let Some(count) = self.members[index].get_mut(member) {
+            *count += 1;
+        } else {
+            self.members[index].insert(member.to_string(), 1);
+        }
+        let strategies = self.queues.keys().copied().collect::<Vec<_>>();
+        strategies.into_iter().for_each(|strategy| {
+            let entry = self.entry(strategy, index);
+            self.queue(strategy).push(entry);
+        });
+    }
+}
+
+fn count_members(host: &Host, members: &mut HashMap<String, usize>) {
+    host.instances.iter().for_each(|instance| {
+        *members
+            .entry(instance.name.get_ancestor_str().to_string())
+            .or_default() += 1;
+    });
+    host.hosts
+        .iter()
+        .for_each(|host| count_members(host, members));
+}
+
+#[cfg(test)]
+mod test;
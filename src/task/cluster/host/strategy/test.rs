@@ -0,0 +1,166 @@
+use crate::task::cluster::{
+    host::{
+        hst::{Host, WithHosts},
+        strategy::{Loads, Strategy},
+    },
+    instance::ins::{Instance, Instances},
+    name::Name,
+    Cluster,
+};
+
+const CLUSTER: &str = r#"---
+topology:
+  - name: router
+    replicasets_count: 2
+    spread_strategy: pack
+    roles:
+      - router
+  - name: storage
+    replicasets_count: 4
+    replication_factor: 2
+    roles:
+      - storage
+hosts:
+  - name: dc-1
+    config:
+      weight: 3
+    hosts:
+      - name: server-1
+        config:
+          address: 192.168.16.11
+      - name: server-2
+        config:
+          address: 192.168.16.12
+  - name: dc-2
+    hosts:
+      - name: server-3
+        config:
+          address: 192.168.16.13
+failover:
+  mode: disabled
+vars:
+  ansible_user: ansible
+  ansible_password: ansible
+  cartridge_app_name: myapp
+  cartridge_cluster_cookie: myapp-cookie
+"#;
+
+fn placed(cluster: &Cluster) -> Vec<(String, Vec<String>)> {
+    cluster
+        .hosts
+        .lower_level_hosts()
+        .into_iter()
+        .map(|host| {
+            (
+                host.name.to_string(),
+                host.instances
+                    .iter()
+                    .map(|instance| instance.name.to_string())
+                    .collect(),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn strategies_per_topology_member() {
+    let cluster: Cluster = serde_yaml::from_str(CLUSTER).unwrap();
+    insta::assert_debug_snapshot!(placed(&cluster));
+
+    let (_, router) = cluster.hosts.find_instance("router-2").unwrap();
+    assert_eq!(router.spread_strategy, Strategy::Pack);
+    assert!(serde_yaml::to_string(router)
+        .unwrap()
+        .contains("spread_strategy: pack"));
+    assert!(cluster
+        .explain("router-2")
+        .unwrap()
+        .to_string()
+        .contains("server-1 chosen as first host in order"));
+}
+
+#[test]
+fn pack_fills_hosts_up_to_their_weight() {
+    let config = CLUSTER
+        .replace("replicasets_count: 2", "replicasets_count: 5")
+        .replace(
+            "  - name: storage\n    replicasets_count: 4\n    replication_factor: 2\n    roles:\n      - storage\n",
+            "",
+        )
+        .replace(
+            "address: 192.168.16.11",
+            "address: 192.168.16.11\n          weight: 2",
+        );
+    let cluster: Cluster = serde_yaml::from_str(&config).unwrap();
+
+    // dc-1 takes 3 routers, 2 of them on server-1, and dc-2 without a weight
+    // takes the rest
+    assert_eq!(
+        placed(&cluster),
+        vec![
+            (
+                "server-1".to_string(),
+                vec!["router-1".to_string(), "router-2".to_string()]
+            ),
+            ("server-2".to_string(), vec!["router-3".to_string()]),
+            (
+                "server-3".to_string(),
+                vec!["router-4".to_string(), "router-5".to_string()]
+            ),
+        ]
+    );
+    assert!(cluster
+        .explain("router-4")
+        .unwrap()
+        .to_string()
+        .contains("dc-2 chosen as first host in order that is not full"));
+}
+
+#[test]
+fn weighted_strategy() {
+    let config = CLUSTER.replace("failover:", "spread_strategy: weighted\nfailover:");
+    let cluster: Cluster = serde_yaml::from_str(&config).unwrap();
+
+    // routers keep their own strategy, dc-1 weighs three times as much as
+    // dc-2 and takes most of the storages
+    insta::assert_debug_snapshot!(placed(&cluster));
+}
+
+#[test]
+fn round_robin_ignores_previous_load() {
+    let hosts = vec![
+        Host::from("dc-1").with_instances(Instances::from(vec![
+            Instance::from(Name::from("api").with_index(1)),
+            Instance::from(Name::from("api").with_index(2)),
+        ])),
+        Host::from("dc-2"),
+    ];
+    let host = Host::from("cluster").with_hosts(hosts);
+
+    let mut loads = Loads::new(&host.hosts, 0);
+    assert_eq!(loads.pop(Strategy::Balanced), Some(1));
+    assert_eq!(loads.pop(Strategy::RoundRobinByDomain), Some(0));
+    loads.add(0, "api");
+    assert_eq!(loads.pop(Strategy::RoundRobinByDomain), Some(1));
+    assert_eq!(loads.pop(Strategy::Pack), Some(0));
+}
+
+#[test]
+fn loads_count_members_of_nested_hosts() {
+    let hosts = vec![
+        Host::from("dc-1").with_hosts(vec![Host::from("server-1").with_instances(
+            Instances::from(vec![Instance::from(Name::from("api").with_index(1))]),
+        )]),
+        Host::from("dc-2"),
+    ];
+    let host = Host::from("cluster").with_hosts(hosts);
+
+    let mut loads = Loads::new(&host.hosts, 0);
+    assert!(loads.contains_member(0, "api"));
+    assert!(!loads.contains_member(1, "api"));
+    assert!(!loads.contains_member(0, "cache"));
+
+    loads.add(1, "cache");
+    assert!(loads.contains_member(1, "cache"));
+    assert!(!loads.contains_member(1, "api"));
+}
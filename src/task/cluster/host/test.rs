@@ -6,13 +6,16 @@ use tabled::Alignment;
 use crate::task::{
     cluster::{
         host::{
+            affinity::Affinity,
             hst::{Address, Host, HostConfig, WithHosts},
+            placement::Placement,
+            strategy::Strategy,
             view::{View, FG_BLUE, FG_WHITE},
         },
         instance::ins::{FailureDomains, Instance, InstanceConfig, Instances},
         name::Name,
         topology::Topology,
-        HostHelper,
+        Cluster, HostHelper,
     },
     utils::uncolorize,
 };
@@ -37,7 +40,8 @@ hosts:
 
     let host: Host = serde_yaml::from_str::<HostHelper>(&hosts_v2_str)
         .unwrap()
-        .into();
+        .try_into()
+        .unwrap();
 
     assert_eq!(host.depth(), 2);
 
@@ -61,7 +65,8 @@ hosts:
 
     let host: Host = serde_yaml::from_str::<HostHelper>(&hosts_v2_str)
         .unwrap()
-        .into();
+        .try_into()
+        .unwrap();
 
     assert_eq!(host.depth(), 5);
 }
@@ -119,7 +124,8 @@ hosts:
 
     let host: Host = serde_yaml::from_str::<HostHelper>(&hosts_v2_str)
         .unwrap()
-        .into();
+        .try_into()
+        .unwrap();
 
     assert_eq!(host.width(), 10);
 }
@@ -154,7 +160,8 @@ hosts:
 "#
     .into();
 
-    let mut host: Host = Host::from(serde_yaml::from_str::<HostHelper>(&hosts_v2_str).unwrap())
+    let mut host: Host = Host::try_from(serde_yaml::from_str::<HostHelper>(&hosts_v2_str).unwrap())
+        .unwrap()
         .with_instances(Instances::from(vec![
             Instance {
                 name: Name::from("storage").with_index(1).with_index(1),
@@ -169,6 +176,8 @@ hosts:
                     color: FG_BLUE,
                     alignment: Alignment::left(),
                 },
+                spread_strategy: Strategy::default(),
+                placement: Placement::default(),
             },
             Instance {
                 name: Name::from("storage").with_index(1).with_index(2),
@@ -183,6 +192,8 @@ hosts:
                     color: FG_BLUE,
                     alignment: Alignment::left(),
                 },
+                spread_strategy: Strategy::default(),
+                placement: Placement::default(),
             },
             Instance {
                 name: Name::from("storage").with_index(1).with_index(3),
@@ -197,6 +208,8 @@ hosts:
                     color: FG_BLUE,
                     alignment: Alignment::left(),
                 },
+                spread_strategy: Strategy::default(),
+                placement: Placement::default(),
             },
             Instance {
                 name: Name::from("storage").with_index(1).with_index(4),
@@ -211,6 +224,8 @@ hosts:
                     color: FG_BLUE,
                     alignment: Alignment::left(),
                 },
+                spread_strategy: Strategy::default(),
+                placement: Placement::default(),
             },
             Instance {
                 name: Name::from("storage").with_index(1).with_index(5),
@@ -225,6 +240,8 @@ hosts:
                     color: FG_BLUE,
                     alignment: Alignment::left(),
                 },
+                spread_strategy: Strategy::default(),
+                placement: Placement::default(),
             },
             Instance {
                 name: Name::from("storage").with_index(1).with_index(6),
@@ -239,6 +256,8 @@ hosts:
                     color: FG_BLUE,
                     alignment: Alignment::left(),
                 },
+                spread_strategy: Strategy::default(),
+                placement: Placement::default(),
             },
             Instance {
                 name: Name::from("storage").with_index(1).with_index(7),
@@ -253,6 +272,8 @@ hosts:
                     color: FG_BLUE,
                     alignment: Alignment::left(),
                 },
+                spread_strategy: Strategy::default(),
+                placement: Placement::default(),
             },
             Instance {
                 name: Name::from("storage").with_index(1).with_index(8),
@@ -267,6 +288,8 @@ hosts:
                     color: FG_BLUE,
                     alignment: Alignment::left(),
                 },
+                spread_strategy: Strategy::default(),
+                placement: Placement::default(),
             },
             Instance {
                 name: Name::from("storage").with_index(1).with_index(9),
@@ -281,6 +304,8 @@ hosts:
                     color: FG_BLUE,
                     alignment: Alignment::left(),
                 },
+                spread_strategy: Strategy::default(),
+                placement: Placement::default(),
             },
             Instance {
                 name: Name::from("storage").with_index(1).with_index(10),
@@ -295,12 +320,14 @@ hosts:
                     color: FG_BLUE,
                     alignment: Alignment::left(),
                 },
+                spread_strategy: Strategy::default(),
+                placement: Placement::default(),
             },
         ]));
 
     assert_eq!(host.size(), 0);
 
-    host.spread();
+    host.spread(&Affinity::default()).unwrap();
 
     assert_eq!(host.size(), 10);
 }
@@ -337,7 +364,8 @@ hosts:
 
     let host: Host = serde_yaml::from_str::<HostHelper>(&hosts_v2_str)
         .unwrap()
-        .into();
+        .try_into()
+        .unwrap();
 
     host.lower_level_hosts()
         .iter()
@@ -413,7 +441,8 @@ hosts:
 
     let mut host: Host = serde_yaml::from_str::<HostHelper>(&hosts_v2_str)
         .unwrap()
-        .into();
+        .try_into()
+        .unwrap();
 
     fn new_instance(name: Name, failure_domains: FailureDomains) -> Instance {
         Instance {
@@ -429,6 +458,8 @@ hosts:
                 color: FG_WHITE,
                 alignment: Alignment::left(),
             },
+            spread_strategy: Strategy::default(),
+            placement: Placement::default(),
         }
     }
 
@@ -456,7 +487,7 @@ hosts:
         new_instance("cache-2-3".into(), vec!["dc-2".into()].into()),
     ]);
 
-    host.spread();
+    host.spread(&Affinity::default()).unwrap();
 
     host
 }
@@ -570,7 +601,7 @@ fn hosts_v2_spreading() {
         .with_config(HostConfig::from((8081, 3031)))
         .with_address(Address::from([192, 168, 123, 11]));
 
-    host.spread();
+    host.spread(&Affinity::default()).unwrap();
 
     println!("{}", &host);
 
@@ -619,7 +650,7 @@ fn hosts_v2_print_table() {
         .with_config(HostConfig::from((8081, 3031)))
         .with_address(Address::from([192, 168, 123, 11]));
 
-    host.spread();
+    host.spread(&Affinity::default()).unwrap();
 
     println!("{}", host);
 
@@ -676,9 +707,11 @@ fn hosts_v2_spread_stateboard() {
         config: InstanceConfig::default(),
         vars: IndexMap::default(),
         view: View::default(),
+        spread_strategy: Strategy::default(),
+        placement: Placement::default(),
     });
 
-    host.spread();
+    host.spread(&Affinity::default()).unwrap();
 
     assert_eq!(
         host.hosts
@@ -849,3 +882,108 @@ hosts:
 
     insta::assert_yaml_snapshot!(hosts_old);
 }
+
+#[test]
+fn hosts_restricted_to_roles() {
+    let cluster_str = r#"---
+topology:
+  - name: router
+    replicasets_count: 2
+    roles:
+      - router
+  - name: storage
+    replicasets_count: 2
+    replication_factor: 2
+    roles:
+      - storage
+hosts:
+  - name: dc-1
+    hosts:
+      - name: server-1
+        config:
+          address: 192.168.16.11
+      - name: server-2
+        config:
+          address: 192.168.16.12
+      - name: server-3
+        config:
+          address: 192.168.16.13
+          denied_roles: [storage]
+  - name: dc-2
+    config:
+      dedicated_to: [router]
+    hosts:
+      - name: server-4
+        config:
+          address: 192.168.16.14
+failover:
+  mode: disabled
+vars:
+  ansible_user: ansible
+  ansible_password: ansible
+  cartridge_app_name: myapp
+  cartridge_cluster_cookie: myapp-cookie
+"#;
+
+    let cluster = serde_yaml::from_str::<Cluster>(cluster_str).unwrap();
+    cluster
+        .hosts
+        .lower_level_hosts()
+        .into_iter()
+        .filter(|host| host.name.to_string() == "server-3" || host.name.to_string() == "server-4")
+        .for_each(|host| {
+            assert!(!host.contains_member("storage"));
+        });
+    let explanation = cluster.explain("storage-1-1").unwrap().to_string();
+    assert!(explanation.contains("dc-2  load 1, dedicated to router"));
+    assert!(explanation.contains("server-3  load 0, denies storage"));
+
+    let cluster_str = cluster_str
+        .replace(
+            "  - name: dc-1\n",
+            "  - name: dc-1\n    config:\n      allowed_roles: [storage]\n",
+        )
+        .replace("dedicated_to: [router]", "dedicated_to: [storage]");
+    let err = serde_yaml::from_str::<Cluster>(&cluster_str).unwrap_err();
+    assert!(err
+        .to_string()
+        .starts_with("SpreadingError placement rules of router-"));
+}
+
+#[test]
+fn hosts_spread_evenly() {
+    let topology: Topology = serde_yaml::from_str(
+        r#"---
+- name: storage
+  replicasets_count: 50
+  replication_factor: 2
+  roles:
+    - storage
+"#,
+    )
+    .unwrap();
+
+    let mut host = Host::from("cluster")
+        .with_hosts(
+            (1..=5)
+                .map(|dc| {
+                    Host::from(format!("dc-{dc}").as_str()).with_hosts(
+                        (1..=4)
+                            .map(|server| Host::from(format!("server-{dc}-{server}").as_str()))
+                            .collect(),
+                    )
+                })
+                .collect(),
+        )
+        .with_instances(Instances::from(&topology))
+        .with_config(HostConfig::from((8081, 3031)));
+
+    host.spread(&Affinity::default()).unwrap();
+
+    let loads = host
+        .lower_level_hosts()
+        .into_iter()
+        .map(|host| host.instances.len())
+        .collect::<Vec<usize>>();
+    assert_eq!(loads, vec![5; 20]);
+}
@@ -0,0 +1,95 @@
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use serde_yaml::Value;
+
+use crate::task::cluster::host::import::anchor_imports;
+use crate::task::cluster::topology::merge_values;
+use crate::task::cluster::ClusterError;
+
+/// Reads the cluster config together with the files it includes. Includes
+/// are merged in order, each one over the previous, and the including file
+/// wins over all of them. Relative paths start at the including file.
+/// ```yaml
+/// include:
+///   - hosts/prod.yml
+///   - vars/secrets.yml
+/// topology: ...
+/// ```
+/// Returns the merged config and the paths of all the files read, the config
+/// itself first. Hosts imports of every file start at that file as well, and
+/// the imported files are among the paths.
+pub fn read_config(path: &Path) -> Result<(Value, Vec<PathBuf>), ClusterError> {
+    let config: Value = serde_yaml::from_reader(File::open(path)?)?;
+    let mut paths = vec![path.to_path_buf()];
+    let config = with_includes(config, path, &mut vec![path.canonicalize()?], &mut paths)?;
+    Ok((config, paths))
+}
+
+/// `chain` holds the canonical paths of the files being included, so a
+/// cycle is found however the path to a file is spelled.
+fn with_includes(
+    mut config: Value,
+    path: &Path,
+    chain: &mut Vec<PathBuf>,
+    paths: &mut Vec<PathBuf>,
+) -> Result<Value, ClusterError> {
+    paths.extend(anchor_imports(&mut config, path));
+
+    let includes = match config
+        .as_mapping_mut()
+        .and_then(|config| config.remove(&Value::from("include")))
+    {
+        None => return Ok(config),
+        Some(Value::String(include)) => vec![include],
+        Some(Value::Sequence(includes)) => includes
+            .into_iter()
+            .map(|include| match include {
+                Value::String(include) => Ok(include),
+                _ => Err(format!(
+                    "include of {} must be a list of paths",
+                    path.display()
+                )),
+            })
+            .collect::<Result<_, _>>()?,
+        Some(_) => {
+            return Err(format!("include of {} must be a list of paths", path.display()).into())
+        }
+    };
+
+    let base = includes
+        .into_iter()
+        .try_fold(Value::Null, |base, include| {
+            let include = path.parent().unwrap_or(Path::new("")).join(include);
+            let canonical = include
+                .canonicalize()
+                .map_err(|error| format!("failed to include {}: {error}", include.display()))?;
+            if chain.contains(&canonical) {
+                return Err(ClusterError::from(format!(
+                    "include cycle {} -> {}",
+                    chain
+                        .iter()
+                        .map(|path| path.display().to_string())
+                        .collect::<Vec<_>>()
+                        .join(" -> "),
+                    canonical.display()
+                )));
+            }
+
+            let included: Value = File::open(&include)
+                .map_err(|error| error.to_string())
+                .and_then(|file| serde_yaml::from_reader(file).map_err(|error| error.to_string()))
+                .map_err(|error| format!("failed to include {}: {error}", include.display()))?;
+            paths.push(include.clone());
+            chain.push(canonical);
+            let included = with_includes(included, &include, chain, paths)?;
+            chain.pop();
+
+            Ok(merge_values(included, base))
+        })?;
+
+    Ok(merge_values(config, base))
+}
+
+#[cfg(test)]
+mod test;
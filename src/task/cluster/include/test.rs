@@ -0,0 +1,213 @@
+use std::fs;
+use std::path::PathBuf;
+
+use serde_yaml::Value;
+
+use crate::task::cluster::{include::read_config, Cluster};
+
+const CLUSTER: &str = r#"---
+include:
+  - hosts/prod.yml
+  - vars/secrets.yml
+topology:
+  - name: storage
+    replicasets_count: 1
+    replication_factor: 2
+    roles:
+      - storage
+failover:
+  mode: disabled
+vars:
+  ansible_user: ansible
+  cartridge_app_name: myapp
+"#;
+
+const HOSTS: &str = r#"---
+hosts:
+  - name: server-1
+    config:
+      address: 192.168.16.11
+  - name: server-2
+    config:
+      address: 192.168.16.12
+vars:
+  ansible_user: root
+  ansible_password: hosts-password
+"#;
+
+const SECRETS: &str = r#"---
+vars:
+  ansible_password: ansible
+  cartridge_cluster_cookie: myapp-cookie
+"#;
+
+fn write_files(dir: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("genin-{dir}-{}", std::process::id()));
+    files.iter().for_each(|(path, text)| {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, text).unwrap();
+    });
+    dir
+}
+
+#[test]
+fn cluster_with_includes() {
+    let dir = write_files(
+        "include",
+        &[
+            ("cluster.genin.yml", CLUSTER),
+            ("hosts/prod.yml", HOSTS),
+            ("vars/secrets.yml", SECRETS),
+        ],
+    );
+    let cluster = Cluster::read(&dir.join("cluster.genin.yml"), None);
+    fs::remove_dir_all(&dir).unwrap();
+    let cluster = cluster.unwrap();
+
+    assert_eq!(
+        cluster.metadata.paths,
+        vec![
+            dir.join("cluster.genin.yml"),
+            dir.join("hosts/prod.yml"),
+            dir.join("vars/secrets.yml"),
+        ]
+    );
+    assert_eq!(cluster.hosts.lower_level_hosts().len(), 2);
+    // the including file wins over the includes, later includes win over
+    // earlier ones
+    assert_eq!(cluster.vars.ansible_user, Some("ansible".into()));
+    assert_eq!(cluster.vars.ansible_password, Some("ansible".into()));
+}
+
+#[test]
+fn include_errors() {
+    let dir = write_files(
+        "include-errors",
+        &[
+            ("a.yml", "include: b.yml\n"),
+            ("b.yml", "include: [a.yml]\n"),
+            ("c.yml", "include: missing.yml\n"),
+            ("d.yml", "include: {hosts: a.yml}\n"),
+            ("e/self.yml", "include: ../e/self.yml\n"),
+        ],
+    );
+    let error = |name: &str| read_config(&dir.join(name)).unwrap_err().to_string();
+    let errors = [
+        error("a.yml"),
+        error("c.yml"),
+        error("d.yml"),
+        error("e/self.yml"),
+    ];
+    let canonical = dir.canonicalize().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    let path = |name: &str| dir.join(name).display().to_string();
+    let canonical = |name: &str| canonical.join(name).display().to_string();
+    assert_eq!(
+        errors[0],
+        format!(
+            "other error include cycle {} -> {} -> {}",
+            canonical("a.yml"),
+            canonical("b.yml"),
+            canonical("a.yml")
+        )
+    );
+    // the same file spelled through its parent directory
+    assert_eq!(
+        errors[3],
+        format!(
+            "other error include cycle {} -> {}",
+            canonical("e/self.yml"),
+            canonical("e/self.yml")
+        )
+    );
+    assert!(
+        errors[1].starts_with(&format!(
+            "other error failed to include {}",
+            path("missing.yml")
+        )),
+        "{}",
+        errors[1]
+    );
+    assert_eq!(
+        errors[2],
+        format!(
+            "other error include of {} must be a list of paths",
+            path("d.yml")
+        )
+    );
+}
+
+#[test]
+fn include_without_includes() {
+    let dir = write_files("include-none", &[("cluster.yml", "vars:\n  a: 1\n")]);
+    let config = read_config(&dir.join("cluster.yml"));
+    fs::remove_dir_all(&dir).unwrap();
+
+    let (config, paths) = config.unwrap();
+    assert_eq!(paths, vec![dir.join("cluster.yml")]);
+    assert_eq!(config["vars"]["a"], Value::from(1));
+}
+
+#[test]
+fn imports_start_at_the_declaring_file() {
+    let dir = write_files(
+        "include-import",
+        &[
+            ("cluster.genin.yml", CLUSTER),
+            (
+                "hosts/prod.yml",
+                "hosts:\n  - name: dc-1\n    hosts:\n      - import: servers.csv\n",
+            ),
+            (
+                "hosts/servers.csv",
+                "name,address\nsrv-1,10.0.0.1\nsrv-2,10.0.0.2\n",
+            ),
+            ("vars/secrets.yml", SECRETS),
+        ],
+    );
+    let config = read_config(&dir.join("cluster.genin.yml"));
+    let (config, paths) = config.unwrap();
+    let cluster = serde_yaml::from_value::<Cluster>(config);
+    fs::remove_dir_all(&dir).unwrap();
+    let cluster = cluster.unwrap();
+
+    assert_eq!(
+        paths,
+        vec![
+            dir.join("cluster.genin.yml"),
+            dir.join("hosts/prod.yml"),
+            dir.join("hosts/servers.csv"),
+            dir.join("vars/secrets.yml"),
+        ]
+    );
+    assert_eq!(
+        cluster
+            .hosts
+            .lower_level_hosts()
+            .iter()
+            .map(|host| host.name.to_string())
+            .collect::<Vec<_>>(),
+        vec!["srv-1", "srv-2"]
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn include_cycle_through_symlink() {
+    let dir = write_files("include-symlink", &[("g.yml", "include: link.yml\n")]);
+    std::os::unix::fs::symlink(dir.join("g.yml"), dir.join("link.yml")).unwrap();
+    let error = read_config(&dir.join("g.yml")).unwrap_err().to_string();
+    let canonical = dir.canonicalize().unwrap().join("g.yml");
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(
+        error,
+        format!(
+            "other error include cycle {} -> {}",
+            canonical.display(),
+            canonical.display()
+        )
+    );
+}
@@ -10,6 +10,9 @@ use tabled::papergrid::AnsiColor;
 use crate::error::{GeninError, GeninErrorKind};
 use crate::task::cluster::host::hst::HostConfig;
 use crate::task::cluster::host::merge_index_maps;
+use crate::task::cluster::host::placement::Placement;
+use crate::task::cluster::host::ports::{HostPorts, PortBase};
+use crate::task::cluster::host::strategy::Strategy;
 use crate::task::cluster::host::view::View;
 use crate::task::cluster::instance::Role;
 use crate::task::cluster::name::Name;
@@ -55,6 +58,10 @@ impl Instances {
         self.0.reverse()
     }
 
+    pub fn sort_by_key<K: Ord>(&mut self, f: impl FnMut(&Instance) -> K) {
+        self.0.sort_by_key(f)
+    }
+
     #[allow(unused)]
     // used in tests
     pub fn pop(&mut self) -> Option<Instance> {
@@ -182,8 +189,13 @@ pub struct Instance {
     pub cartridge_extra_env: IndexMap<String, Value>,
     pub config: InstanceConfig,
     pub vars: IndexMap<String, Value>,
+    /// Strategy the instance was spread with, kept in the state
+    #[serde(default, skip_serializing_if = "Strategy::is_balanced")]
+    pub spread_strategy: Strategy,
     #[serde(skip)]
     pub view: View,
+    #[serde(skip)]
+    pub placement: Placement,
 }
 
 impl PartialOrd for Instance {
@@ -210,6 +222,8 @@ impl<'a> From<(&'a Name, &'a InventoryHost)> for Instance {
             config: InstanceConfig::from_inventory_host(inventory_host.1),
             vars: inventory_host.1.vars.clone(),
             view: View::default(),
+            spread_strategy: Strategy::default(),
+            placement: Placement::default(),
         }
     }
 }
@@ -226,6 +240,8 @@ impl From<Name> for Instance {
             config: InstanceConfig::default(),
             vars: IndexMap::default(),
             view: View::default(),
+            spread_strategy: Strategy::default(),
+            placement: Placement::default(),
         }
     }
 }
@@ -324,6 +340,12 @@ pub struct InstanceConfig {
     pub http_port: Option<u16>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub binary_port: Option<u16>,
+    /// Ports of the instance are taken from these bases instead of the base
+    /// ports of the host, so that each role gets its own pool.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_port_base: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub binary_port_base: Option<u16>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub all_rw: Option<bool>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -344,6 +366,8 @@ impl InstanceConfig {
             } => Self {
                 http_port: Some(*http_port),
                 binary_port: Some(advertise_uri.port),
+                http_port_base: None,
+                binary_port_base: None,
                 all_rw: None,
                 zone: host.zone.clone(),
                 vshard_group: None,
@@ -352,6 +376,8 @@ impl InstanceConfig {
             InvHostConfig::Stateboard(additional_config) => Self {
                 http_port: None,
                 binary_port: None,
+                http_port_base: None,
+                binary_port_base: None,
                 all_rw: None,
                 zone: None,
                 vshard_group: None,
@@ -370,6 +396,8 @@ impl<'a> From<&'a IndexMap<String, Value>> for InstanceConfig {
             binary_port: config.get("advertise_uri").map(|advertise_uri| {
                 serde_yaml::from_str(advertise_uri.as_str().unwrap()).unwrap()
             }),
+            http_port_base: None,
+            binary_port_base: None,
             all_rw: config.get("all_rw").map(|all_rw| all_rw.as_bool().unwrap()),
             zone: config
                 .get("zone")
@@ -390,15 +418,24 @@ impl<'a> From<&'a IndexMap<String, Value>> for InstanceConfig {
 
 #[allow(unused)]
 impl InstanceConfig {
-    pub fn merge_and_up_ports(self, other: HostConfig, index: u16) -> Self {
+    /// Merges the host config, taking free ports of the host for an
+    /// instance that has none yet.
+    pub fn merge_and_take_ports(self, other: HostConfig, ports: &mut HostPorts) -> Self {
         trace!("Config before merge: {:?}", &self);
         Self {
-            http_port: self
-                .http_port
-                .or_else(|| other.http_port.map(|port| port + index)),
-            binary_port: self
-                .binary_port
-                .or_else(|| other.binary_port.map(|port| port + index)),
+            // ports running past u16 are left empty and reported by the port check
+            http_port: self.http_port.or_else(|| {
+                self.http_port_base
+                    .or(other.http_port)
+                    .and_then(|port| ports.take(port))
+            }),
+            binary_port: self.binary_port.or_else(|| {
+                self.binary_port_base
+                    .or(other.binary_port)
+                    .and_then(|port| ports.take(port))
+            }),
+            http_port_base: self.http_port_base,
+            binary_port_base: self.binary_port_base,
             all_rw: self.all_rw,
             zone: self.zone,
             vshard_group: self.vshard_group,
@@ -410,6 +447,8 @@ impl InstanceConfig {
         Self {
             http_port: self.http_port.or(other.http_port),
             binary_port: self.binary_port.or(other.binary_port),
+            http_port_base: self.http_port_base,
+            binary_port_base: self.binary_port_base,
             all_rw: self.all_rw,
             zone: self.zone,
             vshard_group: self.vshard_group,
@@ -420,6 +459,8 @@ impl InstanceConfig {
     pub fn is_none(&self) -> bool {
         self.http_port.is_none()
             && self.binary_port.is_none()
+            && self.http_port_base.is_none()
+            && self.binary_port_base.is_none()
             && self.all_rw.is_none()
             && self.zone.is_none()
             && self.vshard_group.is_none()
@@ -434,7 +475,30 @@ impl InstanceConfig {
         }
     }
 
+    /// Fills the port bases that are not set yet.
+    pub fn with_port_base(self, base: &PortBase) -> Self {
+        Self {
+            http_port_base: self.http_port_base.or(base.http_port),
+            binary_port_base: self.binary_port_base.or(base.binary_port),
+            ..self
+        }
+    }
+
     pub fn with_all_rw(self, all_rw: Option<bool>) -> Self {
         Self { all_rw, ..self }
     }
+
+    /// Takes every setting that is not set yet from `defaults`.
+    pub fn with_defaults(self, defaults: InstanceConfig) -> Self {
+        Self {
+            http_port: self.http_port.or(defaults.http_port),
+            binary_port: self.binary_port.or(defaults.binary_port),
+            http_port_base: self.http_port_base.or(defaults.http_port_base),
+            binary_port_base: self.binary_port_base.or(defaults.binary_port_base),
+            all_rw: self.all_rw.or(defaults.all_rw),
+            zone: self.zone.or(defaults.zone),
+            vshard_group: self.vshard_group.or(defaults.vshard_group),
+            additional_config: merge_index_maps(self.additional_config, defaults.additional_config),
+        }
+    }
 }
@@ -1,8 +1,10 @@
 pub mod ins;
+pub mod overrides;
 
 use indexmap::IndexMap;
 use serde::{de::Visitor, Deserialize, Serialize};
 use serde_yaml::Value;
+use std::fmt::Display;
 
 use super::host::hst::Address;
 
@@ -89,6 +91,18 @@ impl Serialize for Role {
     }
 }
 
+impl Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::FailoverCoordinator(s)
+            | Self::Storage(s)
+            | Self::Router(s)
+            | Self::Api(s)
+            | Self::Custom(s) => write!(f, "{s}"),
+        }
+    }
+}
+
 impl<'a> From<&'a str> for Role {
     fn from(s: &'a str) -> Self {
         match s.to_lowercase().as_str() {
@@ -117,6 +131,18 @@ impl Role {
     pub fn api() -> Self {
         Self::Api("api".into())
     }
+
+    /// Whether the role is called `name`, well known roles also answer to
+    /// any of their aliases.
+    pub fn is_named(&self, name: &str) -> bool {
+        let other = RoleVisitor
+            .visit_str::<serde::de::value::Error>(name)
+            .expect("visiting a role name never fails");
+        match (self, &other) {
+            (Self::Custom(role), Self::Custom(other)) => role == other,
+            _ => std::mem::discriminant(self) == std::mem::discriminant(&other),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
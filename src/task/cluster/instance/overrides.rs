@@ -0,0 +1,143 @@
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+
+use crate::task::cluster::host::hst::Host;
+use crate::task::cluster::host::merge_index_maps;
+use crate::task::cluster::instance::ins::{FailureDomains, InstanceConfig};
+
+/// Settings of a single instance that take precedence over the settings of
+/// its topology member.
+/// ```yaml
+/// instances:
+///   storage-3-1:
+///     host: server-5
+///     zone: san-1
+///     config:
+///       memtx_dir: /mnt/san/storage-3-1
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct InstanceOverride {
+    /// Host the instance is pinned to, any level of the hosts tree will do
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zone: Option<String>,
+    #[serde(default, skip_serializing_if = "InstanceConfig::is_none")]
+    pub config: InstanceConfig,
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub vars: IndexMap<String, Value>,
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub cartridge_extra_env: IndexMap<String, Value>,
+}
+
+/// Overrides of the instances by instance name.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct InstanceOverrides(IndexMap<String, InstanceOverride>);
+
+impl InstanceOverrides {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Applies the overrides to the instances of the host before they are
+    /// spread. Pinned instances get the host as their only failure domain,
+    /// unless the failure domains of the topology member are already inside
+    /// of the host. Pinning an instance outside of the failure domains of its
+    /// topology member is an error.
+    pub fn apply(&self, hosts: &mut Host) -> Result<(), String> {
+        self.0.iter().try_for_each(|(name, instance_override)| {
+            if let Some(host) = &instance_override.host {
+                if !hosts.contains_host(host) {
+                    return Err(format!("{name} is pinned to unknown host {host}"));
+                }
+            }
+
+            let failure_domains = hosts
+                .instances
+                .iter()
+                .find(|instance| instance.name.to_string() == *name)
+                .ok_or_else(|| format!("instances contain unknown instance {name}"))?
+                .failure_domains
+                .clone();
+            let failure_domains = match &instance_override.host {
+                Some(host) => pinned_failure_domains(hosts, name, host, failure_domains)?,
+                None => failure_domains,
+            };
+
+            let instance = hosts
+                .instances
+                .iter_mut()
+                .find(|instance| instance.name.to_string() == *name)
+                .unwrap();
+            instance.failure_domains = failure_domains;
+            instance.config = instance_override
+                .config
+                .clone()
+                .with_defaults(instance.config.clone());
+            if let Some(zone) = &instance_override.zone {
+                instance.config.zone = Some(zone.clone());
+            }
+            instance.vars = merge_index_maps(
+                instance_override.vars.clone(),
+                std::mem::take(&mut instance.vars),
+            );
+            instance.cartridge_extra_env = merge_index_maps(
+                instance_override.cartridge_extra_env.clone(),
+                std::mem::take(&mut instance.cartridge_extra_env),
+            );
+            Ok(())
+        })
+    }
+
+    /// Names of the instances of the hosts tree that are placed outside of
+    /// the host they are pinned to.
+    pub fn misplaced(&self, hosts: &Host) -> Vec<String> {
+        self.0
+            .iter()
+            .filter_map(|(name, instance_override)| {
+                let host = instance_override.host.as_ref()?;
+                let (path, _) = hosts.find_instance(name)?;
+                (!path.contains(host)).then(|| name.clone())
+            })
+            .collect()
+    }
+}
+
+/// Failure domains of the instance pinned to the host. The host becomes the
+/// only failure domain when it is inside one of the failure domains of the
+/// topology member, the failure domains inside the host are kept as they are.
+fn pinned_failure_domains(
+    hosts: &Host,
+    name: &str,
+    host: &str,
+    failure_domains: FailureDomains,
+) -> Result<FailureDomains, String> {
+    let FailureDomains::InProgress(domains) = failure_domains else {
+        return Ok(FailureDomains::from(vec![host.to_string()]));
+    };
+    let contains = |outer: &str, inner: &str| {
+        hosts
+            .find_host(outer)
+            .map(|outer| outer.contains_host(inner))
+            .unwrap_or_default()
+    };
+    if domains.iter().any(|domain| contains(domain, host)) {
+        return Ok(FailureDomains::from(vec![host.to_string()]));
+    }
+    let inside = domains
+        .iter()
+        .filter(|domain| contains(host, domain))
+        .cloned()
+        .collect::<Vec<_>>();
+    if inside.is_empty() {
+        return Err(format!(
+            "{name} is pinned to host {host} outside of its failure domains [{}]",
+            domains.join(", ")
+        ));
+    }
+    Ok(FailureDomains::from(inside))
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,134 @@
+use serde_yaml::Value;
+
+use crate::task::cluster::Cluster;
+
+const CLUSTER: &str = r#"---
+topology:
+  - name: router
+    replicasets_count: 1
+    roles:
+      - router
+  - name: storage
+    replicasets_count: 3
+    replication_factor: 2
+    roles:
+      - storage
+    vars:
+      log_level: 5
+instances:
+  storage-3-1:
+    host: server-1
+    zone: san-1
+    config:
+      memtx_dir: /mnt/san/storage-3-1
+    vars:
+      log_level: 6
+    cartridge_extra_env:
+      SAN_MOUNT: /mnt/san
+hosts:
+  - name: dc-1
+    hosts:
+      - name: server-1
+        config:
+          address: 192.168.16.11
+      - name: server-2
+        config:
+          address: 192.168.16.12
+  - name: dc-2
+    hosts:
+      - name: server-3
+        config:
+          address: 192.168.16.13
+failover:
+  mode: disabled
+vars:
+  ansible_user: ansible
+  ansible_password: ansible
+  cartridge_app_name: myapp
+  cartridge_cluster_cookie: myapp-cookie
+"#;
+
+#[test]
+fn instance_overrides() {
+    let cluster: Cluster = serde_yaml::from_str(CLUSTER).unwrap();
+
+    let (path, instance) = cluster.hosts.find_instance("storage-3-1").unwrap();
+    assert_eq!(path, vec!["cluster", "dc-1", "server-1"]);
+    assert_eq!(instance.config.zone.as_deref(), Some("san-1"));
+    assert_eq!(
+        instance.config.additional_config.get("memtx_dir"),
+        Some(&Value::String("/mnt/san/storage-3-1".into()))
+    );
+    assert_eq!(instance.vars.get("log_level"), Some(&Value::from(6)));
+    assert!(instance.cartridge_extra_env.contains_key("SAN_MOUNT"));
+
+    // other instances of the replicaset keep the settings of the topology
+    let (_, replica) = cluster.hosts.find_instance("storage-3-2").unwrap();
+    assert_eq!(replica.config.zone, None);
+    assert_eq!(replica.vars.get("log_level"), Some(&Value::from(5)));
+
+    assert!(serde_yaml::to_string(&cluster)
+        .unwrap()
+        .contains("instances:\n  storage-3-1:\n    host: server-1\n"));
+}
+
+#[test]
+fn instance_overrides_unknown_names() {
+    let config = CLUSTER.replace("host: server-1", "host: server-9");
+    let err = serde_yaml::from_str::<Cluster>(&config).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "other error storage-3-1 is pinned to unknown host server-9"
+    );
+
+    let config = CLUSTER.replace("  storage-3-1:\n", "  storage-4-1:\n");
+    let err = serde_yaml::from_str::<Cluster>(&config).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "other error instances contain unknown instance storage-4-1"
+    );
+}
+
+#[test]
+fn instance_overrides_merge_moves_pinned_instance() {
+    let without_overrides = CLUSTER.replace("    host: server-1\n", "");
+    let mut old: Cluster = serde_yaml::from_str(&without_overrides).unwrap();
+    let (path, _) = old.hosts.find_instance("storage-3-1").unwrap();
+    assert_ne!(path.last().map(String::as_str), Some("server-1"));
+    let (replica_path, _) = old.hosts.find_instance("storage-3-2").unwrap();
+
+    let mut new: Cluster = serde_yaml::from_str(CLUSTER).unwrap();
+    old.merge(&mut new, false).unwrap();
+
+    let (path, instance) = old.hosts.find_instance("storage-3-1").unwrap();
+    assert_eq!(path, vec!["cluster", "dc-1", "server-1"]);
+    assert_eq!(instance.config.zone.as_deref(), Some("san-1"));
+    assert_eq!(instance.vars.get("log_level"), Some(&Value::from(6)));
+    assert_eq!(
+        old.hosts.collect_instances().iter().count(),
+        new.hosts.collect_instances().iter().count()
+    );
+
+    // the other instances keep their hosts
+    let (path, _) = old.hosts.find_instance("storage-3-2").unwrap();
+    assert_eq!(path, replica_path);
+}
+
+#[test]
+fn instance_overrides_failure_domains_conflict() {
+    let config = CLUSTER.replace(
+        "    vars:\n      log_level: 5\n",
+        "    vars:\n      log_level: 5\n    failure_domains: [dc-2]\n",
+    );
+    let err = serde_yaml::from_str::<Cluster>(&config).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "other error storage-3-1 is pinned to host server-1 outside of its failure domains [dc-2]"
+    );
+
+    // pinning inside of the failure domains narrows them down to the host
+    let config = config.replace("failure_domains: [dc-2]", "failure_domains: [dc-1]");
+    let cluster: Cluster = serde_yaml::from_str(&config).unwrap();
+    let (path, _) = cluster.hosts.find_instance("storage-3-1").unwrap();
+    assert_eq!(path, vec!["cluster", "dc-1", "server-1"]);
+}
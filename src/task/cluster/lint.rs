@@ -0,0 +1,497 @@
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::path::PathBuf;
+
+use clap::ArgMatches;
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+
+use crate::task::cluster::host::hst::{Address, Host};
+use crate::task::cluster::host::view::{FG_RED, FG_YELLOW};
+use crate::task::cluster::instance::ins::{Instance, Instances};
+use crate::task::cluster::instance::Role;
+use crate::task::cluster::name::Name;
+use crate::task::cluster::profile::read_with_profile;
+use crate::task::cluster::topology::Topology;
+use crate::task::cluster::{resolve_config, ClusterError, HostHelper};
+use crate::task::flv::{Failover, FailoverVariants, StateboardParams};
+use crate::task::vars::Vars;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "{}error{}", FG_RED.get_prefix(), FG_RED.get_suffix()),
+            Severity::Warning => write!(
+                f,
+                "{}warning{}",
+                FG_YELLOW.get_prefix(),
+                FG_YELLOW.get_suffix()
+            ),
+        }
+    }
+}
+
+/// Entry of the rule catalogue printed with `genin lint --rules`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Rule {
+    pub id: &'static str,
+    pub severity: Severity,
+    pub summary: &'static str,
+}
+
+impl Display for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}[{}]: {}", self.severity, self.id, self.summary)
+    }
+}
+
+pub const ROUTER_WITHOUT_STORAGE: &str = "router-without-storage";
+pub const MISSING_VSHARD_GROUP: &str = "missing-vshard-group";
+pub const REPLICATION_FACTOR: &str = "replication-factor-exceeds-domains";
+pub const STATEBOARD_ADDRESS: &str = "unknown-stateboard-address";
+pub const DUPLICATE_ADDRESS: &str = "duplicate-host-address";
+pub const UNKNOWN_FAILURE_DOMAIN: &str = "unknown-failure-domain";
+pub const DEFAULT_PASSWORD: &str = "default-password";
+
+pub const RULES: &[Rule] = &[
+    Rule {
+        id: ROUTER_WITHOUT_STORAGE,
+        severity: Severity::Error,
+        summary: "topology has vshard routers but no storages",
+    },
+    Rule {
+        id: MISSING_VSHARD_GROUP,
+        severity: Severity::Error,
+        summary: "storage has no vshard_group while several groups exist",
+    },
+    Rule {
+        id: REPLICATION_FACTOR,
+        severity: Severity::Warning,
+        summary: "replicas of a replicaset outnumber its failure domains",
+    },
+    Rule {
+        id: STATEBOARD_ADDRESS,
+        severity: Severity::Warning,
+        summary: "stateboard address does not match any host",
+    },
+    Rule {
+        id: DUPLICATE_ADDRESS,
+        severity: Severity::Error,
+        summary: "several hosts share the same address",
+    },
+    Rule {
+        id: UNKNOWN_FAILURE_DOMAIN,
+        severity: Severity::Error,
+        summary: "failure_domains names a host that does not exist",
+    },
+    Rule {
+        id: DEFAULT_PASSWORD,
+        severity: Severity::Warning,
+        summary: "password or cookie left at the value from genin init",
+    },
+];
+
+/// Single violation of a lint rule with a suggested fix.
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct Finding {
+    pub rule: &'static str,
+    pub severity: Severity,
+    pub message: String,
+    pub fix: String,
+}
+
+impl Finding {
+    fn new(rule: &'static str, message: String, fix: String) -> Self {
+        let severity = RULES
+            .iter()
+            .find(|known| known.id == rule)
+            .map(|known| known.severity)
+            .expect("every rule is in the catalogue");
+        Self {
+            rule,
+            severity,
+            message,
+            fix,
+        }
+    }
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}[{}]: {}", self.severity, self.rule, self.message)?;
+        write!(f, "  fix: {}", self.fix)
+    }
+}
+
+/// Rules suppressed in the cluster configuration.
+/// ```yaml
+/// lint:
+///   allow:
+///     - default-password
+/// ```
+#[derive(Deserialize, Debug, Default, PartialEq, Eq)]
+pub struct LintConfig {
+    #[serde(default)]
+    pub allow: Vec<String>,
+}
+
+/// Cluster configuration as written by the user. Unlike [`Cluster`] it is
+/// not spread over the hosts, so that mistakes which make spreading fail
+/// can still be reported.
+///
+/// [`Cluster`]: crate::task::cluster::Cluster
+#[derive(Debug)]
+pub struct Linter {
+    topology: Topology,
+    hosts: Host,
+    failover: Failover,
+    vars: Vars,
+    config: LintConfig,
+}
+
+impl<'de> Deserialize<'de> for Linter {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct LinterHelper {
+            topology: Topology,
+            hosts: Vec<HostHelper>,
+            #[serde(default)]
+            failover: Failover,
+            vars: Box<Vars>,
+            #[serde(default)]
+            lint: LintConfig,
+        }
+
+        let config =
+            resolve_config(Value::deserialize(deserializer)?).map_err(serde::de::Error::custom)?;
+        let helper = LinterHelper::deserialize(config).map_err(serde::de::Error::custom)?;
+        let hosts =
+            Host::try_from(HostHelper::root(helper.hosts)).map_err(serde::de::Error::custom)?;
+        // selectors are resolved to failure domains as the build does
+        Ok(Linter {
+            topology: helper
+                .topology
+                .with_placement(&hosts)
+                .map_err(serde::de::Error::custom)?,
+            hosts,
+            failover: helper.failover,
+            vars: *helper.vars,
+            config: helper.lint,
+        })
+    }
+}
+
+impl<'a> TryFrom<&'a ArgMatches> for Linter {
+    type Error = ClusterError;
+
+    fn try_from(args: &'a ArgMatches) -> Result<Self, Self::Error> {
+        let path = PathBuf::from(args.get_one::<String>("source").unwrap());
+        let profile = args.try_get_one::<String>("profile").ok().flatten();
+        let linter: Linter =
+            serde_yaml::from_value(read_with_profile(&path, profile.map(String::as_str))?.0)?;
+
+        if let Some(unknown) = linter
+            .config
+            .allow
+            .iter()
+            .find(|id| !RULES.iter().any(|rule| &rule.id == id))
+        {
+            return Err(ClusterError::Other(format!(
+                "unknown lint rule {unknown} in lint.allow"
+            )));
+        }
+
+        Ok(linter)
+    }
+}
+
+impl Linter {
+    /// Runs every rule that is not suppressed, errors first.
+    pub fn lint(&self) -> Vec<Finding> {
+        let instances = Instances::from(&self.topology);
+
+        let mut findings: Vec<Finding> = [
+            self.router_without_storage(&instances),
+            self.missing_vshard_group(&instances),
+            self.replication_factor(&instances),
+            self.stateboard_address(),
+            self.duplicate_address(),
+            self.unknown_failure_domain(&instances),
+            self.default_password(),
+        ]
+        .into_iter()
+        .flatten()
+        .filter(|finding| !self.config.allow.iter().any(|id| id == finding.rule))
+        .collect();
+
+        findings.sort_by_key(|finding| finding.severity);
+        findings
+    }
+
+    fn router_without_storage(&self, instances: &Instances) -> Vec<Finding> {
+        let routers = instances
+            .iter()
+            .filter(|instance| instance.roles.iter().any(is_router))
+            .map(|instance| instance.name.get_ancestor_str().to_string())
+            .collect::<HashSet<String>>();
+
+        if routers.is_empty() || instances.iter().any(|ins| ins.roles.iter().any(is_storage)) {
+            return Vec::new();
+        }
+
+        let mut routers = routers.into_iter().collect::<Vec<String>>();
+        routers.sort();
+        vec![Finding::new(
+            ROUTER_WITHOUT_STORAGE,
+            format!(
+                "{} routes requests but the topology has no storage",
+                routers.join(", ")
+            ),
+            "add a replicaset with the storage role to the topology".into(),
+        )]
+    }
+
+    fn missing_vshard_group(&self, instances: &Instances) -> Vec<Finding> {
+        let storages = instances
+            .iter()
+            .filter(|instance| instance.roles.iter().any(is_storage))
+            .collect::<Vec<&Instance>>();
+
+        // groups are declared either in cartridge_vshard_groups or on the storages
+        let mut groups = match self.vars.another_fields.get("cartridge_vshard_groups") {
+            Some(Value::Mapping(groups)) => groups
+                .iter()
+                .filter_map(|(name, _)| name.as_str().map(String::from))
+                .collect(),
+            Some(Value::Sequence(groups)) => groups
+                .iter()
+                .filter_map(|name| name.as_str().map(String::from))
+                .collect(),
+            _ => Vec::new(),
+        };
+        storages
+            .iter()
+            .filter_map(|storage| storage.config.vshard_group.clone())
+            .for_each(|group| {
+                if !groups.contains(&group) {
+                    groups.push(group);
+                }
+            });
+
+        if groups.len() < 2 {
+            return Vec::new();
+        }
+
+        unique_ancestors(
+            storages
+                .iter()
+                .filter(|storage| storage.config.vshard_group.is_none())
+                .map(|storage| &storage.name),
+        )
+        .into_iter()
+        .map(|name| {
+            Finding::new(
+                MISSING_VSHARD_GROUP,
+                format!(
+                    "{name} has no vshard_group while the cluster has groups {}",
+                    groups.join(", ")
+                ),
+                format!("set config.vshard_group of {name} to one of the groups"),
+            )
+        })
+        .collect()
+    }
+
+    fn replication_factor(&self, instances: &Instances) -> Vec<Finding> {
+        let hosts = self.hosts.lower_level_hosts().len();
+
+        // replicas are named like storage-1-1, storage-1-2 and every
+        // replicaset of a topology member has the same replication factor,
+        // so it is enough to count the replicas of the first one
+        let mut replicasets: IndexMap<Name, (usize, usize)> = IndexMap::new();
+        instances
+            .iter()
+            .filter(|instance| {
+                instance.name.len() == 3 && instance.name.parent_index_as_usize() == Some(1)
+            })
+            .for_each(|instance| {
+                let domains = Vec::<String>::from(instance.failure_domains.clone())
+                    .into_iter()
+                    .collect::<HashSet<String>>()
+                    .len();
+                replicasets
+                    .entry(instance.name.get_ancestor_name())
+                    .or_insert((0, if domains > 0 { domains } else { hosts }))
+                    .0 += 1;
+            });
+
+        replicasets
+            .into_iter()
+            .filter(|(_, (replicas, domains))| replicas > domains)
+            .map(|(name, (replicas, domains))| {
+                Finding::new(
+                    REPLICATION_FACTOR,
+                    format!(
+                        "{name} has replication_factor {replicas} but only {domains} \
+                        failure domains, some replicas will share a host"
+                    ),
+                    format!(
+                        "add hosts or failure_domains to {name} or lower its replication_factor"
+                    ),
+                )
+            })
+            .collect()
+    }
+
+    fn stateboard_address(&self) -> Vec<Finding> {
+        match &self.failover.failover_variants {
+            FailoverVariants::StateboardVariant(StateboardParams { uri, .. })
+                if self.hosts.get_name_by_address(&uri.address).is_none() =>
+            {
+                vec![Finding::new(
+                    STATEBOARD_ADDRESS,
+                    format!("stateboard uri {uri} does not match the address of any host"),
+                    "set stateboard_params.uri to the address of one of the hosts".into(),
+                )]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn duplicate_address(&self) -> Vec<Finding> {
+        let mut addresses: IndexMap<String, Vec<String>> = IndexMap::new();
+        self.hosts
+            .lower_level_hosts()
+            .into_iter()
+            .for_each(|host| match &host.config.address {
+                Address::Ip(_) | Address::Uri(_) => addresses
+                    .entry(host.config.address.to_string())
+                    .or_default()
+                    .push(host.name.to_string()),
+                _ => {}
+            });
+
+        addresses
+            .into_iter()
+            .filter(|(_, hosts)| hosts.len() > 1)
+            .map(|(address, hosts)| {
+                Finding::new(
+                    DUPLICATE_ADDRESS,
+                    format!("hosts {} have the same address {address}", hosts.join(", ")),
+                    "give every host a unique address".into(),
+                )
+            })
+            .collect()
+    }
+
+    fn unknown_failure_domain(&self, instances: &Instances) -> Vec<Finding> {
+        fn collect(host: &Host, names: &mut HashSet<String>) {
+            names.insert(host.name.to_string());
+            host.hosts.iter().for_each(|host| collect(host, names));
+        }
+
+        let mut names = HashSet::new();
+        collect(&self.hosts, &mut names);
+
+        let mut unknown: IndexMap<(String, String), ()> = IndexMap::new();
+        instances.iter().for_each(|instance| {
+            Vec::<String>::from(instance.failure_domains.clone())
+                .into_iter()
+                .filter(|domain| !names.contains(domain))
+                .for_each(|domain| {
+                    unknown.insert((instance.name.get_ancestor_str().to_string(), domain), ());
+                });
+        });
+
+        unknown
+            .into_keys()
+            .map(|(name, domain)| {
+                Finding::new(
+                    UNKNOWN_FAILURE_DOMAIN,
+                    format!("{name} has failure domain {domain} which is not a known host"),
+                    format!("use the name of a host, datacenter or region instead of {domain}"),
+                )
+            })
+            .collect()
+    }
+
+    fn default_password(&self) -> Vec<Finding> {
+        let defaults = Vars::default();
+        let mut findings = Vec::new();
+
+        for (field, value, default) in [
+            (
+                "ansible_password",
+                &self.vars.ansible_password,
+                &defaults.ansible_password,
+            ),
+            (
+                "cartridge_cluster_cookie",
+                &self.vars.cartridge_cluster_cookie,
+                &defaults.cartridge_cluster_cookie,
+            ),
+        ] {
+            if value.is_some() && value == default {
+                findings.push(Finding::new(
+                    DEFAULT_PASSWORD,
+                    format!("vars.{field} has the default value"),
+                    format!("set vars.{field} to a secret value"),
+                ));
+            }
+        }
+
+        if let FailoverVariants::StateboardVariant(StateboardParams { password, .. }) =
+            &self.failover.failover_variants
+        {
+            if password == &StateboardParams::default().password {
+                findings.push(Finding::new(
+                    DEFAULT_PASSWORD,
+                    "failover.stateboard_params.password has the default value".into(),
+                    "set failover.stateboard_params.password to a secret value".into(),
+                ));
+            }
+        }
+
+        findings
+    }
+}
+
+fn is_router(role: &Role) -> bool {
+    match role {
+        Role::Router(_) => true,
+        Role::Custom(name) => name.contains("vshard-router"),
+        _ => false,
+    }
+}
+
+fn is_storage(role: &Role) -> bool {
+    match role {
+        Role::Storage(_) => true,
+        Role::Custom(name) => name.contains("vshard-storage"),
+        _ => false,
+    }
+}
+
+fn unique_ancestors<'a>(names: impl Iterator<Item = &'a Name>) -> Vec<String> {
+    let mut ancestors = Vec::new();
+    names.for_each(|name| {
+        let ancestor = name.get_ancestor_str().to_string();
+        if !ancestors.contains(&ancestor) {
+            ancestors.push(ancestor);
+        }
+    });
+    ancestors
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,110 @@
+use crate::task::cluster::lint::{
+    Linter, Severity, DEFAULT_PASSWORD, DUPLICATE_ADDRESS, MISSING_VSHARD_GROUP, RULES,
+};
+
+const BROKEN: &str = r#"---
+topology:
+  - name: router
+    replicasets_count: 2
+    roles:
+      - router
+  - name: storage
+    replicasets_count: 2
+    replication_factor: 3
+    failure_domains: [dc-1, dc-3]
+    roles:
+      - storage
+  - name: cold-storage
+    replicasets_count: 1
+    roles:
+      - storage
+    config:
+      vshard_group: cold
+hosts:
+  - name: dc-1
+    hosts:
+      - name: server-1
+        config:
+          address: 192.168.16.11
+      - name: server-2
+        config:
+          address: 192.168.16.11
+  - name: dc-2
+    hosts:
+      - name: server-3
+        config:
+          address: 192.168.16.13
+failover:
+  mode: stateful
+  state_provider: stateboard
+  stateboard_params:
+    uri: "192.168.16.20:4401"
+    password: password
+vars:
+  ansible_user: ansible
+  ansible_password: ansible
+  cartridge_app_name: myapp
+  cartridge_cluster_cookie: myapp-cookie
+  cartridge_vshard_groups:
+    hot: {}
+"#;
+
+#[test]
+fn lint_broken_cluster() {
+    let linter: Linter = serde_yaml::from_str(BROKEN).unwrap();
+
+    insta::assert_yaml_snapshot!(linter.lint());
+}
+
+#[test]
+fn lint_router_without_storage() {
+    let linter: Linter = serde_yaml::from_str(
+        r#"---
+topology:
+  - name: router
+    roles:
+      - vshard-router
+hosts:
+  - name: server-1
+    config:
+      address: 192.168.16.11
+failover:
+  mode: eventual
+vars:
+  ansible_password: secret
+  cartridge_cluster_cookie: secret
+"#,
+    )
+    .unwrap();
+
+    let findings = linter.lint();
+
+    assert_eq!(findings.len(), 1);
+    assert_eq!(findings[0].rule, "router-without-storage");
+    assert_eq!(findings[0].severity, Severity::Error);
+}
+
+#[test]
+fn lint_suppressed_rules() {
+    let linter: Linter = serde_yaml::from_str(&format!(
+        "{BROKEN}lint:\n  allow:\n    - {DEFAULT_PASSWORD}\n    - {DUPLICATE_ADDRESS}\n"
+    ))
+    .unwrap();
+
+    let rules = linter
+        .lint()
+        .into_iter()
+        .map(|finding| finding.rule)
+        .collect::<Vec<&str>>();
+
+    assert!(rules.contains(&MISSING_VSHARD_GROUP));
+    assert!(!rules.contains(&DEFAULT_PASSWORD));
+    assert!(!rules.contains(&DUPLICATE_ADDRESS));
+}
+
+#[test]
+fn lint_rules_are_unique() {
+    RULES.iter().enumerate().for_each(|(index, rule)| {
+        assert!(RULES[index + 1..].iter().all(|other| other.id != rule.id));
+    });
+}
@@ -0,0 +1,107 @@
+use std::path::{Path, PathBuf};
+
+use serde_yaml::Value;
+
+use crate::task::cluster::include::read_config;
+use crate::task::cluster::ClusterError;
+
+/// Overlay of the profile next to the base config, the profile goes after
+/// the first part of the file name: `cluster.genin.yml` with profile `prod`
+/// becomes `cluster.prod.genin.yml`.
+pub fn overlay_path(path: &Path, profile: &str) -> PathBuf {
+    let file_name = path
+        .file_name()
+        .map(|file_name| file_name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let file_name = match file_name.split_once('.') {
+        Some((stem, extension)) => format!("{stem}.{profile}.{extension}"),
+        None => format!("{file_name}.{profile}"),
+    };
+    path.with_file_name(file_name)
+}
+
+/// Reads the base config and merges the overlay of the profile into it, the
+/// paths of the files read by both are returned along with the config.
+pub fn read_with_profile(
+    path: &Path,
+    profile: Option<&str>,
+) -> Result<(Value, Vec<PathBuf>), ClusterError> {
+    let (base, mut paths) = read_config(path)?;
+    let Some(profile) = profile else {
+        return Ok((base, paths));
+    };
+
+    let overlay_path = overlay_path(path, profile);
+    if !overlay_path.is_file() {
+        return Err(format!(
+            "profile {profile} has no overlay {}",
+            overlay_path.display()
+        )
+        .into());
+    }
+    let (overlay, overlay_paths) = read_config(&overlay_path)?;
+    paths.extend(overlay_paths);
+
+    Ok((merge_overlay(overlay, base), paths))
+}
+
+fn name(value: &Value) -> Option<&Value> {
+    value.get("name")
+}
+
+fn is_deleted(value: &Value) -> bool {
+    value.get("delete") == Some(&Value::Bool(true))
+}
+
+/// Merges the overlay into the base. Mappings are merged key by key and a
+/// `~` in the overlay deletes the key. Lists of named items, like the
+/// topology and the hosts, are merged by name, an item with `delete: true`
+/// deletes the item of the base. Anything else in the overlay replaces the
+/// base.
+pub fn merge_overlay(overlay: Value, base: Value) -> Value {
+    match (overlay, base) {
+        (Value::Mapping(overlay), Value::Mapping(mut base)) => {
+            overlay.into_iter().for_each(|(key, value)| {
+                if value.is_null() {
+                    base = std::mem::take(&mut base)
+                        .into_iter()
+                        .filter(|(base_key, _)| *base_key != key)
+                        .collect();
+                    return;
+                }
+                match base.get_mut(&key) {
+                    Some(base) => *base = merge_overlay(value, std::mem::take(base)),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            });
+            Value::Mapping(base)
+        }
+        (Value::Sequence(overlay), Value::Sequence(mut base))
+            if overlay
+                .iter()
+                .chain(base.iter())
+                .all(|item| name(item).is_some()) =>
+        {
+            overlay.into_iter().for_each(|item| {
+                let position = base.iter().position(|base| name(base) == name(&item));
+                match position {
+                    Some(position) if is_deleted(&item) => {
+                        base.remove(position);
+                    }
+                    Some(position) => {
+                        base[position] = merge_overlay(item, std::mem::take(&mut base[position]))
+                    }
+                    None if is_deleted(&item) => {}
+                    None => base.push(item),
+                }
+            });
+            Value::Sequence(base)
+        }
+        (overlay, _) => overlay,
+    }
+}
+
+#[cfg(test)]
+mod test;
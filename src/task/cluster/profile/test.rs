@@ -0,0 +1,164 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::{Arg, ArgAction, ArgMatches, Command};
+
+use crate::task::cluster::{
+    profile::{merge_overlay, overlay_path, read_with_profile},
+    Cluster,
+};
+use crate::task::state::backend::GzipDir;
+use crate::task::state::diff::snapshot;
+use crate::task::state::secrets::SecretsPolicy;
+use crate::task::state::state_dir;
+
+const BASE: &str = r#"---
+topology:
+  - name: router
+    replicasets_count: 1
+    roles:
+      - router
+  - name: storage
+    replicasets_count: 2
+    replication_factor: 2
+    roles:
+      - storage
+  - name: cache
+    roles:
+      - cache
+hosts:
+  - name: server-1
+    config:
+      address: 192.168.16.11
+  - name: server-2
+    config:
+      address: 192.168.16.12
+failover:
+  mode: disabled
+vars:
+  ansible_user: ansible
+  ansible_password: ansible
+  cartridge_app_name: myapp
+  cartridge_cluster_cookie: myapp-cookie
+  cartridge_package_path: /tmp/myapp.rpm
+"#;
+
+const PROD: &str = r#"---
+topology:
+  - name: storage
+    replication_factor: 3
+  - name: cache
+    delete: true
+hosts:
+  - name: server-3
+    config:
+      address: 192.168.16.13
+vars:
+  ansible_password: prod-password
+  cartridge_package_path: ~
+"#;
+
+fn args(source: &Path, profile: Option<&str>) -> ArgMatches {
+    let mut argv = vec![
+        "genin".to_string(),
+        "--source".to_string(),
+        source.display().to_string(),
+    ];
+    if let Some(profile) = profile {
+        argv.extend(["--profile".to_string(), profile.to_string()]);
+    }
+    Command::new("genin")
+        .args([
+            Arg::new("source").long("source").action(ArgAction::Set),
+            Arg::new("profile").long("profile").action(ArgAction::Set),
+            Arg::new("state-dir")
+                .long("state-dir")
+                .action(ArgAction::Set),
+        ])
+        .try_get_matches_from(argv)
+        .unwrap()
+}
+
+#[test]
+fn profile_overlay_path() {
+    assert_eq!(
+        overlay_path(Path::new("configs/cluster.genin.yml"), "prod"),
+        PathBuf::from("configs/cluster.prod.genin.yml")
+    );
+    assert_eq!(
+        overlay_path(Path::new("cluster"), "dev"),
+        PathBuf::from("cluster.dev")
+    );
+}
+
+#[test]
+fn profile_merge_overlay() {
+    let merged = merge_overlay(
+        serde_yaml::from_str(PROD).unwrap(),
+        serde_yaml::from_str(BASE).unwrap(),
+    );
+
+    insta::assert_yaml_snapshot!(merged);
+}
+
+#[test]
+fn cluster_with_profile() {
+    let dir = std::env::temp_dir().join(format!("genin-profile-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("cluster.genin.yml");
+    fs::write(&source, BASE).unwrap();
+    fs::write(dir.join("cluster.prod.genin.yml"), PROD).unwrap();
+
+    let base = Cluster::try_from(&args(&source, None));
+    let prod = Cluster::try_from(&args(&source, Some("prod")));
+    let stage = read_with_profile(&source, Some("stage"));
+    fs::remove_dir_all(&dir).unwrap();
+
+    let base = base.unwrap();
+    let prod = prod.unwrap();
+    assert_eq!(base.hosts.lower_level_hosts().len(), 2);
+    assert_eq!(prod.hosts.lower_level_hosts().len(), 3);
+    assert_eq!(
+        prod.metadata.paths,
+        vec![source.clone(), dir.join("cluster.prod.genin.yml")]
+    );
+    assert!(prod.hosts.find_instance("storage-1-3").is_some());
+    assert!(prod.hosts.find_instance("cache-1").is_none());
+    assert_eq!(prod.vars.cartridge_package_path, None);
+    assert_eq!(
+        stage.unwrap_err().to_string(),
+        format!(
+            "other error profile stage has no overlay {}",
+            dir.join("cluster.stage.genin.yml").display()
+        )
+    );
+
+    assert_eq!(state_dir(&args(&source, None)), ".geninstate");
+    assert_eq!(state_dir(&args(&source, Some("prod"))), ".geninstate-prod");
+}
+
+#[test]
+fn cluster_configs_read_with_profile() {
+    let dir = std::env::temp_dir().join(format!("genin-profile-read-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("cluster.genin.yml");
+    fs::write(&source, BASE).unwrap();
+    fs::write(dir.join("cluster.prod.genin.yml"), PROD).unwrap();
+
+    let backend = GzipDir {
+        dir: dir.join(".geninstate").display().to_string(),
+        policy: SecretsPolicy::Plain,
+    };
+    let read = Cluster::read(&source, Some("prod"));
+    let diffed = snapshot(&backend, &source.display().to_string(), Some("prod"));
+    fs::remove_dir_all(&dir).unwrap();
+
+    // the upgrade and the diff of cluster configs see the overlay too
+    [read.unwrap(), diffed.unwrap()]
+        .into_iter()
+        .for_each(|cluster| {
+            assert_eq!(cluster.hosts.lower_level_hosts().len(), 3);
+            assert!(cluster.hosts.find_instance("cache-1").is_none());
+            assert_eq!(cluster.vars.ansible_password, Some("prod-password".into()));
+        });
+}
@@ -1,4 +1,8 @@
-use crate::task::{cluster::host::hst::Address, serde_genin, utils::uncolorize};
+use crate::task::{
+    cluster::host::{affinity::Affinity, hst::Address},
+    serde_genin,
+    utils::uncolorize,
+};
 use clap::{Arg, ArgAction, Command};
 use std::convert::TryFrom;
 use std::net::IpAddr;
@@ -65,7 +69,8 @@ hosts:
 
     let host: Host = serde_yaml::from_str::<HostHelper>(&hosts_v2_str)
         .unwrap()
-        .into();
+        .try_into()
+        .unwrap();
 
     assert_eq!(host, hosts_v2_model);
 
@@ -405,7 +410,7 @@ vars:
     let mut cluster_new: Cluster = serde_yaml::from_str(new_cluster_str).unwrap();
 
     cluster_old.merge(&mut cluster_new, false).unwrap();
-    cluster_old.hosts.spread();
+    cluster_old.hosts.spread(&Affinity::default()).unwrap();
 
     let mut upgrade_with_same_name = uncolorize(&cluster_old);
 
@@ -534,7 +539,7 @@ vars:
     "#;
 
     let mut cluster: Cluster = serde_yaml::from_str(cluster).unwrap();
-    cluster.hosts.spread();
+    cluster.hosts.spread(&Affinity::default()).unwrap();
 
     let inventory = Inventory::try_from(&cluster).unwrap();
     let ansible_host = inventory.all.children.values().last().unwrap();
@@ -3,7 +3,7 @@ use std::{cmp::Ordering, collections::HashSet};
 use indexmap::IndexMap;
 use log::{debug, trace};
 use serde::{Deserialize, Serialize};
-use serde_yaml::{Number, Value};
+use serde_yaml::{Mapping, Number, Value};
 use tabled::Alignment;
 
 use crate::task::{
@@ -12,7 +12,15 @@ use crate::task::{
 };
 
 use super::{
-    host::view::{TableColors, View},
+    host::{
+        affinity::{Affinity, AffinityKind, AffinityRule},
+        hst::Host,
+        placement::Placement,
+        ports::PortBase,
+        selector::Selector,
+        strategy::Strategy,
+        view::{TableColors, View},
+    },
     instance::{
         ins::{Instance, InstanceConfig, Instances},
         Role,
@@ -51,6 +59,7 @@ impl TryFrom<Instances> for Topology {
                          cartridge_extra_env,
                          config,
                          vars,
+                         spread_strategy,
                          ..
                      }| {
                         debug!(
@@ -68,6 +77,11 @@ impl TryFrom<Instances> for Topology {
                                 replication_factor: Some(0),
                                 weight: *weight,
                                 failure_domains: failure_domains.clone().into(),
+                                placement: None,
+                                spread_strategy: (!spread_strategy.is_balanced())
+                                    .then_some(*spread_strategy),
+                                colocate_with: Vec::new(),
+                                avoid: Vec::new(),
                                 roles: roles.clone(),
                                 cartridge_extra_env: cartridge_extra_env.clone(),
                                 config: config.clone(),
@@ -93,6 +107,7 @@ impl TryFrom<Instances> for Topology {
                             replication_factor,
                             weight,
                             failure_domains,
+                            spread_strategy,
                             roles,
                             cartridge_extra_env,
                             config,
@@ -115,6 +130,10 @@ impl TryFrom<Instances> for Topology {
                                 replication_factor,
                                 weight,
                                 failure_domains,
+                                placement: None,
+                                spread_strategy,
+                                colocate_with: Vec::new(),
+                                avoid: Vec::new(),
                                 roles,
                                 cartridge_extra_env,
                                 config,
@@ -162,6 +181,10 @@ impl<'a> From<&'a Topology> for Instances {
                          replication_factor,
                          weight,
                          failure_domains,
+                         placement: _,
+                         spread_strategy,
+                         colocate_with: _,
+                         avoid: _,
                          roles,
                          cartridge_extra_env,
                          config,
@@ -188,6 +211,8 @@ impl<'a> From<&'a Topology> for Instances {
                                                     name.clone_with_index(repliaset_num),
                                                 ),
                                             },
+                                            spread_strategy: spread_strategy.unwrap_or_default(),
+                                            placement: Placement::default(),
                                         })
                                         .collect::<Vec<Instance>>()
                                 } else {
@@ -204,6 +229,8 @@ impl<'a> From<&'a Topology> for Instances {
                                             alignment: Alignment::left(),
                                             color: table_colors.next_color(name.clone()),
                                         },
+                                        spread_strategy: spread_strategy.unwrap_or_default(),
+                                        placement: Placement::default(),
                                     }]
                                 }
                             })
@@ -229,6 +256,117 @@ impl Topology {
 
         Ok(self)
     }
+
+    /// Collects the `colocate_with` and `avoid` rules of the members,
+    /// checking that they reference existing members.
+    pub fn affinity(&self) -> Result<Affinity, String> {
+        let mut affinity = Affinity::default();
+        self.0.iter().try_for_each(|topology_set| {
+            let name = topology_set.name.to_string();
+            let rules = topology_set
+                .colocate_with
+                .iter()
+                .map(|rule| (AffinityKind::Colocate, rule))
+                .chain(
+                    topology_set
+                        .avoid
+                        .iter()
+                        .map(|rule| (AffinityKind::Avoid, rule)),
+                );
+            for (kind, rule) in rules {
+                if !self
+                    .0
+                    .iter()
+                    .any(|other| other.name.to_string() == rule.name)
+                {
+                    return Err(format!(
+                        "{name} references unknown topology member {}",
+                        rule.name
+                    ));
+                }
+                if kind == AffinityKind::Colocate && rule.name == name {
+                    return Err(format!("{name} can not be colocated with itself"));
+                }
+                affinity.push(&name, kind, rule.clone());
+            }
+            Ok(())
+        })?;
+        Ok(affinity)
+    }
+
+    /// Adds the leaf hosts matching the placement selector of every member
+    /// to its failure domains.
+    pub fn with_placement(self, hosts: &Host) -> Result<Self, String> {
+        self.0
+            .into_iter()
+            .map(|topology_set| {
+                let Some(selector) = &topology_set.placement else {
+                    return Ok(topology_set);
+                };
+                selector
+                    .validate()
+                    .map_err(|err| format!("placement of {}: {err}", topology_set.name))?;
+
+                let selected = hosts.select(selector);
+                if selected.is_empty() {
+                    return Err(format!(
+                        "placement [{selector}] of {} matches no host",
+                        topology_set.name
+                    ));
+                }
+                let mut failure_domains = topology_set.failure_domains.clone();
+                selected.into_iter().for_each(|name| {
+                    if !failure_domains.contains(&name) {
+                        failure_domains.push(name);
+                    }
+                });
+
+                Ok(TopologySet {
+                    failure_domains,
+                    ..topology_set
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(Self)
+    }
+
+    /// Gives the strategy to every topology member without its own one.
+    pub fn with_spread_strategy(self, strategy: Option<Strategy>) -> Self {
+        Self(
+            self.0
+                .into_iter()
+                .map(|topology_set| TopologySet {
+                    spread_strategy: topology_set.spread_strategy.or(strategy),
+                    ..topology_set
+                })
+                .collect(),
+        )
+    }
+
+    /// Gives every topology member the port bases of its first role found in
+    /// `role_ports`, unless the member sets its own bases in `config`.
+    pub fn with_role_ports(self, role_ports: &IndexMap<String, PortBase>) -> Self {
+        Self(
+            self.0
+                .into_iter()
+                .map(|topology_set| {
+                    let base = topology_set.roles.iter().find_map(|role| {
+                        role_ports
+                            .iter()
+                            .find(|(name, _)| role.is_named(name))
+                            .map(|(_, base)| base)
+                    });
+                    match base {
+                        Some(base) => TopologySet {
+                            config: topology_set.config.with_port_base(base),
+                            ..topology_set
+                        },
+                        None => topology_set,
+                    }
+                })
+                .collect(),
+        )
+    }
 }
 
 impl From<Vec<TopologyMember>> for Topology {
@@ -263,6 +401,10 @@ impl From<Vec<TopologyMember>> for Topology {
                                 }
                             }),
                             failure_domains: Default::default(),
+                            placement: None,
+                            spread_strategy: None,
+                            colocate_with: Vec::new(),
+                            avoid: Vec::new(),
                             roles,
                             cartridge_extra_env: IndexMap::default(),
                             config: InstanceConfig {
@@ -287,6 +429,10 @@ impl Default for Topology {
                 replication_factor: None,
                 weight: None,
                 failure_domains: Default::default(),
+                placement: None,
+                spread_strategy: None,
+                colocate_with: Vec::new(),
+                avoid: Vec::new(),
                 roles: vec![Role::router(), Role::failover_coordinator()],
                 cartridge_extra_env: IndexMap::default(),
                 config: InstanceConfig::default(),
@@ -298,6 +444,10 @@ impl Default for Topology {
                 replication_factor: Some(2),
                 weight: None,
                 failure_domains: Default::default(),
+                placement: None,
+                spread_strategy: None,
+                colocate_with: Vec::new(),
+                avoid: Vec::new(),
                 roles: vec![Role::storage()],
                 cartridge_extra_env: IndexMap::default(),
                 config: InstanceConfig::default(),
@@ -322,6 +472,14 @@ struct TopologySet {
     weight: Option<usize>,
     #[serde(skip_serializing_if = "Vec::is_empty")]
     failure_domains: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    placement: Option<Selector>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    spread_strategy: Option<Strategy>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    colocate_with: Vec<AffinityRule>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    avoid: Vec<AffinityRule>,
     #[serde(default)]
     roles: Vec<Role>,
     #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
@@ -353,6 +511,14 @@ impl<'de> Deserialize<'de> for TopologySet {
             #[serde(default)]
             failure_domains: Vec<String>,
             #[serde(default)]
+            placement: Option<Selector>,
+            #[serde(default)]
+            spread_strategy: Option<Strategy>,
+            #[serde(default)]
+            colocate_with: Vec<AffinityRule>,
+            #[serde(default)]
+            avoid: Vec<AffinityRule>,
+            #[serde(default)]
             roles: Vec<Role>,
             #[serde(default)]
             all_rw: Option<bool>,
@@ -371,6 +537,10 @@ impl<'de> Deserialize<'de> for TopologySet {
                  replication_factor,
                  weight,
                  failure_domains,
+                 placement,
+                 spread_strategy,
+                 colocate_with,
+                 avoid,
                  roles,
                  all_rw,
                  cartridge_extra_env,
@@ -390,6 +560,10 @@ impl<'de> Deserialize<'de> for TopologySet {
                     }),
                     weight,
                     failure_domains,
+                    placement,
+                    spread_strategy,
+                    colocate_with,
+                    avoid,
                     roles,
                     cartridge_extra_env,
                     config: config.unwrap_or_default().with_all_rw(all_rw),
@@ -808,5 +982,128 @@ impl std::fmt::Debug for ErrInstanceConfig {
     }
 }
 
+/// Resolves `extends` of the topology members in a raw cluster config and
+/// drops the `templates` section, so that the topology is deserialized with
+/// every inherited setting in place.
+/// ```yaml
+/// templates:
+///   storage:
+///     replicasets_count: 1
+///     replication_factor: 2
+///     roles: [storage]
+/// topology:
+///   - name: storage-hot
+///     extends: storage
+///     config:
+///       vshard_group: hot
+///   - name: storage-cold
+///     extends: storage-hot
+///     config:
+///       vshard_group: cold
+/// ```
+/// A member extends a template or another topology member, templates may
+/// extend each other too. Mappings are merged deeply with the local settings
+/// taking precedence, any other value is replaced as a whole.
+pub fn resolve_extends(mut config: Value) -> Result<Value, String> {
+    let Value::Mapping(mapping) = &mut config else {
+        return Ok(config);
+    };
+    let templates = match mapping.remove(&Value::from("templates")) {
+        Some(Value::Mapping(templates)) => templates,
+        Some(Value::Null) | None => Mapping::new(),
+        Some(_) => return Err("templates must be a mapping of template names".into()),
+    };
+    let Some(Value::Sequence(topology)) = mapping.get(&Value::from("topology")) else {
+        return Ok(config);
+    };
+
+    let resolved = topology
+        .iter()
+        .map(|member| {
+            let name = member_name(member);
+            resolve_member(&name, member, &templates, topology, &mut vec![name.clone()])
+        })
+        .collect::<Result<Vec<Value>, String>>()?;
+    mapping.insert(Value::from("topology"), Value::Sequence(resolved));
+    Ok(config)
+}
+
+fn member_name(member: &Value) -> String {
+    member
+        .get("name")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn resolve_member(
+    name: &str,
+    member: &Value,
+    templates: &Mapping,
+    topology: &[Value],
+    chain: &mut Vec<String>,
+) -> Result<Value, String> {
+    let Value::Mapping(local) = member else {
+        return Ok(member.clone());
+    };
+    let mut local = local.clone();
+    let Some(parent) = local.remove(&Value::from("extends")) else {
+        return Ok(Value::Mapping(local));
+    };
+    let parent = parent
+        .as_str()
+        .ok_or_else(|| format!("extends of {name} must be a name"))?
+        .to_string();
+
+    // templates take precedence, so that a member may extend the template
+    // of the same name
+    let (link, base) = match templates.get(&Value::from(parent.as_str())) {
+        Some(template) => (format!("template {parent}"), template),
+        None => (
+            parent.clone(),
+            topology
+                .iter()
+                .find(|member| member_name(member) == parent)
+                .ok_or_else(|| {
+                    format!("{name} extends unknown template or topology member {parent}")
+                })?,
+        ),
+    };
+    if chain.contains(&link) {
+        chain.push(link);
+        return Err(format!("extends cycle {}", chain.join(" -> ")));
+    }
+
+    chain.push(link.clone());
+    let mut base = resolve_member(&link, base, templates, topology, chain)?;
+    chain.pop();
+
+    if let Value::Mapping(base) = &mut base {
+        base.remove(&Value::from("name"));
+    }
+    Ok(merge_values(Value::Mapping(local), base))
+}
+
+/// Merges mappings key by key, `local` wins over `base` for anything else.
+pub fn merge_values(local: Value, base: Value) -> Value {
+    match (local, base) {
+        (Value::Mapping(local), Value::Mapping(mut base)) => {
+            let mut merged = Mapping::new();
+            local.into_iter().for_each(|(key, value)| {
+                let value = match base.remove(&key) {
+                    Some(base) => merge_values(value, base),
+                    None => value,
+                };
+                merged.insert(key, value);
+            });
+            base.into_iter().for_each(|(key, value)| {
+                merged.insert(key, value);
+            });
+            Value::Mapping(merged)
+        }
+        (local, _) => local,
+    }
+}
+
 #[cfg(test)]
 mod test;
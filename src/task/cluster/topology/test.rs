@@ -7,7 +7,8 @@ use crate::task::cluster::{
         Role,
     },
     name::Name,
-    topology::{Topology, TopologySet},
+    topology::{resolve_extends, Topology, TopologySet},
+    Cluster,
 };
 
 #[test]
@@ -38,6 +39,10 @@ fn topology_from_instances() {
             replication_factor: None,
             weight: None,
             failure_domains: Default::default(),
+            placement: None,
+            spread_strategy: None,
+            colocate_with: Vec::new(),
+            avoid: Vec::new(),
             roles: vec![Role::router(), Role::failover_coordinator()],
             cartridge_extra_env: IndexMap::new(),
             config: InstanceConfig::default(),
@@ -49,6 +54,10 @@ fn topology_from_instances() {
             replication_factor: Some(2),
             weight: None,
             failure_domains: Default::default(),
+            placement: None,
+            spread_strategy: None,
+            colocate_with: Vec::new(),
+            avoid: Vec::new(),
             roles: vec![Role::storage()],
             cartridge_extra_env: IndexMap::new(),
             config: InstanceConfig::default(),
@@ -83,6 +92,10 @@ roles:
         replication_factor: None,
         weight: None,
         failure_domains: Default::default(),
+        placement: None,
+        spread_strategy: None,
+        colocate_with: Vec::new(),
+        avoid: Vec::new(),
         roles: vec![Role::router(), Role::failover_coordinator()],
         cartridge_extra_env: IndexMap::new(),
         config: InstanceConfig::default(),
@@ -132,6 +145,10 @@ fn non_unique_replicaset_names() {
             replication_factor: None,
             weight: None,
             failure_domains: Default::default(),
+            placement: None,
+            spread_strategy: None,
+            colocate_with: Vec::new(),
+            avoid: Vec::new(),
             roles: vec![Role::router(), Role::failover_coordinator()],
             cartridge_extra_env: IndexMap::new(),
             config: InstanceConfig::default(),
@@ -143,6 +160,10 @@ fn non_unique_replicaset_names() {
             replication_factor: Some(2),
             weight: None,
             failure_domains: Default::default(),
+            placement: None,
+            spread_strategy: None,
+            colocate_with: Vec::new(),
+            avoid: Vec::new(),
             roles: vec![Role::storage()],
             cartridge_extra_env: IndexMap::new(),
             config: InstanceConfig::default(),
@@ -155,3 +176,69 @@ fn non_unique_replicaset_names() {
         Err("Replicaset names must be unique".into())
     )
 }
+
+const TEMPLATES: &str = r#"---
+templates:
+  storage:
+    replicasets_count: 1
+    replication_factor: 2
+    roles:
+      - storage
+    config:
+      memtx_memory: 1073741824
+      vshard_group: default
+    vars:
+      log_level: 5
+topology:
+  - name: router
+    replicasets_count: 1
+    roles:
+      - router
+  - name: storage-hot
+    extends: storage
+    config:
+      vshard_group: hot
+  - name: storage-cold
+    extends: storage-hot
+    replicasets_count: 2
+    config:
+      vshard_group: cold
+    vars:
+      log_level: 4
+hosts:
+  - name: server-1
+    config:
+      address: 192.168.16.11
+failover:
+  mode: disabled
+vars:
+  ansible_user: ansible
+  ansible_password: ansible
+  cartridge_app_name: myapp
+  cartridge_cluster_cookie: myapp-cookie
+"#;
+
+#[test]
+fn topology_templates() {
+    let cluster: Cluster = serde_yaml::from_str(TEMPLATES).unwrap();
+
+    insta::assert_yaml_snapshot!(cluster.topology);
+}
+
+#[test]
+fn topology_extends_errors() {
+    let config =
+        serde_yaml::from_str(&TEMPLATES.replace("extends: storage\n", "extends: stor\n")).unwrap();
+    assert_eq!(
+        resolve_extends(config).unwrap_err(),
+        "storage-hot extends unknown template or topology member stor"
+    );
+
+    let config =
+        serde_yaml::from_str(&TEMPLATES.replace("extends: storage\n", "extends: storage-cold\n"))
+            .unwrap();
+    assert_eq!(
+        resolve_extends(config).unwrap_err(),
+        "extends cycle storage-hot -> storage-cold -> storage-hot"
+    );
+}
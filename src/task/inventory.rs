@@ -1,5 +1,5 @@
 use std::convert::TryFrom;
-use std::fs::File;
+use std::fs::{read_to_string, File};
 use std::io;
 use std::io::Write;
 use std::path::PathBuf;
@@ -9,6 +9,7 @@ use indexmap::{IndexMap, IndexSet};
 use log::debug;
 use serde::{Deserialize, Serialize};
 use serde_yaml::Value;
+use sha256::digest;
 use thiserror::Error;
 
 use crate::task::cluster::host::hst::Address;
@@ -16,6 +17,7 @@ use crate::task::cluster::host::hst::Host;
 use crate::task::cluster::instance::ins::Instance;
 use crate::task::cluster::name::Name;
 use crate::task::flv::Uri;
+use crate::task::state::diff::diff_serialized;
 use crate::task::vars::Vars;
 use crate::task::Cluster;
 use crate::task::Validate;
@@ -31,19 +33,50 @@ use super::utils::create_file_or_copy;
 pub struct HeaderInfo {
     title: String,
     version: String,
+    /// Sha256 of the inventory contents following the header
+    hash: Option<String>,
 }
 impl Default for HeaderInfo {
     fn default() -> Self {
         Self {
             title: "Inventory generated by Genin".to_owned(),
             version: APP_VERSION.to_owned(),
+            hash: None,
         }
     }
 }
 
 impl std::fmt::Display for HeaderInfo {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "# {}\n# version: {}\n", self.title, self.version)
+        write!(f, "# {}\n# version: {}\n", self.title, self.version)?;
+        if let Some(hash) = &self.hash {
+            writeln!(f, "# sha256: {hash}")?;
+        }
+        Ok(())
+    }
+}
+
+impl HeaderInfo {
+    pub fn with_hash(self, hash: String) -> Self {
+        Self {
+            hash: Some(hash),
+            ..self
+        }
+    }
+
+    /// Splits inventory text into the hash from its header (if any) and
+    /// the contents following the header.
+    pub fn split(text: &str) -> (Option<&str>, &str) {
+        let mut hash = None;
+        let mut body = text;
+        while body.starts_with('#') {
+            let (line, rest) = body.split_once('\n').unwrap_or((body, ""));
+            if let Some(value) = line.strip_prefix("# sha256: ") {
+                hash = Some(value.trim());
+            }
+            body = rest;
+        }
+        (hash, body)
     }
 }
 
@@ -290,14 +323,54 @@ impl Inventory {
         );
 
         let mut file = create_file_or_copy(path, args.get_flag("force"))?;
-        let info = HeaderInfo::default().to_string();
+        let body = serde_yaml::to_string(self)?;
+        let info = HeaderInfo::default().with_hash(digest(&body)).to_string();
         file.write_all(info.as_bytes()).unwrap();
-
-        serde_yaml::to_writer(file, &self)?;
+        file.write_all(body.as_bytes())?;
 
         Ok(())
     }
 
+    /// Compares the inventory with the one already written to the output
+    /// file and fails with a readable diff if someone edited it by hand.
+    pub fn check(&self, args: &ArgMatches) -> Result<(), InventoryError> {
+        let path = args
+            .get_one::<String>("output")
+            .cloned()
+            .unwrap_or("inventory.yml".into());
+        let existing = Inventory::try_from(args)?;
+
+        if &existing == self {
+            println!("Inventory {path} is up to date");
+            return Ok(());
+        }
+
+        println!("Inventory {path} differs from genin output:");
+        diff_serialized(&existing, self)
+            .iter()
+            .for_each(|field| println!("  {field}"));
+
+        Err(InventoryError::Drift(path))
+    }
+
+    /// Checks that the inventory contents match the hash in its header.
+    pub fn verify(path: &str) -> Result<(), InventoryError> {
+        let text = read_to_string(path)?;
+        match HeaderInfo::split(&text) {
+            (Some(hash), body) if hash == digest(body) => {
+                println!("Inventory {path} was not modified");
+                Ok(())
+            }
+            (Some(_), _) => Err(InventoryError::Modified(path.into())),
+            (None, _) => Err(InventoryError::NoHash(path.into())),
+        }
+    }
+
+    /// Sha256 of the inventory contents without the header.
+    pub fn content_hash(&self) -> Result<String, InventoryError> {
+        Ok(digest(serde_yaml::to_string(self)?))
+    }
+
     pub fn try_into_cluster(&self) -> Result<Cluster, InventoryError> {
         Ok(Cluster::try_from(self)?)
     }
@@ -313,6 +386,12 @@ pub enum InventoryError {
     Genin(#[from] GeninError),
     #[error("cluster error")]
     Cluster(#[from] ClusterError),
+    #[error("inventory {0} differs from genin output")]
+    Drift(String),
+    #[error("inventory {0} was modified after generation")]
+    Modified(String),
+    #[error("inventory {0} has no content hash in its header")]
+    NoHash(String),
 }
 
 impl Validate for Inventory {
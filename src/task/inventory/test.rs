@@ -2,7 +2,7 @@ use std::convert::TryFrom;
 
 use crate::task::cluster::Cluster;
 
-use super::Inventory;
+use super::{HeaderInfo, Inventory};
 
 #[test]
 fn inventory_per_instance_vars() {
@@ -121,3 +121,20 @@ vars:
 
     insta::assert_yaml_snapshot!(inventory);
 }
+
+#[test]
+fn inventory_header_hash() {
+    let header = HeaderInfo::default()
+        .with_hash("0123abcd".into())
+        .to_string();
+    assert!(header.ends_with("# sha256: 0123abcd\n"));
+
+    let text = format!("{header}---\nall:\n  vars: {{}}\n");
+    assert_eq!(
+        HeaderInfo::split(&text),
+        (Some("0123abcd"), "---\nall:\n  vars: {}\n")
+    );
+
+    let text = format!("{}---\nall: {{}}\n", HeaderInfo::default());
+    assert_eq!(HeaderInfo::split(&text), (None, "---\nall: {}\n"));
+}
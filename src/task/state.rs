@@ -1,6 +1,13 @@
+pub mod backend;
+pub mod diff;
+pub mod lock;
+pub mod metadata;
+pub mod retention;
+pub mod secrets;
+
 use std::{
     fmt::Display,
-    fs::{create_dir_all, remove_dir_all, File},
+    fs::{create_dir_all, read_dir, remove_dir_all, remove_file, rename, File},
     io::{self, Read, Write},
     os::unix::fs,
     path::PathBuf,
@@ -8,17 +15,23 @@ use std::{
 
 use clap::ArgMatches;
 use flate2::{read, write::GzEncoder, Compression};
+use log::warn;
 use serde::{Deserialize, Serialize};
 use sha256::{digest, try_digest, TrySha256Digest};
 use thiserror::Error;
 
 use crate::task::cluster::host::view::{FG_GREEN, FG_RED};
 use crate::task::cluster::topology::Topology;
+use crate::task::state::backend::StateBackend;
+use crate::task::state::metadata::StateMetadata;
+use crate::task::state::secrets::{SecretsMode, SecretsPolicy};
 use crate::task::{cluster::host::hst::Host, flv::Failover, vars::Vars};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct State {
     uid: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    parent: Option<String>,
     args_str: String,
     pub kind: StateKind,
     pub path: String,
@@ -30,9 +43,13 @@ pub struct State {
     pub topology: Topology,
     pub hosts: Host,
     pub failover: Failover,
+    #[serde(default, skip_serializing_if = "StateMetadata::is_empty")]
+    pub metadata: StateMetadata,
+    #[serde(default, skip_serializing_if = "SecretsMode::is_plain")]
+    secrets: SecretsMode,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum StateKind {
     Build,
     Upgrade,
@@ -53,10 +70,17 @@ impl PartialEq for State {
     }
 }
 
-impl<'a> TryFrom<&'a PathBuf> for State {
-    type Error = StateError;
+impl State {
+    /// Reads the state and decrypts its secrets with the key of the policy.
+    pub fn read(path: &PathBuf, policy: &SecretsPolicy) -> Result<Self, StateError> {
+        let mut state = State::read_sealed(path)?;
+        state.reveal(policy)?;
+
+        Ok(state)
+    }
 
-    fn try_from(path: &'a PathBuf) -> Result<Self, Self::Error> {
+    /// Reads the state without decrypting its secrets.
+    pub fn read_sealed(path: &PathBuf) -> Result<Self, StateError> {
         let file = File::open(path)?;
 
         let mut buffer = Vec::new();
@@ -65,12 +89,11 @@ impl<'a> TryFrom<&'a PathBuf> for State {
 
         Ok(serde_json::from_slice(&buffer)?)
     }
-}
 
-impl State {
     pub fn builder() -> StateBuilder {
         StateBuilder {
             uid: None,
+            parent: None,
             kind: None,
             path: None,
             hosts_changes: None,
@@ -79,22 +102,29 @@ impl State {
             vars: None,
             failover: None,
             topology: None,
+            sources: None,
+            inventory: None,
         }
     }
 
     pub fn recreate(args: &ArgMatches) -> Result<(), io::Error> {
-        let state_dir = args
-            .get_one::<String>("state-dir")
-            .cloned()
-            .unwrap_or(".geninstate".into());
-
-        remove_dir_all(state_dir.clone())
-            .is_err()
-            .then(|| println!("State dir \"{}\" is empty", state_dir));
+        let state_dir = state_dir(args);
+
+        // the lock of the running build is kept
+        match read_dir(&state_dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.file_name() != lock::LOCK_FILE)
+                .try_for_each(|entry| match entry.file_type()?.is_dir() {
+                    true => remove_dir_all(entry.path()),
+                    false => remove_file(entry.path()),
+                })?,
+            Err(_) => println!("State dir \"{}\" is empty", state_dir),
+        }
         Ok(())
     }
 
-    pub fn dump_by_path(&mut self, path: &str) -> Result<(), io::Error> {
+    pub fn dump_by_path(&mut self, path: &str, policy: &SecretsPolicy) -> Result<(), io::Error> {
         if let Some(parent) = PathBuf::from(path).parent() {
             match create_dir_all(parent) {
                 Err(err) if err.kind() != io::ErrorKind::AlreadyExists => {
@@ -105,45 +135,108 @@ impl State {
         }
 
         self.path = path.to_string();
-        let mut encoder = GzEncoder::new(File::create(path)?, Compression::default());
-        let val = &serde_json::to_vec(self)?;
+
+        // write next to the target and rename, so readers never see a
+        // half written state and the `latest` symlink is replaced rather
+        // than written through
+        let tmp = format!("{path}.{}.tmp", std::process::id());
+        let mut encoder = GzEncoder::new(File::create(&tmp)?, Compression::default());
+        let val = &serde_json::to_vec(&self.protected(policy))?;
         encoder.write_all(val)?;
+        encoder.finish()?;
 
-        Ok(())
+        rename(tmp, path)
+    }
+
+    pub fn dump_by_uid(
+        &mut self,
+        state_dir: &str,
+        policy: &SecretsPolicy,
+    ) -> Result<(), io::Error> {
+        self.warn_plaintext_secrets(policy);
+        self.dump_by_path(&format!("{state_dir}/{}.gz", &self.uid), policy)
     }
 
-    pub fn dump_by_uid(&mut self, state_dir: &str) -> Result<(), io::Error> {
-        self.dump_by_path(&format!("{state_dir}/{}.gz", &self.uid))
+    /// Warns if secrets are about to be written in plain text.
+    pub fn warn_plaintext_secrets(&mut self, policy: &SecretsPolicy) {
+        if matches!(policy, SecretsPolicy::Plain) {
+            let secrets = self.plaintext_secrets();
+            if !secrets.is_empty() {
+                warn!(
+                    "state will contain plaintext secrets ({}), use --state-key-file \
+                    to encrypt them or --strip-secrets to leave them out",
+                    secrets.join(", ")
+                );
+            }
+        }
     }
 
-    pub fn symlink_latest(&mut self, latest: &str) -> Result<(), io::Error> {
-        fs::symlink(format!("{}.gz", &self.uid), latest)
+    /// Atomically points `latest` at this state.
+    pub fn symlink_latest(&self, latest: &str) -> Result<(), io::Error> {
+        let tmp = format!("{latest}.{}.tmp", std::process::id());
+        match remove_file(&tmp) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+        fs::symlink(format!("{}.gz", &self.uid), &tmp)?;
+        rename(tmp, latest)
     }
 
+    /// Latest state of the backend selected with `--state-backend`.
     pub fn from_latest(args: &ArgMatches) -> Result<Self, StateError> {
-        let path = format!(
-            "{}/latest.gz",
-            args.get_one::<String>("state-dir")
-                .cloned()
-                .unwrap_or(".geninstate".into())
-        );
-        let file = File::open(&path)?;
+        backend::backend(args)?.latest()
+    }
 
-        let mut buffer = Vec::new();
-        let mut decoder = read::GzDecoder::new(file);
-        decoder.read_to_end(&mut buffer)?;
+    /// Latest state, or none if nothing was built yet. Any other failure to
+    /// read it, like a missing key or a broken file, is an error rather than
+    /// a reason to build from scratch over the history.
+    pub fn latest_if_any(args: &ArgMatches) -> Result<Option<Self>, StateError> {
+        match Self::from_latest(args) {
+            Ok(state) => Ok(Some(state)),
+            Err(StateError::NotFound(_)) => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    pub fn uid(&self) -> &str {
+        &self.uid
+    }
 
-        let mut state: State = serde_json::from_slice(&buffer)?;
+    /// Points `latest` of the backend at the state with given uid, unique
+    /// uid prefix or `previous` and returns that state.
+    pub fn checkout(backend: &dyn StateBackend, uid: &str) -> Result<Self, StateError> {
+        let mut state = match uid {
+            "previous" => backend.previous()?,
+            uid => backend.find(uid)?,
+        };
+        backend.checkout(&state)?;
+        state.reveal(backend.policy())?;
         state.hosts.finalize_failure_domains();
-        state.path = path;
+        if matches!(state.secrets, SecretsMode::Stripped) {
+            warn!(
+                "state {} was saved without secrets, the inventory will not contain them",
+                state.uid
+            );
+        }
 
         Ok(state)
     }
 
+    pub fn print_log_entry(&self) {
+        println!("---");
+        println!("state {}", self.uid);
+        if let Some(parent) = &self.parent {
+            println!("parent {parent}");
+        }
+        println!("{}: {}", self.kind, self.args_str);
+        self.print_changes();
+    }
+
     pub fn print_kind(&self) {
         println!("---");
         println!("{}: {}", self.kind, self.args_str);
         println!("State file: {}", self.path);
+        print!("{}", self.metadata);
     }
 
     pub fn print_changes(&self) {
@@ -162,15 +255,44 @@ impl State {
     }
 }
 
+/// State directory passed with `--state-dir` or `.geninstate` by default.
+/// Every profile keeps its states apart from the others by default.
+pub fn state_dir(args: &ArgMatches) -> String {
+    let state_dir = args.try_get_one::<String>("state-dir").ok().flatten();
+    let profile = args.try_get_one::<String>("profile").ok().flatten();
+    match (state_dir, profile) {
+        (Some(state_dir), _) => state_dir.clone(),
+        (None, Some(profile)) => format!(".geninstate-{profile}"),
+        (None, None) => ".geninstate".into(),
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum StateError {
     #[error("io error")]
     Io(#[from] io::Error),
     #[error("serde error")]
     Serde(#[from] serde_json::Error),
+    #[error("state {0} not found")]
+    NotFound(String),
+    #[error("state reference {0} matches more than one state")]
+    Ambiguous(String),
+    #[error("{0}")]
+    Retention(String),
+    #[error("{0} of the verified states are broken")]
+    Broken(usize),
+    #[error("{0}")]
+    Secrets(String),
+    #[error("git error: {0}")]
+    Git(String),
+    #[error(
+        "state directory is locked by {holder}, \
+        remove {path} if that process is no longer running"
+    )]
+    Locked { path: String, holder: String },
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
 pub enum Change {
     Added(String),
     Removed(String),
@@ -197,6 +319,9 @@ impl Display for Change {
 
 pub struct StateBuilder {
     uid: Option<String>,
+    sources: Option<Vec<PathBuf>>,
+    inventory: Option<String>,
+    parent: Option<String>,
     kind: Option<StateKind>,
     path: Option<String>,
     instances_changes: Option<Vec<Change>>,
@@ -224,6 +349,10 @@ impl StateBuilder {
         })
     }
 
+    pub fn parent(self, parent: Option<String>) -> Self {
+        Self { parent, ..self }
+    }
+
     pub fn make_build_state(self) -> Self {
         Self {
             kind: Some(StateKind::Build),
@@ -280,6 +409,21 @@ impl StateBuilder {
         }
     }
 
+    pub fn sources(self, sources: &[PathBuf]) -> Self {
+        Self {
+            sources: Some(sources.to_vec()),
+            ..self
+        }
+    }
+
+    /// Sha256 of the inventory generated from the state.
+    pub fn inventory(self, inventory: String) -> Self {
+        Self {
+            inventory: Some(inventory),
+            ..self
+        }
+    }
+
     pub fn path(self, path: &str) -> Self {
         Self {
             path: Some(path.into()),
@@ -290,6 +434,7 @@ impl StateBuilder {
     pub fn build(self) -> Result<State, String> {
         Ok(State {
             uid: self.uid.ok_or::<String>("uid is not set".into())?,
+            parent: self.parent,
             args_str: std::env::args().skip(2).collect::<Vec<String>>().join(" "),
             path: self.path.ok_or::<String>("path is not set".into())?,
             kind: self.kind.ok_or::<String>("kind is not set".into())?,
@@ -303,6 +448,10 @@ impl StateBuilder {
             topology: self
                 .topology
                 .ok_or::<String>("topology is not set".into())?,
+            metadata: StateMetadata::current()
+                .with_sources(&self.sources.unwrap_or_default())
+                .with_inventory(self.inventory),
+            secrets: SecretsMode::default(),
         })
     }
 }
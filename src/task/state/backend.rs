@@ -0,0 +1,466 @@
+use std::{
+    fs::{create_dir_all, read_dir, read_to_string, write},
+    io,
+    path::{Path, PathBuf},
+    process::Command,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use clap::ArgMatches;
+use log::warn;
+
+use crate::task::state::metadata::StateMetadata;
+use crate::task::state::retention::{self, RetentionPolicy, Verification};
+use crate::task::state::secrets::SecretsPolicy;
+use crate::task::state::{state_dir, State, StateError};
+use crate::task::utils::uncolorize;
+
+/// Storage of the state history. States returned by the history are
+/// sealed, their secrets are decrypted with [`State::reveal`] and the
+/// policy of the backend when needed.
+pub trait StateBackend {
+    /// Path the latest state is stored at. Upgrades exported with
+    /// `--export-state` are saved elsewhere and do not move `latest`.
+    fn latest_path(&self) -> String;
+
+    /// Policy the states are saved and decrypted with.
+    fn policy(&self) -> &SecretsPolicy;
+
+    /// Latest state without decrypting its secrets, `NotFound` if nothing
+    /// was saved yet.
+    fn latest_sealed(&self) -> Result<State, StateError>;
+
+    fn latest(&self) -> Result<State, StateError> {
+        let mut state = self.latest_sealed()?;
+        state.reveal(self.policy())?;
+        state.hosts.finalize_failure_domains();
+        state.path = self.latest_path();
+
+        Ok(state)
+    }
+
+    /// Saves the state to the history and to `state.path`.
+    fn save(&self, state: &mut State) -> Result<(), StateError>;
+
+    /// States with the time they were created, most recent first.
+    fn history(&self) -> Result<Vec<(SystemTime, State)>, StateError>;
+
+    /// State with given uid or unique uid prefix.
+    fn find(&self, uid: &str) -> Result<State, StateError> {
+        let mut candidates = self
+            .history()?
+            .into_iter()
+            .map(|(_, state)| state)
+            .filter(|state| state.uid.starts_with(uid))
+            .collect::<Vec<State>>();
+        if let Some(position) = candidates.iter().position(|state| state.uid == uid) {
+            return Ok(candidates.swap_remove(position));
+        }
+
+        match candidates.len() {
+            0 => Err(StateError::NotFound(uid.into())),
+            1 => Ok(candidates.remove(0)),
+            _ => Err(StateError::Ambiguous(uid.into())),
+        }
+    }
+
+    /// Points `latest` at the state from the history.
+    fn checkout(&self, state: &State) -> Result<(), StateError>;
+
+    /// Removes the states the retention policy does not keep and returns
+    /// them, nothing is removed with `dry_run`.
+    fn prune(&self, policy: &RetentionPolicy, dry_run: bool) -> Result<Vec<PathBuf>, StateError>;
+
+    /// Reads every state of the history and the latest one.
+    fn verify(&self) -> Result<Vec<Verification>, StateError>;
+
+    /// The state the latest one was derived from. States written before
+    /// parents were recorded fall back to the most recent state that
+    /// differs from the latest one.
+    fn previous(&self) -> Result<State, StateError> {
+        let latest = self.latest_sealed()?;
+        if let Some(parent) = &latest.parent {
+            return self.find(parent);
+        }
+
+        self.history()?
+            .into_iter()
+            .map(|(_, state)| state)
+            .find(|state| state.uid != latest.uid)
+            .ok_or_else(|| StateError::NotFound("previous".into()))
+    }
+
+    /// Chain of states starting from the given one and following the parents.
+    fn log(&self, uid: &str) -> Result<Vec<State>, StateError> {
+        let mut state = match uid {
+            "latest" => self.latest_sealed()?,
+            uid => self.find(uid)?,
+        };
+        let mut chain = Vec::new();
+
+        while let Some(parent) = state.parent.clone() {
+            if chain.iter().any(|state: &State| state.uid == parent) || state.uid == parent {
+                break;
+            }
+            let next = match self.find(&parent) {
+                Ok(next) => next,
+                Err(StateError::NotFound(_)) => break,
+                Err(err) => {
+                    warn!("log stops at unreadable state {parent}: {err}");
+                    break;
+                }
+            };
+            chain.push(std::mem::replace(&mut state, next));
+        }
+        chain.push(state);
+
+        Ok(chain)
+    }
+}
+
+/// Backend selected with `--state-backend`, gzip directory by default.
+pub fn backend(args: &ArgMatches) -> Result<Box<dyn StateBackend>, StateError> {
+    let dir = state_dir(args);
+    let policy = SecretsPolicy::try_from(args)?;
+    match args
+        .try_get_one::<String>("state-backend")
+        .ok()
+        .flatten()
+        .map(String::as_str)
+    {
+        Some("git") => Ok(Box::new(GitRepo { dir, policy })),
+        _ => Ok(Box::new(GzipDir { dir, policy })),
+    }
+}
+
+/// Directory of gzipped json states named by uid with `latest.gz`
+/// pointing at the current one.
+pub struct GzipDir {
+    pub dir: String,
+    pub policy: SecretsPolicy,
+}
+
+impl GzipDir {
+    /// Paths of all states stored in the directory (without `latest.gz`)
+    /// with the time each state was created, most recent first. The time
+    /// is taken from the state metadata, so the order survives a git
+    /// checkout, states without metadata fall back to file mtime.
+    pub fn paths_with_time(&self) -> Result<Vec<(SystemTime, PathBuf)>, StateError> {
+        let mut entries = read_dir(&self.dir)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| {
+                entry
+                    .file_type()
+                    .map(|file_type| file_type.is_file())
+                    .unwrap_or_default()
+                    && entry.file_name() != "latest.gz"
+                    && entry
+                        .path()
+                        .extension()
+                        .map(|ext| ext == "gz")
+                        .unwrap_or_default()
+            })
+            .map(|entry| {
+                let modified = entry.metadata()?.modified()?;
+                let created = StateMetadata::read(&entry.path())
+                    .ok()
+                    .filter(|metadata| !metadata.is_empty())
+                    .map(|metadata| UNIX_EPOCH + Duration::from_secs(metadata.timestamp))
+                    .unwrap_or(modified);
+                Ok(((created, modified), entry.path()))
+            })
+            .collect::<Result<Vec<((SystemTime, SystemTime), PathBuf)>, io::Error>>()?;
+
+        // states created within the same second are ordered by mtime
+        entries.sort_by(|left, right| right.cmp(left));
+
+        Ok(entries
+            .into_iter()
+            .map(|((created, _), path)| (created, path))
+            .collect())
+    }
+
+    /// Path of the state with given uid or unique uid prefix.
+    pub fn path_by_uid(&self, uid: &str) -> Result<PathBuf, StateError> {
+        let path = Path::new(&self.dir).join(format!("{uid}.gz"));
+        if path.is_file() {
+            return Ok(path);
+        }
+
+        let mut candidates = self
+            .paths_with_time()?
+            .into_iter()
+            .map(|(_, path)| path)
+            .filter(|path| {
+                path.file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .map(|stem| stem.starts_with(uid))
+                    .unwrap_or_default()
+            })
+            .collect::<Vec<PathBuf>>();
+
+        match candidates.len() {
+            0 => Err(StateError::NotFound(uid.into())),
+            1 => Ok(candidates.remove(0)),
+            _ => Err(StateError::Ambiguous(uid.into())),
+        }
+    }
+
+    fn read(path: PathBuf) -> Result<State, StateError> {
+        let mut state = State::read_sealed(&path)?;
+        state.path = path.to_string_lossy().into();
+        Ok(state)
+    }
+}
+
+impl StateBackend for GzipDir {
+    fn latest_path(&self) -> String {
+        Path::new(&self.dir)
+            .join("latest.gz")
+            .to_string_lossy()
+            .into()
+    }
+
+    fn policy(&self) -> &SecretsPolicy {
+        &self.policy
+    }
+
+    fn latest_sealed(&self) -> Result<State, StateError> {
+        let path = PathBuf::from(self.latest_path());
+        if path.symlink_metadata().is_err() {
+            return Err(StateError::NotFound("latest".into()));
+        }
+        State::read_sealed(&path)
+    }
+
+    fn save(&self, state: &mut State) -> Result<(), StateError> {
+        let path = state.path.clone();
+        state.dump_by_uid(&self.dir, &self.policy)?;
+
+        if path == self.latest_path() {
+            state.symlink_latest(&path)?;
+        } else {
+            state.dump_by_path(&path, &self.policy)?;
+        }
+
+        Ok(())
+    }
+
+    /// Unreadable states are left out with a warning, `genin state verify`
+    /// reports them.
+    fn history(&self) -> Result<Vec<(SystemTime, State)>, StateError> {
+        Ok(self
+            .paths_with_time()?
+            .into_iter()
+            .filter_map(|(created, path)| match Self::read(path.clone()) {
+                Ok(state) => Some((created, state)),
+                Err(err) => {
+                    warn!("skipping unreadable state {}: {err}", path.display());
+                    None
+                }
+            })
+            .collect())
+    }
+
+    fn find(&self, uid: &str) -> Result<State, StateError> {
+        Self::read(self.path_by_uid(uid)?)
+    }
+
+    fn checkout(&self, state: &State) -> Result<(), StateError> {
+        Ok(state.symlink_latest(&self.latest_path())?)
+    }
+
+    fn prune(&self, policy: &RetentionPolicy, dry_run: bool) -> Result<Vec<PathBuf>, StateError> {
+        retention::prune(self, policy, dry_run)
+    }
+
+    fn verify(&self) -> Result<Vec<Verification>, StateError> {
+        retention::verify(self)
+    }
+}
+
+/// Local git repository with a single pretty printed `state.json`,
+/// every build or upgrade is a commit.
+pub struct GitRepo {
+    pub dir: String,
+    pub policy: SecretsPolicy,
+}
+
+impl GitRepo {
+    fn git(&self, args: &[&str]) -> Result<String, StateError> {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(&self.dir)
+            .args(args)
+            .output()?;
+
+        if !output.status.success() {
+            return Err(StateError::Git(
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into())
+    }
+
+    /// Commits that changed `state.json` with their commit time, most
+    /// recent first.
+    fn commits(&self) -> Result<Vec<(SystemTime, String)>, StateError> {
+        Ok(self
+            .git(&["log", "--format=%H %ct", "--", "state.json"])?
+            .lines()
+            .filter_map(|line| {
+                let (commit, time) = line.split_once(' ')?;
+                let time = UNIX_EPOCH + Duration::from_secs(time.parse().ok()?);
+                Some((time, commit.to_string()))
+            })
+            .collect())
+    }
+
+    /// State as it was saved by the commit, its path is the git revision.
+    fn read(&self, commit: &str) -> Result<State, StateError> {
+        let revision = format!("{commit}:state.json");
+        let mut state: State = serde_json::from_str(&self.git(&["show", &revision])?)?;
+        state.path = revision;
+        Ok(state)
+    }
+
+    fn commit(&self, message: &str) -> Result<(), StateError> {
+        self.git(&["add", "state.json"])?;
+        self.git(&["commit", "-q", "--allow-empty", "-m", message])?;
+        Ok(())
+    }
+
+    fn commit_message(state: &State) -> String {
+        let mut message = vec![
+            format!("{}: {}", uncolorize(&state.kind), state.args_str),
+            String::new(),
+            format!("state {}", state.uid),
+        ];
+        if let Some(parent) = &state.parent {
+            message.push(format!("parent {parent}"));
+        }
+        if !state.metadata.sources.is_empty() {
+            message.push("Sources:".into());
+            message.extend(
+                state
+                    .metadata
+                    .sources
+                    .iter()
+                    .map(|(path, hash)| format!("  {path} sha256:{hash}")),
+            );
+        }
+        if let Some(inventory) = &state.metadata.inventory {
+            message.push(format!("Inventory: sha256:{inventory}"));
+        }
+        if !state.instances_changes.is_empty() {
+            message.push("Topology changes:".into());
+            message.extend(state.instances_changes.iter().map(uncolorize));
+        }
+        if !state.hosts_changes.is_empty() {
+            message.push("Hosts changes:".into());
+            message.extend(state.hosts_changes.iter().map(uncolorize));
+        }
+        message.join("\n")
+    }
+}
+
+impl StateBackend for GitRepo {
+    fn latest_path(&self) -> String {
+        Path::new(&self.dir)
+            .join("state.json")
+            .to_string_lossy()
+            .into()
+    }
+
+    fn policy(&self) -> &SecretsPolicy {
+        &self.policy
+    }
+
+    fn latest_sealed(&self) -> Result<State, StateError> {
+        if !Path::new(&self.latest_path()).exists() {
+            return Err(StateError::NotFound("latest".into()));
+        }
+        Ok(serde_json::from_str(&read_to_string(self.latest_path())?)?)
+    }
+
+    fn save(&self, state: &mut State) -> Result<(), StateError> {
+        if state.path != self.latest_path() {
+            return Ok(state.dump_by_path(&state.path.clone(), &self.policy)?);
+        }
+
+        create_dir_all(&self.dir)?;
+        if !Path::new(&self.dir).join(".git").exists() {
+            self.git(&["init", "-q"])?;
+        }
+
+        state.warn_plaintext_secrets(&self.policy);
+        let protected = state.protected(&self.policy);
+        write(
+            &state.path,
+            format!("{}\n", serde_json::to_string_pretty(&protected)?),
+        )?;
+
+        self.commit(&Self::commit_message(state))
+    }
+
+    /// A state checked out again is a new commit, only its most recent
+    /// commit is kept in the history.
+    fn history(&self) -> Result<Vec<(SystemTime, State)>, StateError> {
+        let mut history: Vec<(SystemTime, State)> = Vec::new();
+        for (committed, commit) in self.commits()? {
+            let state = self.read(&commit)?;
+            if history.iter().any(|(_, seen)| seen.uid == state.uid) {
+                continue;
+            }
+            let created = match state.metadata.is_empty() {
+                true => committed,
+                false => UNIX_EPOCH + Duration::from_secs(state.metadata.timestamp),
+            };
+            history.push((created, state));
+        }
+        Ok(history)
+    }
+
+    /// Commits the state as it was saved, encrypted secrets stay encrypted.
+    fn checkout(&self, state: &State) -> Result<(), StateError> {
+        let mut state = state.clone();
+        state.path = self.latest_path();
+        write(
+            &state.path,
+            format!("{}\n", serde_json::to_string_pretty(&state)?),
+        )?;
+        self.commit(&format!("Checkout: {}\n\nstate {}", state.uid, state.uid))
+    }
+
+    fn prune(&self, _: &RetentionPolicy, _: bool) -> Result<Vec<PathBuf>, StateError> {
+        Err(StateError::Retention(
+            "states of the git backend are commits, prune the repository history with git".into(),
+        ))
+    }
+
+    fn verify(&self) -> Result<Vec<Verification>, StateError> {
+        let mut report = self
+            .commits()?
+            .into_iter()
+            .map(|(_, commit)| {
+                let path = PathBuf::from(format!("{commit}:state.json"));
+                match self.read(&commit) {
+                    Ok(_) => Verification::Ok(path),
+                    Err(err) => Verification::Broken(path, err.to_string()),
+                }
+            })
+            .collect::<Vec<Verification>>();
+
+        let latest = PathBuf::from(self.latest_path());
+        report.push(match self.latest_sealed() {
+            Ok(_) => Verification::Ok(latest),
+            Err(_) if !latest.is_file() => Verification::MissingLatest(latest),
+            Err(err) => Verification::Broken(latest, err.to_string()),
+        });
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod test;
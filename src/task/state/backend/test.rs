@@ -0,0 +1,101 @@
+use std::fs::{read_link, remove_dir_all, write};
+use std::path::PathBuf;
+
+use crate::task::cluster::Cluster;
+use crate::task::state::backend::{GitRepo, GzipDir, StateBackend};
+use crate::task::state::retention::RetentionPolicy;
+use crate::task::state::secrets::SecretsPolicy;
+use crate::task::state::{Change, State};
+
+fn state(path: &str) -> State {
+    state_with_parent(path, "f5d95f27")
+}
+
+fn state_with_parent(path: &str, parent: &str) -> State {
+    let source = PathBuf::from("tests/resources/cluster.genin.yml");
+    let cluster = Cluster::read(&source, None).unwrap();
+
+    State::builder()
+        .uid(vec![source.clone()])
+        .unwrap()
+        .sources(&[source])
+        .make_upgrade_state()
+        .parent(Some(parent.into()))
+        .path(path)
+        .instances_changes(vec![
+            Change::Added("router-3".into()),
+            Change::Removed("stateboard".into()),
+        ])
+        .hosts_changes(vec![Change::Added("server-3".into())])
+        .hosts(&cluster.hosts)
+        .vars(&cluster.vars)
+        .failover(&cluster.failover)
+        .topology(&cluster.topology)
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn gzip_backend_save_and_latest() {
+    let backend = GzipDir {
+        dir: "tests/.gzip_backend_save_and_latest".into(),
+        policy: SecretsPolicy::Plain,
+    };
+    let _ = remove_dir_all(&backend.dir);
+
+    let mut saved = state(&backend.latest_path());
+    backend.save(&mut saved).unwrap();
+
+    assert_eq!(
+        read_link(backend.latest_path()).unwrap(),
+        PathBuf::from(format!("{}.gz", saved.uid()))
+    );
+
+    let latest = backend.latest().unwrap();
+    assert_eq!(latest, saved);
+    assert_eq!(latest.path, backend.latest_path());
+    assert_eq!(latest.metadata, saved.metadata);
+    assert!(latest
+        .metadata
+        .sources
+        .contains_key("tests/resources/cluster.genin.yml"));
+
+    remove_dir_all(&backend.dir).unwrap();
+}
+
+#[test]
+fn gzip_backend_skips_unreadable_states() {
+    let backend = GzipDir {
+        dir: "tests/.gzip_backend_skips_unreadable_states".into(),
+        policy: SecretsPolicy::Plain,
+    };
+    let _ = remove_dir_all(&backend.dir);
+
+    let mut saved = state_with_parent(&backend.latest_path(), "0badc0de");
+    backend.save(&mut saved).unwrap();
+    write(format!("{}/0badc0de.gz", backend.dir), "not a state").unwrap();
+
+    let history = backend.history().unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].1, saved);
+    assert_eq!(backend.find(saved.uid()).unwrap(), saved);
+    assert_eq!(backend.log("latest").unwrap(), vec![saved.clone()]);
+
+    // the parent of latest can't be read, so prune doesn't know what to keep
+    let policy = RetentionPolicy {
+        keep_last: Some(1),
+        ..RetentionPolicy::default()
+    };
+    assert!(backend.prune(&policy, false).is_err());
+    assert!(PathBuf::from(format!("{}/0badc0de.gz", backend.dir)).is_file());
+
+    remove_dir_all(&backend.dir).unwrap();
+}
+
+#[test]
+fn git_backend_commit_message() {
+    let mut state = state("state.json");
+    state.args_str = "-s tests/resources/cluster-new.genin.yml".into();
+
+    insta::assert_display_snapshot!(GitRepo::commit_message(&state));
+}
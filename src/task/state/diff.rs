@@ -0,0 +1,440 @@
+use std::fmt::Display;
+use std::path::PathBuf;
+
+use clap::ArgMatches;
+use indexmap::IndexMap;
+use serde::Serialize;
+use serde_yaml::Value;
+
+use crate::task::cluster::host::hst::Host;
+use crate::task::cluster::host::view::{FG_BRIGHT_BLACK, FG_GREEN, FG_RED, FG_YELLOW};
+use crate::task::cluster::instance::ins::Instance;
+use crate::task::cluster::{Cluster, ClusterError};
+use crate::task::state::backend::StateBackend;
+use crate::task::state::secrets::{is_secret, REDACTED};
+use crate::task::state::{Change, State, StateError};
+
+/// Field by field difference between two cluster snapshots.
+///
+/// Each side of the diff may be a saved state or a `cluster.genin.yml`
+/// that was not built yet.
+/// ```yaml
+/// instances:
+///   - name: storage-1-1
+///     change: Changed
+///     fields:
+///       - field: host
+///         old: server-1
+///         new: server-2
+/// hosts: []
+/// vars: []
+/// failover: []
+/// ```
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+pub struct StateDiff {
+    pub instances: Vec<MemberDiff>,
+    pub hosts: Vec<MemberDiff>,
+    pub vars: Vec<FieldDiff>,
+    pub failover: Vec<FieldDiff>,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct MemberDiff {
+    pub name: String,
+    pub change: DiffKind,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<FieldDiff>,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub enum DiffKind {
+    Added,
+    Removed,
+    Changed,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct FieldDiff {
+    pub field: String,
+    pub old: Option<Value>,
+    pub new: Option<Value>,
+}
+
+impl StateDiff {
+    pub fn between(old: &Cluster, new: &Cluster) -> Self {
+        Self {
+            instances: diff_members(instances_by_name(old), instances_by_name(new)),
+            hosts: diff_members(hosts_by_name(old), hosts_by_name(new)),
+            vars: diff_serialized(&vars_without_failover(old), &vars_without_failover(new)),
+            failover: diff_serialized(&old.failover, &new.failover),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+            && self.hosts.is_empty()
+            && self.vars.is_empty()
+            && self.failover.is_empty()
+    }
+
+    pub fn print(&self, args: &ArgMatches) -> Result<(), StateError> {
+        match args.get_one::<String>("format").map(String::as_str) {
+            Some("json") => println!("{}", serde_json::to_string_pretty(self)?),
+            _ => print!("{self}"),
+        }
+        Ok(())
+    }
+}
+
+impl Display for StateDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "No changes");
+        }
+
+        for (title, members) in [("Instances", &self.instances), ("Hosts", &self.hosts)] {
+            if !members.is_empty() {
+                writeln!(f, "{title}:")?;
+                members
+                    .iter()
+                    .try_for_each(|member| write!(f, "{member}"))?;
+            }
+        }
+
+        for (title, fields) in [("Vars", &self.vars), ("Failover", &self.failover)] {
+            if !fields.is_empty() {
+                writeln!(f, "{title}:")?;
+                fields
+                    .iter()
+                    .try_for_each(|field| writeln!(f, "  {field}"))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for MemberDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (sign, color) = match self.change {
+            DiffKind::Added => ('+', FG_GREEN),
+            DiffKind::Removed => ('-', FG_RED),
+            DiffKind::Changed => ('~', FG_YELLOW),
+        };
+        writeln!(
+            f,
+            "  {}{sign} {}{}",
+            color.get_prefix(),
+            self.name,
+            color.get_suffix()
+        )?;
+        self.fields
+            .iter()
+            .try_for_each(|field| writeln!(f, "      {field}"))
+    }
+}
+
+impl Display for FieldDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {}{}{} -> {}{}{}",
+            self.field,
+            FG_RED.get_prefix(),
+            as_text(&self.old),
+            FG_RED.get_suffix(),
+            FG_GREEN.get_prefix(),
+            as_text(&self.new),
+            FG_GREEN.get_suffix(),
+        )
+    }
+}
+
+fn as_text(value: &Option<Value>) -> String {
+    match value {
+        Some(Value::String(value)) => value.clone(),
+        Some(value) => serde_json::to_string(value).unwrap_or_default(),
+        None => format!(
+            "{}<none>{}",
+            FG_BRIGHT_BLACK.get_prefix(),
+            FG_BRIGHT_BLACK.get_suffix()
+        ),
+    }
+}
+
+/// Changes `build --dry-run` would make to the latest state: the same
+/// instance and host changes the upgrade state records, plus instances
+/// moved or reconfigured and changed vars.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Plan {
+    pub instances_changes: Vec<Change>,
+    pub hosts_changes: Vec<Change>,
+    pub diff: StateDiff,
+}
+
+impl Plan {
+    /// Plan of the merged cluster against the latest state, or of a new
+    /// state when there is no previous one.
+    pub fn new(latest: Option<&Cluster>, merged: &Cluster, hosts_changes: Vec<Change>) -> Self {
+        match latest {
+            Some(latest) => {
+                let mut diff = StateDiff::between(latest, merged);
+                // additions and removals are already in the change lists
+                diff.instances
+                    .retain(|member| member.change == DiffKind::Changed);
+                diff.hosts
+                    .retain(|member| member.change == DiffKind::Changed);
+                Self {
+                    instances_changes: merged.instances_changes(),
+                    hosts_changes,
+                    diff,
+                }
+            }
+            None => Self {
+                instances_changes: instances_by_name(merged)
+                    .into_keys()
+                    .map(Change::Added)
+                    .collect(),
+                hosts_changes: hosts_by_name(merged)
+                    .into_keys()
+                    .map(Change::Added)
+                    .collect(),
+                diff: StateDiff::default(),
+            },
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances_changes.is_empty() && self.hosts_changes.is_empty() && self.diff.is_empty()
+    }
+}
+
+impl Display for Plan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "No changes");
+        }
+
+        for (title, changes) in [
+            ("Topology changes", &self.instances_changes),
+            ("Hosts changes", &self.hosts_changes),
+        ] {
+            if !changes.is_empty() {
+                writeln!(f, "{title}:")?;
+                changes
+                    .iter()
+                    .try_for_each(|change| writeln!(f, "{change}"))?;
+            }
+        }
+
+        if !self.diff.is_empty() {
+            write!(f, "{}", self.diff)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Loads one side of the diff by reference. The reference may be
+/// `latest`, `previous`, a state uid (or its unique prefix), a path to
+/// a state file or a path to a cluster configuration, which is read with
+/// the overlay of the profile.
+pub fn snapshot(
+    backend: &dyn StateBackend,
+    reference: &str,
+    profile: Option<&str>,
+) -> Result<Cluster, ClusterError> {
+    let path = PathBuf::from(reference);
+    let mut state = match reference {
+        "latest" => return Ok(backend.latest()?.into()),
+        "previous" => backend.previous()?,
+        _ if reference.ends_with(".yml") || reference.ends_with(".yaml") => {
+            return Cluster::read(&path, profile)
+        }
+        _ if path.is_file() => State::read_sealed(&path)?,
+        uid => backend.find(uid)?,
+    };
+    state.reveal(backend.policy())?;
+    Ok(state.into())
+}
+
+fn instances_by_name(cluster: &Cluster) -> IndexMap<String, (String, Value)> {
+    cluster
+        .hosts
+        .lower_level_hosts()
+        .into_iter()
+        .flat_map(|host| {
+            host.instances.iter().map(move |instance| {
+                (
+                    instance.name.to_string(),
+                    (host.name.to_string(), instance_as_value(instance)),
+                )
+            })
+        })
+        .collect()
+}
+
+fn instance_as_value(instance: &Instance) -> Value {
+    let mut value = serde_yaml::to_value(instance).unwrap_or_default();
+    if let Value::Mapping(mapping) = &mut value {
+        mapping.remove(&Value::String("name".into()));
+    }
+    value
+}
+
+/// Leaf hosts with the name of the domain they belong to, so that moving
+/// a server between datacenters is reported like an instance move.
+fn hosts_by_name(cluster: &Cluster) -> IndexMap<String, (String, Value)> {
+    fn collect(host: &Host, parent: &str, hosts: &mut IndexMap<String, (String, Value)>) {
+        if host.hosts.is_empty() {
+            hosts.insert(
+                host.name.to_string(),
+                (
+                    parent.to_string(),
+                    serde_yaml::to_value(&host.config).unwrap_or_default(),
+                ),
+            );
+        }
+        host.hosts
+            .iter()
+            .for_each(|child| collect(child, &host.name.to_string(), hosts));
+    }
+
+    let mut hosts = IndexMap::new();
+    collect(&cluster.hosts, "", &mut hosts);
+    hosts
+}
+
+fn vars_without_failover(cluster: &Cluster) -> Value {
+    let mut vars = cluster.vars.clone();
+    vars.cartridge_failover_params = None;
+    serde_yaml::to_value(vars).unwrap_or_default()
+}
+
+fn diff_members(
+    old: IndexMap<String, (String, Value)>,
+    new: IndexMap<String, (String, Value)>,
+) -> Vec<MemberDiff> {
+    let mut diff: Vec<MemberDiff> = old
+        .iter()
+        .filter(|(name, _)| !new.contains_key(*name))
+        .map(|(name, _)| MemberDiff {
+            name: name.clone(),
+            change: DiffKind::Removed,
+            fields: Vec::new(),
+        })
+        .collect();
+
+    for (name, (new_parent, new_member)) in new.iter() {
+        match old.get(name) {
+            None => diff.push(MemberDiff {
+                name: name.clone(),
+                change: DiffKind::Added,
+                fields: Vec::new(),
+            }),
+            Some((old_parent, old_member)) => {
+                let mut fields = Vec::new();
+                if old_parent != new_parent {
+                    fields.push(FieldDiff {
+                        field: "host".into(),
+                        old: Some(Value::String(old_parent.clone())),
+                        new: Some(Value::String(new_parent.clone())),
+                    });
+                }
+                fields.extend(diff_serialized(old_member, new_member));
+                if !fields.is_empty() {
+                    diff.push(MemberDiff {
+                        name: name.clone(),
+                        change: DiffKind::Changed,
+                        fields,
+                    });
+                }
+            }
+        }
+    }
+
+    diff
+}
+
+/// Compare two serializable values key by key, descending into nested
+/// mappings and naming fields with a dotted path.
+pub fn diff_serialized<T: Serialize>(old: &T, new: &T) -> Vec<FieldDiff> {
+    let mut fields = Vec::new();
+    diff_values(
+        "",
+        &serde_yaml::to_value(old).unwrap_or_default(),
+        &serde_yaml::to_value(new).unwrap_or_default(),
+        &mut fields,
+    );
+    fields
+}
+
+fn diff_values(path: &str, old: &Value, new: &Value, fields: &mut Vec<FieldDiff>) {
+    match (old, new) {
+        (Value::Mapping(old), Value::Mapping(new)) => {
+            let keys = old
+                .iter()
+                .chain(new.iter())
+                .map(|(key, _)| key.clone())
+                .collect::<Vec<Value>>();
+            let mut seen = Vec::new();
+            for key in keys {
+                if seen.contains(&key) {
+                    continue;
+                }
+                let name = key
+                    .as_str()
+                    .map(String::from)
+                    .unwrap_or_else(|| serde_json::to_string(&key).unwrap_or_default());
+                let path = if path.is_empty() {
+                    name
+                } else {
+                    format!("{path}.{name}")
+                };
+                match (old.get(&key), new.get(&key)) {
+                    (Some(old), Some(new)) => diff_values(&path, old, new, fields),
+                    (old, new) => fields.push(FieldDiff {
+                        old: old.map(|old| redact(&path, old.clone())),
+                        new: new.map(|new| redact(&path, new.clone())),
+                        field: path,
+                    }),
+                }
+                seen.push(key);
+            }
+        }
+        (old, new) if old != new => fields.push(FieldDiff {
+            field: path.to_string(),
+            old: Some(old.clone())
+                .filter(|value| !value.is_null())
+                .map(|old| redact(path, old)),
+            new: Some(new.clone())
+                .filter(|value| !value.is_null())
+                .map(|new| redact(path, new)),
+        }),
+        _ => {}
+    }
+}
+
+/// Replaces secrets in the value of the field, nested ones included, so
+/// that diffs and plans only tell that a secret was set or changed.
+fn redact(path: &str, value: Value) -> Value {
+    match value {
+        _ if is_secret(path) => Value::String(REDACTED.into()),
+        Value::Mapping(mapping) => Value::Mapping(
+            mapping
+                .into_iter()
+                .map(|(key, value)| {
+                    let field = match key.as_str() {
+                        Some(name) => format!("{path}.{name}"),
+                        None => path.to_string(),
+                    };
+                    (key, redact(&field, value))
+                })
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+#[cfg(test)]
+mod test;
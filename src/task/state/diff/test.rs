@@ -0,0 +1,135 @@
+use crate::task::state::backend::GzipDir;
+use crate::task::state::diff::{snapshot, Plan, StateDiff};
+use crate::task::state::secrets::SecretsPolicy;
+use crate::task::state::StateError;
+use crate::task::{cluster::ClusterError, utils::uncolorize};
+
+fn backend(dir: &str) -> GzipDir {
+    GzipDir {
+        dir: dir.into(),
+        policy: SecretsPolicy::Plain,
+    }
+}
+
+#[test]
+fn diff_between_cluster_configs() {
+    let old = snapshot(
+        &backend(".geninstate"),
+        "tests/resources/cluster.genin.yml",
+        None,
+    )
+    .unwrap();
+    let new = snapshot(
+        &backend(".geninstate"),
+        "tests/resources/cluster-new.genin.yml",
+        None,
+    )
+    .unwrap();
+
+    let diff = StateDiff::between(&old, &new);
+
+    insta::assert_yaml_snapshot!(diff);
+    insta::assert_display_snapshot!("diff_between_cluster_configs_text", uncolorize(diff));
+}
+
+#[test]
+fn diff_redacts_secrets() {
+    let old = snapshot(
+        &backend(".geninstate"),
+        "tests/resources/cluster.genin.yml",
+        None,
+    )
+    .unwrap();
+    let new = snapshot(
+        &backend(".geninstate"),
+        "tests/resources/cluster-new.genin.yml",
+        None,
+    )
+    .unwrap();
+
+    let diff = StateDiff::between(&old, &new);
+    let text = uncolorize(&diff);
+    let json = serde_json::to_string(&diff).unwrap();
+
+    assert!(text.contains("ansible_password: <changed> -> <changed>"));
+    assert!(!text.contains("\"password\":\"password\""));
+    assert!(json
+        .contains("{\"field\":\"ansible_password\",\"old\":\"<changed>\",\"new\":\"<changed>\"}"));
+    assert!(!json.contains("\"password\":\"password\""));
+}
+
+#[test]
+fn diff_of_same_cluster_is_empty() {
+    let old = snapshot(
+        &backend(".geninstate"),
+        "tests/resources/cluster.genin.yml",
+        None,
+    )
+    .unwrap();
+    let new = snapshot(
+        &backend(".geninstate"),
+        "tests/resources/cluster.genin.yml",
+        None,
+    )
+    .unwrap();
+
+    let diff = StateDiff::between(&old, &new);
+
+    assert!(diff.is_empty());
+    assert_eq!(diff.to_string(), "No changes\n");
+}
+
+#[test]
+fn diff_unknown_state_uid() {
+    assert!(matches!(
+        snapshot(&backend("tests/resources"), "0123456789abcdef", None),
+        Err(ClusterError::State(StateError::NotFound(_)))
+    ));
+}
+
+#[test]
+fn plan_of_merged_cluster() {
+    let latest = snapshot(
+        &backend(".geninstate"),
+        "tests/resources/cluster.genin.yml",
+        None,
+    )
+    .unwrap();
+    let mut old = snapshot(
+        &backend(".geninstate"),
+        "tests/resources/cluster.genin.yml",
+        None,
+    )
+    .unwrap();
+    let mut new = snapshot(
+        &backend(".geninstate"),
+        "tests/resources/cluster-new.genin.yml",
+        None,
+    )
+    .unwrap();
+
+    old.hosts.clear_view();
+    let hosts_diff = old.merge(&mut new, false).unwrap();
+
+    let plan = uncolorize(Plan::new(Some(&latest), &old, hosts_diff));
+    assert!(plan.contains("ansible_password: <changed> -> <changed>"));
+    assert!(!plan.contains("\"password\":\"password\""));
+
+    insta::assert_display_snapshot!(plan);
+}
+
+#[test]
+fn plan_without_latest_state() {
+    let cluster = snapshot(
+        &backend(".geninstate"),
+        "tests/resources/cluster.genin.yml",
+        None,
+    )
+    .unwrap();
+
+    let plan = Plan::new(None, &cluster, Vec::new());
+
+    assert!(plan.diff.is_empty());
+    assert_eq!(plan.instances_changes.len(), 7);
+    assert_eq!(plan.hosts_changes.len(), 2);
+}
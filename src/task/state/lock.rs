@@ -0,0 +1,130 @@
+use std::{
+    fmt::Display,
+    fs::{create_dir_all, read_to_string, remove_file, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+    process::{self, Command},
+};
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::task::state::StateError;
+use crate::task::utils::{current_user, format_unix, unix_now};
+
+pub const LOCK_FILE: &str = ".lock";
+
+/// Exclusive lock on the state directory. It is held for the whole
+/// read-merge-write cycle and released when dropped. A lock left behind by a
+/// process that is no longer running, after a crash or a Ctrl-C, is stale and
+/// taken over by the next process.
+#[derive(Debug)]
+pub struct StateLock {
+    path: PathBuf,
+}
+
+/// Process holding the lock, stored in the lock file.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct LockHolder {
+    pub pid: u32,
+    pub user: String,
+    /// Seconds since unix epoch
+    pub started: u64,
+}
+
+impl LockHolder {
+    fn current() -> Self {
+        Self {
+            pid: process::id(),
+            user: current_user(),
+            started: unix_now(),
+        }
+    }
+}
+
+impl Display for LockHolder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "pid {} of user {} since {}",
+            self.pid,
+            self.user,
+            format_unix(self.started)
+        )
+    }
+}
+
+impl StateLock {
+    pub fn acquire(state_dir: &str) -> Result<Self, StateError> {
+        create_dir_all(state_dir)?;
+        let path = PathBuf::from(state_dir).join(LOCK_FILE);
+
+        match Self::create(&path) {
+            Err(StateError::Locked { .. }) => {
+                // the holder may not have written its info yet
+                let holder = read_to_string(&path)
+                    .ok()
+                    .and_then(|holder| serde_json::from_str::<LockHolder>(&holder).ok());
+                match holder {
+                    Some(holder) if !is_running(holder.pid) => {
+                        warn!("removing stale lock {} of {holder}", path.display());
+                        remove_file(&path)?;
+                        Self::create(&path)
+                    }
+                    holder => Err(StateError::Locked {
+                        path: path.display().to_string(),
+                        holder: holder
+                            .map(|holder| holder.to_string())
+                            .unwrap_or_else(|| "unknown process".into()),
+                    }),
+                }
+            }
+            result => result,
+        }
+    }
+
+    fn create(path: &PathBuf) -> Result<Self, StateError> {
+        match OpenOptions::new().write(true).create_new(true).open(path) {
+            Ok(mut file) => {
+                file.write_all(&serde_json::to_vec(&LockHolder::current())?)?;
+                debug!("state directory of {} locked", path.display());
+                Ok(Self { path: path.clone() })
+            }
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => Err(StateError::Locked {
+                path: path.display().to_string(),
+                holder: "unknown process".into(),
+            }),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+/// Whether a process with the pid is running on this machine, processes
+/// that can not be checked are taken as running.
+fn is_running(pid: u32) -> bool {
+    if cfg!(target_os = "linux") {
+        return PathBuf::from(format!("/proc/{pid}")).exists();
+    }
+    if cfg!(unix) {
+        return Command::new("kill")
+            .args(["-0", &pid.to_string()])
+            .output()
+            .map(|output| {
+                output.status.success()
+                    || !String::from_utf8_lossy(&output.stderr).contains("No such process")
+            })
+            .unwrap_or(true);
+    }
+    true
+}
+
+impl Drop for StateLock {
+    fn drop(&mut self) {
+        if let Err(err) = remove_file(&self.path) {
+            warn!("failed to release lock {}: {err}", self.path.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test;
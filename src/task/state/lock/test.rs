@@ -0,0 +1,52 @@
+use std::fs::{read_dir, remove_dir_all, write};
+use std::process;
+
+use crate::task::state::lock::{StateLock, LOCK_FILE};
+use crate::task::state::StateError;
+
+#[test]
+fn state_lock_is_exclusive() {
+    let state_dir = "tests/.state_lock_is_exclusive";
+    let _ = remove_dir_all(state_dir);
+
+    let lock = StateLock::acquire(state_dir).unwrap();
+    assert!(read_dir(state_dir)
+        .unwrap()
+        .any(|entry| entry.unwrap().file_name() == LOCK_FILE));
+
+    match StateLock::acquire(state_dir) {
+        Err(StateError::Locked { holder, .. }) => {
+            assert!(holder.starts_with(&format!("pid {} of user ", process::id())))
+        }
+        result => panic!("expected lock error, got {result:?}"),
+    }
+
+    drop(lock);
+    assert_eq!(read_dir(state_dir).unwrap().count(), 0);
+    assert!(StateLock::acquire(state_dir).is_ok());
+
+    remove_dir_all(state_dir).unwrap();
+}
+
+#[test]
+fn stale_state_lock_is_taken_over() {
+    let state_dir = "tests/.stale_state_lock_is_taken_over";
+    let _ = remove_dir_all(state_dir);
+    std::fs::create_dir_all(state_dir).unwrap();
+
+    let lock_path = format!("{state_dir}/{LOCK_FILE}");
+    let holder = |pid: u32| format!(r#"{{"pid":{pid},"user":"alice","started":1700000000}}"#);
+
+    write(&lock_path, holder(process::id())).unwrap();
+    assert!(matches!(
+        StateLock::acquire(state_dir),
+        Err(StateError::Locked { .. })
+    ));
+
+    write(&lock_path, holder(u32::MAX)).unwrap();
+    let lock = StateLock::acquire(state_dir).unwrap();
+    drop(lock);
+    assert_eq!(read_dir(state_dir).unwrap().count(), 0);
+
+    remove_dir_all(state_dir).unwrap();
+}
@@ -0,0 +1,117 @@
+use std::{fmt::Display, fs::File, io::Read, path::PathBuf};
+
+use flate2::read;
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use sha256::try_digest;
+
+use crate::task::state::StateError;
+use crate::task::utils::{current_user, format_unix, unix_now};
+use crate::APP_VERSION;
+
+/// Audit information of the state.
+/// ```yaml
+/// timestamp: 1700000000
+/// user: alice
+/// hostname: ci-runner-1
+/// version: 0.9.0
+/// sources:
+///   cluster.genin.yml: 5f2a...
+/// inventory: 91c0...
+/// ```
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct StateMetadata {
+    /// Seconds since unix epoch
+    pub timestamp: u64,
+    pub user: String,
+    pub hostname: String,
+    pub version: String,
+    /// Sha256 of each source config by path
+    #[serde(default)]
+    pub sources: IndexMap<String, String>,
+    /// Sha256 of the generated inventory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inventory: Option<String>,
+}
+
+impl StateMetadata {
+    /// Metadata of the state created right now by the current user.
+    pub fn current() -> Self {
+        Self {
+            timestamp: unix_now(),
+            user: current_user(),
+            hostname: gethostname::gethostname().to_string_lossy().into(),
+            version: APP_VERSION.into(),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_sources(self, sources: &[PathBuf]) -> Self {
+        Self {
+            sources: sources
+                .iter()
+                .map(|source| {
+                    (
+                        source.display().to_string(),
+                        try_digest(source.as_path()).unwrap_or_default(),
+                    )
+                })
+                .collect(),
+            ..self
+        }
+    }
+
+    pub fn with_inventory(self, inventory: Option<String>) -> Self {
+        Self { inventory, ..self }
+    }
+
+    /// Reads only the metadata of the state file, so that states with
+    /// encrypted secrets can be listed without the key.
+    pub fn read(path: &PathBuf) -> Result<Self, StateError> {
+        #[derive(Deserialize)]
+        struct Header {
+            #[serde(default)]
+            metadata: StateMetadata,
+        }
+
+        let mut buffer = Vec::new();
+        read::GzDecoder::new(File::open(path)?).read_to_end(&mut buffer)?;
+
+        Ok(serde_json::from_slice::<Header>(&buffer)?.metadata)
+    }
+
+    /// States written before the metadata was recorded have no timestamp.
+    pub fn is_empty(&self) -> bool {
+        self.timestamp == 0
+    }
+}
+
+impl Display for StateMetadata {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return Ok(());
+        }
+
+        writeln!(
+            f,
+            "Created: {} by {}@{} (genin {})",
+            format_unix(self.timestamp),
+            self.user,
+            self.hostname,
+            self.version
+        )?;
+        if !self.sources.is_empty() {
+            writeln!(f, "Sources:")?;
+            self.sources
+                .iter()
+                .try_for_each(|(path, hash)| writeln!(f, "  {path} sha256:{hash}"))?;
+        }
+        if let Some(inventory) = &self.inventory {
+            writeln!(f, "Inventory: sha256:{inventory}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test;
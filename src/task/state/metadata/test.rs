@@ -0,0 +1,48 @@
+use std::path::PathBuf;
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::task::state::metadata::StateMetadata;
+use crate::task::utils::{format_unix, parse_time, parse_time_until};
+
+#[test]
+fn state_metadata_display() {
+    let metadata = StateMetadata {
+        timestamp: 1700000000,
+        user: "alice".into(),
+        hostname: "ci-runner-1".into(),
+        version: "0.9.0".into(),
+        ..StateMetadata::default()
+    }
+    .with_sources(&[PathBuf::from("tests/resources/cluster.genin.yml")])
+    .with_inventory(Some("0123456789abcdef".into()));
+
+    insta::assert_display_snapshot!(metadata);
+    assert_eq!(StateMetadata::default().to_string(), "");
+}
+
+#[test]
+fn state_metadata_time_parsing() {
+    let time = UNIX_EPOCH + Duration::from_secs(1700000000);
+
+    assert_eq!(format_unix(1700000000), "2023-11-14T22:13:20Z");
+    assert_eq!(parse_time("2023-11-14T22:13:20Z"), Ok(time));
+    assert_eq!(parse_time("2023-11-14 22:13:20"), Ok(time));
+    assert_eq!(
+        parse_time("2023-11-14"),
+        Ok(time - Duration::from_secs(22 * 60 * 60 + 13 * 60 + 20))
+    );
+    assert!(parse_time("7d").is_ok());
+    assert!(parse_time("yesterday").is_err());
+}
+
+#[test]
+fn state_metadata_until_includes_the_day() {
+    let time = UNIX_EPOCH + Duration::from_secs(1700000000);
+    let until = parse_time_until("2023-11-14").unwrap();
+
+    assert!(time <= until);
+    assert!(UNIX_EPOCH + Duration::from_secs(1700006399) <= until);
+    assert!(UNIX_EPOCH + Duration::from_secs(1700006400) > until);
+    assert_eq!(parse_time_until("2023-11-14T22:13:20Z"), Ok(time));
+    assert!(parse_time_until("7d").is_ok());
+}
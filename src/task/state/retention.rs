@@ -0,0 +1,207 @@
+use std::fs::{read_link, remove_file};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use clap::ArgMatches;
+use log::warn;
+
+use crate::task::cluster::host::view::{FG_GREEN, FG_RED};
+use crate::task::state::backend::{GzipDir, StateBackend};
+use crate::task::state::{State, StateError, StateKind};
+use crate::task::utils::parse_duration;
+
+/// Which states `genin state prune` keeps. A state survives pruning if
+/// at least one of the policies keeps it. The state `latest` points at
+/// and its parent are never removed, so `genin state checkout previous`
+/// keeps working after pruning.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    pub keep_last: Option<usize>,
+    pub keep_newer_than: Option<Duration>,
+    pub keep_builds: bool,
+}
+
+impl<'a> TryFrom<&'a ArgMatches> for RetentionPolicy {
+    type Error = StateError;
+
+    fn try_from(args: &'a ArgMatches) -> Result<Self, Self::Error> {
+        let policy = Self {
+            keep_last: args.get_one::<usize>("keep-last").copied(),
+            keep_newer_than: args
+                .get_one::<String>("keep-newer-than")
+                .map(|duration| parse_duration(duration))
+                .transpose()
+                .map_err(StateError::Retention)?,
+            keep_builds: args.get_flag("keep-builds"),
+        };
+
+        if policy == Self::default() {
+            return Err(StateError::Retention(
+                "at least one of --keep-last, --keep-newer-than or --keep-builds is required"
+                    .into(),
+            ));
+        }
+
+        Ok(policy)
+    }
+}
+
+impl RetentionPolicy {
+    /// Returns the states that should be removed. Paths must be sorted
+    /// from the most recent state to the oldest one.
+    pub fn select(&self, paths: &[(SystemTime, PathBuf)], now: SystemTime) -> Vec<PathBuf> {
+        paths
+            .iter()
+            .enumerate()
+            .filter(|(position, (modified, path))| {
+                let by_count = self
+                    .keep_last
+                    .map(|keep_last| *position < keep_last)
+                    .unwrap_or_default();
+                let by_age = self
+                    .keep_newer_than
+                    .map(|duration| {
+                        now.duration_since(*modified)
+                            .map(|age| age < duration)
+                            .unwrap_or(true)
+                    })
+                    .unwrap_or_default();
+                let by_kind = self.keep_builds && is_build(path);
+
+                !(by_count || by_age || by_kind)
+            })
+            .map(|(_, (_, path))| path.clone())
+            .collect()
+    }
+}
+
+fn is_build(path: &PathBuf) -> bool {
+    match State::read_sealed(path) {
+        Ok(state) => matches!(state.kind, StateKind::Build),
+        Err(err) => {
+            // unreadable states are kept, `genin state verify` reports them
+            warn!("failed to read state {}: {err}", path.display());
+            true
+        }
+    }
+}
+
+/// Removes states according to the retention policy and returns
+/// removed (or to be removed with `dry_run`) paths.
+pub fn prune(
+    dir: &GzipDir,
+    policy: &RetentionPolicy,
+    dry_run: bool,
+) -> Result<Vec<PathBuf>, StateError> {
+    // pruning without knowing what to protect could remove them
+    let protected = [latest_target(&dir.dir)?, previous_target(dir)?];
+    let paths = dir
+        .paths_with_time()?
+        .into_iter()
+        .filter(|(_, path)| !protected.contains(&Some(path.clone())))
+        .collect::<Vec<(SystemTime, PathBuf)>>();
+
+    let to_remove = policy.select(&paths, SystemTime::now());
+    if !dry_run {
+        to_remove.iter().try_for_each(remove_file)?;
+    }
+
+    Ok(to_remove)
+}
+
+/// File the `latest` pointer refers to. For a regular `latest.gz`
+/// file this is the uid file with the same uid.
+fn latest_target(state_dir: &str) -> Result<Option<PathBuf>, StateError> {
+    let latest = Path::new(state_dir).join("latest.gz");
+    if latest.symlink_metadata().is_err() {
+        return Ok(None);
+    }
+    match read_link(&latest) {
+        Ok(target) => Ok(Some(Path::new(state_dir).join(target))),
+        Err(_) => Ok(Some(
+            Path::new(state_dir).join(format!("{}.gz", State::read_sealed(&latest)?.uid())),
+        )),
+    }
+}
+
+/// File of the state `genin state checkout previous` goes back to, none if
+/// there is no such state.
+fn previous_target(dir: &GzipDir) -> Result<Option<PathBuf>, StateError> {
+    match dir.previous() {
+        Ok(state) => Ok(Some(
+            Path::new(&dir.dir).join(format!("{}.gz", state.uid())),
+        )),
+        Err(StateError::NotFound(_)) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verification {
+    Ok(PathBuf),
+    Broken(PathBuf, String),
+    MissingLatest(PathBuf),
+}
+
+impl std::fmt::Display for Verification {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Verification::Ok(path) => write!(
+                f,
+                "{}ok{} {}",
+                FG_GREEN.get_prefix(),
+                FG_GREEN.get_suffix(),
+                path.display()
+            ),
+            Verification::Broken(path, err) => write!(
+                f,
+                "{}broken{} {}: {err}",
+                FG_RED.get_prefix(),
+                FG_RED.get_suffix(),
+                path.display()
+            ),
+            Verification::MissingLatest(path) => write!(
+                f,
+                "{}missing{} latest points at {} which does not exist",
+                FG_RED.get_prefix(),
+                FG_RED.get_suffix(),
+                path.display()
+            ),
+        }
+    }
+}
+
+impl Verification {
+    pub fn is_ok(&self) -> bool {
+        matches!(self, Verification::Ok(_))
+    }
+}
+
+/// Decompresses and deserializes every state in the state directory,
+/// including the one `latest` points at.
+pub fn verify(dir: &GzipDir) -> Result<Vec<Verification>, StateError> {
+    let check = |path: PathBuf| match State::read_sealed(&path) {
+        Ok(_) => Verification::Ok(path),
+        Err(err) => Verification::Broken(path, err.to_string()),
+    };
+
+    let mut report = dir
+        .paths_with_time()?
+        .into_iter()
+        .map(|(_, path)| check(path))
+        .collect::<Vec<Verification>>();
+
+    let latest = Path::new(&dir.dir).join("latest.gz");
+    match read_link(&latest) {
+        Ok(target) if !Path::new(&dir.dir).join(&target).is_file() => {
+            report.push(Verification::MissingLatest(target))
+        }
+        _ if latest.is_file() => report.push(check(latest)),
+        _ => report.push(Verification::MissingLatest(latest)),
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,75 @@
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use crate::task::state::retention::RetentionPolicy;
+use crate::task::utils::parse_duration;
+
+fn states(now: SystemTime) -> Vec<(SystemTime, PathBuf)> {
+    [1, 5, 30, 90]
+        .into_iter()
+        .map(|days| {
+            (
+                now - Duration::from_secs(days * 24 * 60 * 60),
+                PathBuf::from(format!("{days}d.gz")),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn retention_keep_last() {
+    let now = SystemTime::now();
+    let policy = RetentionPolicy {
+        keep_last: Some(2),
+        ..RetentionPolicy::default()
+    };
+
+    assert_eq!(
+        policy.select(&states(now), now),
+        vec![PathBuf::from("30d.gz"), PathBuf::from("90d.gz")]
+    );
+}
+
+#[test]
+fn retention_keep_newer_than_or_last() {
+    let now = SystemTime::now();
+    let policy = RetentionPolicy {
+        keep_last: Some(1),
+        keep_newer_than: Some(parse_duration("1w").unwrap()),
+        ..RetentionPolicy::default()
+    };
+
+    assert_eq!(
+        policy.select(&states(now), now),
+        vec![PathBuf::from("30d.gz"), PathBuf::from("90d.gz")]
+    );
+
+    let policy = RetentionPolicy {
+        keep_last: Some(3),
+        keep_newer_than: Some(parse_duration("2d").unwrap()),
+        ..RetentionPolicy::default()
+    };
+
+    assert_eq!(
+        policy.select(&states(now), now),
+        vec![PathBuf::from("90d.gz")]
+    );
+}
+
+#[test]
+fn retention_duration_parsing() {
+    assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+    assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(30 * 60)));
+    assert_eq!(parse_duration("12h"), Ok(Duration::from_secs(12 * 60 * 60)));
+    assert_eq!(
+        parse_duration("7d"),
+        Ok(Duration::from_secs(7 * 24 * 60 * 60))
+    );
+    assert_eq!(
+        parse_duration("2w"),
+        Ok(Duration::from_secs(2 * 7 * 24 * 60 * 60))
+    );
+    assert!(parse_duration("12").is_err());
+    assert!(parse_duration("d").is_err());
+    assert!(parse_duration("5y").is_err());
+}
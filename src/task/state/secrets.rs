@@ -0,0 +1,246 @@
+use std::{fs::read, io};
+
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use clap::ArgMatches;
+use serde::{Deserialize, Serialize};
+
+use crate::task::flv::{Failover, FailoverVariants};
+use crate::task::state::{State, StateError};
+
+const ENCRYPTED_PREFIX: &str = "genin:encrypted:";
+/// Shown in place of a secret in diffs and plans.
+pub const REDACTED: &str = "<changed>";
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+
+/// How secrets were stored in the state file.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SecretsMode {
+    #[default]
+    Plain,
+    Stripped,
+    Encrypted,
+}
+
+impl SecretsMode {
+    pub fn is_plain(&self) -> bool {
+        matches!(self, SecretsMode::Plain)
+    }
+}
+
+/// What to do with secrets when a state is written, the key of
+/// `Encrypt` also decrypts the states that are read.
+pub enum SecretsPolicy {
+    Plain,
+    Strip,
+    Encrypt(StateKey),
+}
+
+impl<'a> TryFrom<&'a ArgMatches> for SecretsPolicy {
+    type Error = io::Error;
+
+    /// Policy of `--state-key-file` (or the key passed with
+    /// `GENIN_STATE_KEY`) and `--strip-secrets`, the key wins if both
+    /// are given.
+    fn try_from(args: &'a ArgMatches) -> Result<Self, Self::Error> {
+        let arg = |name: &str| args.try_get_one::<String>(name).ok().flatten();
+        if let Some(key) = arg("state-key") {
+            return Ok(SecretsPolicy::Encrypt(StateKey::new(key.as_bytes())?));
+        }
+        if let Some(path) = arg("state-key-file") {
+            return Ok(SecretsPolicy::Encrypt(StateKey::new(&read(path)?)?));
+        }
+
+        match args.try_get_one::<bool>("strip-secrets") {
+            Ok(Some(true)) => Ok(SecretsPolicy::Strip),
+            _ => Ok(SecretsPolicy::Plain),
+        }
+    }
+}
+
+impl SecretsPolicy {
+    pub fn key(&self) -> Option<&StateKey> {
+        match self {
+            SecretsPolicy::Encrypt(key) => Some(key),
+            _ => None,
+        }
+    }
+}
+
+pub struct StateKey(ChaCha20Poly1305);
+
+impl StateKey {
+    /// Takes the key as is, passphrases are not accepted since there is
+    /// no key derivation. The key is 32 random bytes, either raw as from
+    /// `head -c 32 /dev/urandom` or in hex as from `openssl rand -hex 32`.
+    pub fn new(key: &[u8]) -> Result<Self, io::Error> {
+        let hex = std::str::from_utf8(key)
+            .map(str::trim)
+            .ok()
+            .filter(|hex| hex.len() == KEY_LEN * 2);
+        let bytes = match hex {
+            Some(hex) => (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+                .collect::<Option<Vec<u8>>>(),
+            None => (key.len() == KEY_LEN).then(|| key.to_vec()),
+        }
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "state encryption key must be {KEY_LEN} random bytes or {} hex digits, \
+                    generate one with `openssl rand -hex {KEY_LEN}`",
+                    KEY_LEN * 2
+                ),
+            )
+        })?;
+
+        Ok(Self(ChaCha20Poly1305::new(Key::from_slice(&bytes))))
+    }
+
+    pub fn encrypt(&self, secret: &str) -> String {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let mut sealed = nonce.to_vec();
+        // encryption into a vector can only fail on allocation
+        sealed.extend(self.0.encrypt(&nonce, secret.as_bytes()).unwrap());
+
+        format!(
+            "{ENCRYPTED_PREFIX}{}",
+            sealed
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect::<String>()
+        )
+    }
+
+    pub fn decrypt(&self, secret: &str) -> Result<String, StateError> {
+        let invalid = || StateError::Secrets("failed to decrypt state secrets, wrong key?".into());
+
+        let hex = secret.strip_prefix(ENCRYPTED_PREFIX).ok_or_else(invalid)?;
+        if hex.len() % 2 != 0 || hex.len() < NONCE_LEN * 2 {
+            return Err(invalid());
+        }
+        let sealed = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| invalid())?;
+
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let plain = self
+            .0
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| invalid())?;
+
+        String::from_utf8(plain).map_err(|_| invalid())
+    }
+}
+
+impl State {
+    /// Copy of the state with secrets handled according to the policy.
+    pub fn protected(&self, policy: &SecretsPolicy) -> State {
+        let mut state = self.clone();
+        match policy {
+            SecretsPolicy::Plain => {}
+            SecretsPolicy::Strip => {
+                state.vars.ansible_password = None;
+                state.vars.cartridge_cluster_cookie = None;
+                state
+                    .secrets_mut()
+                    .into_iter()
+                    .for_each(|(_, secret)| secret.clear());
+                state.secrets = SecretsMode::Stripped;
+            }
+            SecretsPolicy::Encrypt(key) => {
+                state
+                    .secrets_mut()
+                    .into_iter()
+                    .filter(|(_, secret)| !secret.is_empty())
+                    .for_each(|(_, secret)| *secret = key.encrypt(secret));
+                state.secrets = SecretsMode::Encrypted;
+            }
+        }
+        state
+    }
+
+    /// Decrypts secrets of the state read from disk with the key of the
+    /// policy.
+    pub fn reveal(&mut self, policy: &SecretsPolicy) -> Result<(), StateError> {
+        if !matches!(self.secrets, SecretsMode::Encrypted) {
+            return Ok(());
+        }
+
+        let key = policy.key().ok_or_else(|| {
+            StateError::Secrets(format!(
+                "state {} has encrypted secrets, pass the key with --state-key-file, \
+                GENIN_STATE_KEY_FILE or GENIN_STATE_KEY",
+                self.uid
+            ))
+        })?;
+
+        for (_, secret) in self.secrets_mut() {
+            if secret.starts_with(ENCRYPTED_PREFIX) {
+                *secret = key.decrypt(secret)?;
+            }
+        }
+        self.secrets = SecretsMode::Plain;
+
+        Ok(())
+    }
+
+    /// Names of the fields holding plaintext secrets.
+    pub fn plaintext_secrets(&mut self) -> Vec<&'static str> {
+        if !self.secrets.is_plain() {
+            return Vec::new();
+        }
+        let mut names = self
+            .secrets_mut()
+            .into_iter()
+            .filter(|(_, secret)| !secret.is_empty())
+            .map(|(name, _)| name)
+            .collect::<Vec<&str>>();
+        names.dedup();
+        names
+    }
+
+    fn secrets_mut(&mut self) -> Vec<(&'static str, &mut String)> {
+        let mut secrets = Vec::new();
+        if let Some(password) = self.vars.ansible_password.as_mut() {
+            secrets.push(("ansible_password", password));
+        }
+        if let Some(cookie) = self.vars.cartridge_cluster_cookie.as_mut() {
+            secrets.push(("cartridge_cluster_cookie", cookie));
+        }
+        if let Some(failover) = self.vars.cartridge_failover_params.as_mut() {
+            secrets.extend(failover_secrets(failover));
+        }
+        secrets.extend(failover_secrets(&mut self.failover));
+        secrets
+    }
+}
+
+/// Whether the field, named by its dotted path, holds a secret: the
+/// cluster cookie or any password.
+pub fn is_secret(field: &str) -> bool {
+    let name = field.rsplit('.').next().unwrap_or(field);
+    name == "password" || name.ends_with("_password") || name == "cartridge_cluster_cookie"
+}
+
+fn failover_secrets(failover: &mut Failover) -> Vec<(&'static str, &mut String)> {
+    match &mut failover.failover_variants {
+        FailoverVariants::StateboardVariant(params) => {
+            vec![("stateboard_params.password", &mut params.password)]
+        }
+        FailoverVariants::ETCD2Variant(params) => {
+            vec![("etcd2_params.password", &mut params.password)]
+        }
+        FailoverVariants::Disabled => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,137 @@
+use std::path::{Path, PathBuf};
+
+use clap::{Arg, ArgAction, Command};
+
+use crate::task::cluster::Cluster;
+use crate::task::flv::FailoverVariants;
+use crate::task::state::secrets::{SecretsMode, SecretsPolicy, StateKey};
+use crate::task::state::{State, StateError};
+
+const KEY: &str = "5c7e3a1f09d24b6e8a0f4c2d7b19e6a35f0c8d2e4a6b1c3d9e7f0a2b4c6d8e1f";
+
+fn state() -> State {
+    let cluster = Cluster::read(Path::new("tests/resources/cluster.genin.yml"), None).unwrap();
+
+    State::builder()
+        .uid(vec![PathBuf::from("tests/resources/cluster.genin.yml")])
+        .unwrap()
+        .make_build_state()
+        .path("state.gz")
+        .hosts(&cluster.hosts)
+        .vars(&cluster.vars)
+        .failover(&cluster.failover)
+        .topology(&cluster.topology)
+        .build()
+        .unwrap()
+}
+
+fn stateboard_password(state: &State) -> String {
+    match &state.failover.failover_variants {
+        FailoverVariants::StateboardVariant(params) => params.password.clone(),
+        variant => panic!("unexpected failover variant {variant:?}"),
+    }
+}
+
+#[test]
+fn secrets_encrypt_and_decrypt() {
+    let key = StateKey::new(KEY.as_bytes()).unwrap();
+    let encrypted = key.encrypt("myapp-cookie");
+
+    assert!(encrypted.starts_with("genin:encrypted:"));
+    assert!(!encrypted.contains("myapp-cookie"));
+    assert_ne!(encrypted, key.encrypt("myapp-cookie"));
+    assert_eq!(key.decrypt(&encrypted).unwrap(), "myapp-cookie");
+
+    let wrong = StateKey::new(&[7; 32]).unwrap();
+    assert!(matches!(
+        wrong.decrypt(&encrypted),
+        Err(StateError::Secrets(_))
+    ));
+    assert!(StateKey::new(b" \n").is_err());
+}
+
+#[test]
+fn secrets_key_is_32_bytes() {
+    let raw = (0..KEY.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&KEY[i..i + 2], 16).unwrap())
+        .collect::<Vec<u8>>();
+    let encrypted = StateKey::new(&raw).unwrap().encrypt("myapp-cookie");
+
+    // the hex key from a file with a trailing newline is the same key
+    let hex = StateKey::new(format!("{KEY}\n").as_bytes()).unwrap();
+    assert_eq!(hex.decrypt(&encrypted).unwrap(), "myapp-cookie");
+
+    // passphrases are not stretched into keys
+    assert!(StateKey::new(b"correct horse battery staple").is_err());
+    assert!(StateKey::new(&KEY.as_bytes()[..62]).is_err());
+    assert!(StateKey::new(KEY.replace('5', "z").as_bytes()).is_err());
+}
+
+#[test]
+fn secrets_protected_state() {
+    let mut state = state();
+    assert_eq!(
+        state.plaintext_secrets(),
+        vec![
+            "ansible_password",
+            "cartridge_cluster_cookie",
+            "stateboard_params.password"
+        ]
+    );
+
+    let mut stripped = state.protected(&SecretsPolicy::Strip);
+    assert_eq!(stripped.secrets, SecretsMode::Stripped);
+    assert_eq!(stripped.vars.ansible_password, None);
+    assert_eq!(stripped.vars.cartridge_cluster_cookie, None);
+    assert_eq!(stateboard_password(&stripped), "");
+    assert!(stripped.plaintext_secrets().is_empty());
+
+    let key = StateKey::new(KEY.as_bytes()).unwrap();
+    let encrypted = state.protected(&SecretsPolicy::Encrypt(key));
+    assert_eq!(encrypted.secrets, SecretsMode::Encrypted);
+
+    let key = StateKey::new(KEY.as_bytes()).unwrap();
+    assert_eq!(
+        key.decrypt(encrypted.vars.ansible_password.as_ref().unwrap())
+            .unwrap(),
+        state.vars.ansible_password.clone().unwrap()
+    );
+    assert_eq!(
+        key.decrypt(&stateboard_password(&encrypted)).unwrap(),
+        stateboard_password(&state)
+    );
+
+    // the original state is left untouched
+    assert_eq!(state.secrets, SecretsMode::Plain);
+    assert!(!stateboard_password(&state).starts_with("genin:encrypted:"));
+}
+
+#[test]
+fn secrets_policy_from_args() {
+    let policy = |args: &[&str]| {
+        SecretsPolicy::try_from(
+            &Command::new("genin")
+                .arg(
+                    Arg::new("state-key")
+                        .long("state-key")
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("strip-secrets")
+                        .long("strip-secrets")
+                        .action(ArgAction::SetTrue),
+                )
+                .try_get_matches_from([&["genin"], args].concat())
+                .unwrap(),
+        )
+        .unwrap()
+    };
+
+    assert!(matches!(policy(&[]), SecretsPolicy::Plain));
+    assert!(matches!(policy(&["--strip-secrets"]), SecretsPolicy::Strip));
+    assert!(matches!(
+        policy(&["--strip-secrets", "--state-key", KEY]),
+        SecretsPolicy::Encrypt(_)
+    ));
+}
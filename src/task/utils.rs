@@ -1,4 +1,10 @@
-use std::{fs::File, io, path::PathBuf};
+use std::{
+    env,
+    fs::File,
+    io,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use log::warn;
 
@@ -36,3 +42,68 @@ pub fn create_file_or_copy(path: PathBuf, force: bool) -> Result<File, io::Error
         ))
     }
 }
+
+/// Parses durations like `90s`, `30m`, `12h`, `7d` or `2w`.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| format!("duration {s} has no unit (s, m, h, d, w)"))?;
+    let (amount, unit) = s.split_at(split);
+    let amount = amount
+        .parse::<u64>()
+        .map_err(|err| format!("invalid duration {s}: {err}"))?;
+
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        unit => return Err(format!("unknown duration unit {unit} in {s}")),
+    };
+
+    Ok(Duration::from_secs(amount * seconds))
+}
+
+/// Parses a point in time given as RFC 3339 timestamp, a date
+/// (`2023-11-14`) or a duration ago (`7d`).
+pub fn parse_time(s: &str) -> Result<SystemTime, String> {
+    let s = s.trim();
+    if let Ok(ago) = parse_duration(s) {
+        return Ok(SystemTime::now() - ago);
+    }
+    humantime::parse_rfc3339_weak(s)
+        .or_else(|_| humantime::parse_rfc3339_weak(&format!("{s} 00:00:00")))
+        .map_err(|err| format!("invalid time {s}: {err}"))
+}
+
+/// Same as [`parse_time`] for the upper bound, a date stands for the last
+/// moment of the day, so that `--until 2023-11-14` includes the whole day.
+pub fn parse_time_until(s: &str) -> Result<SystemTime, String> {
+    let s = s.trim();
+    match humantime::parse_rfc3339_weak(&format!("{s} 00:00:00")) {
+        Ok(day) => Ok(day + Duration::from_secs(24 * 60 * 60) - Duration::from_nanos(1)),
+        Err(_) => parse_time(s),
+    }
+}
+
+/// Seconds since unix epoch.
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or_default()
+}
+
+/// Formats seconds since unix epoch as RFC 3339 timestamp.
+pub fn format_unix(secs: u64) -> String {
+    humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(secs)).to_string()
+}
+
+/// Name of the OS user running genin.
+pub fn current_user() -> String {
+    env::var("USER")
+        .or_else(|_| env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".into())
+}
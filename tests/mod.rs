@@ -4,7 +4,7 @@ use std::{
     process::{Command, Output},
 };
 
-const INVENTORY_HEADER_SIZE: usize = 2;
+const INVENTORY_HEADER_SIZE: usize = 3;
 const GENIN_CMD: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/target/debug/genin",);
 
 fn create_file(path: &str) {
@@ -140,7 +140,10 @@ fn warning_message_on_build_output() {
 
     assert_eq!(
         result,
-        b"WARN: the target file inventory.yml already exists so the new file will be \
+        b"WARN: state will contain plaintext secrets (ansible_password, cartridge_cluster_cookie, \
+            stateboard_params.password), use --state-key-file to encrypt them or --strip-secrets \
+            to leave them out\n\
+            WARN: the target file inventory.yml already exists so the new file will be \
             saved with name inventory.copy.yml\n"
     );
 }
@@ -434,7 +437,12 @@ fn build_with_upgrade() {
             .expect("Failed to execute command");
 
         let result = build_result_from_output(output);
-        let result = format!("{result}\n{}", read_to_string(&upg_src).unwrap());
+        // only the first upgrade changes the cluster and writes a new state
+        let (warnings, result): (Vec<&str>, Vec<&str>) = result
+            .split_inclusive('\n')
+            .partition(|line| line.starts_with("WARN: state will contain plaintext secrets"));
+        assert_eq!(warnings.len(), usize::from(i == 0));
+        let result = format!("{}\n{}", result.concat(), read_to_string(&upg_src).unwrap());
         insta::assert_display_snapshot!("cluster_new_genin", result);
         assert_eq!(read_dir(&state_dir).unwrap().count(), 3);
     }
@@ -501,3 +509,863 @@ fn remove_role_with_undescrore_name() {
     let result = format!("{result}\n{}", read_to_string(&src).unwrap());
     insta::assert_display_snapshot!("undescrore_names", result);
 }
+
+#[test]
+fn checkout_previous_state() {
+    let src = "tests/resources/cluster.genin.yml";
+    let upg_src = "tests/resources/cluster-new.genin.yml";
+    let base_dir = "tests/.checkout_previous_state";
+    let state_dir = format!("{base_dir}/.geninstate");
+    cleanup_test_dir(base_dir);
+
+    for (source, inventory) in [(src, "inventory.yml"), (upg_src, "upg_inventory.yml")] {
+        Command::new(GENIN_CMD)
+            .arg("build")
+            .arg("-s")
+            .arg(source)
+            .arg("-o")
+            .arg(format!("{base_dir}/{inventory}"))
+            .arg("--state-dir")
+            .arg(&state_dir)
+            .arg("-q")
+            .arg("-y")
+            .output()
+            .expect("Failed to execute command");
+    }
+    assert_eq!(read_dir(&state_dir).unwrap().count(), 3);
+
+    let output = Command::new(GENIN_CMD)
+        .arg("state")
+        .arg("checkout")
+        .arg("previous")
+        .arg("-o")
+        .arg(format!("{base_dir}/checkout_inventory.yml"))
+        .arg("--state-dir")
+        .arg(&state_dir)
+        .arg("-q")
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+
+    assert_eq!(read_dir(&state_dir).unwrap().count(), 3);
+    assert_eq!(
+        read_inventory(&format!("{base_dir}/inventory.yml")),
+        read_inventory(&format!("{base_dir}/checkout_inventory.yml"))
+    );
+
+    let output = Command::new(GENIN_CMD)
+        .arg("state")
+        .arg("log")
+        .arg("--state-dir")
+        .arg(&state_dir)
+        .output()
+        .expect("Failed to execute command");
+
+    let log = build_result_from_output(output);
+    assert_eq!(log.matches("\nstate ").count(), 1);
+    assert!(log.contains("Build: -s tests/resources/cluster.genin.yml"));
+}
+
+#[test]
+fn prune_and_verify_states() {
+    let src = "tests/resources/cluster.genin.yml";
+    let upg_src = "tests/resources/cluster-new.genin.yml";
+    let base_dir = "tests/.prune_and_verify_states";
+    let state_dir = format!("{base_dir}/.geninstate");
+    cleanup_test_dir(base_dir);
+
+    let last_src = format!("{base_dir}/cluster.genin.yml");
+    std::fs::write(
+        &last_src,
+        read_to_string(upg_src).unwrap().replacen(
+            "replicasets_count: 3",
+            "replicasets_count: 4",
+            1,
+        ),
+    )
+    .unwrap();
+
+    for source in [src, upg_src, &last_src] {
+        Command::new(GENIN_CMD)
+            .arg("build")
+            .arg("-s")
+            .arg(source)
+            .arg("-o")
+            .arg(format!("{base_dir}/inventory.yml"))
+            .arg("--state-dir")
+            .arg(&state_dir)
+            .arg("-f")
+            .arg("-q")
+            .arg("-y")
+            .output()
+            .expect("Failed to execute command");
+    }
+    assert_eq!(read_dir(&state_dir).unwrap().count(), 4);
+
+    let prune = |dry_run: bool| {
+        let mut command = Command::new(GENIN_CMD);
+        command
+            .arg("state")
+            .arg("prune")
+            .arg("--keep-last")
+            .arg("0")
+            .arg("--state-dir")
+            .arg(&state_dir);
+        if dry_run {
+            command.arg("--dry-run");
+        }
+        command.output().expect("Failed to execute command")
+    };
+
+    let output = prune(true);
+    assert!(output.status.success());
+    assert!(build_result_from_output(output).contains("Would remove"));
+    assert_eq!(read_dir(&state_dir).unwrap().count(), 4);
+
+    // latest target and its parent always survive pruning
+    let output = prune(false);
+    assert!(output.status.success());
+    assert_eq!(read_dir(&state_dir).unwrap().count(), 3);
+
+    let output = Command::new(GENIN_CMD)
+        .arg("state")
+        .arg("checkout")
+        .arg("previous")
+        .arg("-o")
+        .arg(format!("{base_dir}/checkout_inventory.yml"))
+        .arg("--state-dir")
+        .arg(&state_dir)
+        .arg("-q")
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+
+    let verify = || {
+        Command::new(GENIN_CMD)
+            .arg("state")
+            .arg("verify")
+            .arg("--state-dir")
+            .arg(&state_dir)
+            .output()
+            .expect("Failed to execute command")
+    };
+    assert!(verify().status.success());
+
+    std::fs::write(format!("{state_dir}/latest.gz"), "broken").unwrap();
+    assert!(!verify().status.success());
+}
+
+fn read_state(path: &str) -> String {
+    use std::io::Read;
+
+    let mut state = String::new();
+    flate2::read::GzDecoder::new(File::open(path).unwrap())
+        .read_to_string(&mut state)
+        .unwrap();
+    state
+}
+
+/// Host or instance with the name in the hosts tree of a state.
+fn find_in_state<'a>(host: &'a serde_json::Value, name: &str) -> Option<&'a serde_json::Value> {
+    if host["name"] == name {
+        return Some(host);
+    }
+    ["hosts", "instances"]
+        .into_iter()
+        .flat_map(|key| host[key].as_array().into_iter().flatten())
+        .find_map(|host| find_in_state(host, name))
+}
+
+const STATE_KEY: &str = "5c7e3a1f09d24b6e8a0f4c2d7b19e6a35f0c8d2e4a6b1c3d9e7f0a2b4c6d8e1f";
+
+#[test]
+fn state_secrets() {
+    let src = "tests/resources/cluster.genin.yml";
+    let upg_src = "tests/resources/cluster-new.genin.yml";
+    let base_dir = "tests/.state_secrets";
+    let state_dir = format!("{base_dir}/.geninstate");
+    cleanup_test_dir(base_dir);
+
+    // encrypted with the key from the environment
+    let output = Command::new(GENIN_CMD)
+        .arg("build")
+        .arg("-s")
+        .arg(src)
+        .arg("-o")
+        .arg(format!("{base_dir}/inventory.yml"))
+        .arg("--state-dir")
+        .arg(&state_dir)
+        .arg("-q")
+        .arg("-y")
+        .env("GENIN_STATE_KEY", STATE_KEY)
+        .output()
+        .expect("Failed to execute command");
+    assert!(!build_result_from_output(output).contains("plaintext secrets"));
+
+    let state = read_state(&format!("{state_dir}/latest.gz"));
+    assert!(state.contains("genin:encrypted:"));
+    assert!(!state.contains("myapp-cookie"));
+
+    let diff = |key: Option<&str>| {
+        let mut command = Command::new(GENIN_CMD);
+        command
+            .arg("state")
+            .arg("diff")
+            .arg("latest")
+            .arg("latest")
+            .arg("--state-dir")
+            .arg(&state_dir);
+        if let Some(key) = key {
+            command.env("GENIN_STATE_KEY", key);
+        }
+        command.output().expect("Failed to execute command")
+    };
+    assert!(!diff(None).status.success());
+    assert!(!diff(Some(&"0".repeat(64))).status.success());
+    assert!(diff(Some(STATE_KEY)).status.success());
+
+    // without the key the build fails instead of starting over in plaintext
+    let build = |command: &str| {
+        Command::new(GENIN_CMD)
+            .arg("build")
+            .args(command.split_whitespace())
+            .arg("-s")
+            .arg(upg_src)
+            .arg("-o")
+            .arg(format!("{base_dir}/upg_inventory.yml"))
+            .arg("--state-dir")
+            .arg(&state_dir)
+            .arg("-q")
+            .arg("-y")
+            .output()
+            .expect("Failed to execute command")
+    };
+    for command in ["", "--check", "--dry-run"] {
+        assert!(!build(command).status.success(), "build {command}");
+    }
+    let state = read_state(&format!("{state_dir}/latest.gz"));
+    assert!(state.contains("genin:encrypted:"));
+
+    // the log does not need the secrets
+    let output = Command::new(GENIN_CMD)
+        .arg("state")
+        .arg("log")
+        .arg("--state-dir")
+        .arg(&state_dir)
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+
+    // stripped secrets are taken from the config on the next build
+    cleanup_test_dir(base_dir);
+    Command::new(GENIN_CMD)
+        .arg("build")
+        .arg("-s")
+        .arg(src)
+        .arg("-o")
+        .arg(format!("{base_dir}/inventory.yml"))
+        .arg("--state-dir")
+        .arg(&state_dir)
+        .arg("--strip-secrets")
+        .arg("-q")
+        .arg("-y")
+        .output()
+        .expect("Failed to execute command");
+
+    let state = read_state(&format!("{state_dir}/latest.gz"));
+    assert!(!state.contains("myapp-cookie"));
+    assert!(!state.contains("ansible_password"));
+
+    Command::new(GENIN_CMD)
+        .arg("build")
+        .arg("-s")
+        .arg(upg_src)
+        .arg("-o")
+        .arg(format!("{base_dir}/upg_inventory.yml"))
+        .arg("--state-dir")
+        .arg(&state_dir)
+        .arg("--strip-secrets")
+        .arg("-q")
+        .arg("-y")
+        .output()
+        .expect("Failed to execute command");
+
+    let inventory = read_to_string(format!("{base_dir}/upg_inventory.yml")).unwrap();
+    assert!(inventory.contains("cartridge_cluster_cookie: myapp-cookie"));
+    assert!(inventory.contains("ansible_password: vagrant"));
+}
+
+#[test]
+fn build_with_locked_state() {
+    let src = "tests/resources/cluster.genin.yml";
+    let base_dir = "tests/.build_with_locked_state";
+    let state_dir = format!("{base_dir}/.geninstate");
+    cleanup_test_dir(&state_dir);
+
+    // the lock of a running process, the test itself
+    std::fs::write(
+        format!("{state_dir}/.lock"),
+        format!(
+            r#"{{"pid":{},"user":"alice","started":1700000000}}"#,
+            std::process::id()
+        ),
+    )
+    .unwrap();
+
+    let build = || {
+        Command::new(GENIN_CMD)
+            .arg("build")
+            .arg("-s")
+            .arg(src)
+            .arg("-o")
+            .arg(format!("{base_dir}/inventory.yml"))
+            .arg("--state-dir")
+            .arg(&state_dir)
+            .arg("-f")
+            .arg("-q")
+            .arg("-y")
+            .output()
+            .expect("Failed to execute command")
+    };
+
+    let output = build();
+    assert!(!output.status.success());
+    assert!(build_result_from_output(output).contains(&format!(
+        "pid {} of user alice since 2023-11-14T22:13:20Z",
+        std::process::id()
+    )));
+    assert_eq!(read_dir(&state_dir).unwrap().count(), 1);
+
+    // the lock of a process that is gone is stale
+    std::fs::write(
+        format!("{state_dir}/.lock"),
+        format!(
+            r#"{{"pid":{},"user":"alice","started":1700000000}}"#,
+            u32::MAX
+        ),
+    )
+    .unwrap();
+    assert!(build().status.success());
+    // the lock is released after the build
+    assert_eq!(read_dir(&state_dir).unwrap().count(), 2);
+}
+
+#[test]
+fn git_state_backend() {
+    let src = "tests/resources/cluster.genin.yml";
+    let upg_src = "tests/resources/cluster-new.genin.yml";
+    let base_dir = "tests/.git_state_backend";
+    let state_dir = format!("{base_dir}/state");
+    cleanup_test_dir(base_dir);
+
+    for source in [src, upg_src] {
+        let output = Command::new(GENIN_CMD)
+            .arg("build")
+            .arg("-s")
+            .arg(source)
+            .arg("-o")
+            .arg(format!("{base_dir}/inventory.yml"))
+            .arg("--state-dir")
+            .arg(&state_dir)
+            .arg("--state-backend")
+            .arg("git")
+            .arg("-f")
+            .arg("-q")
+            .arg("-y")
+            .env("GIT_AUTHOR_NAME", "genin")
+            .env("GIT_AUTHOR_EMAIL", "genin@example.com")
+            .env("GIT_COMMITTER_NAME", "genin")
+            .env("GIT_COMMITTER_EMAIL", "genin@example.com")
+            .output()
+            .expect("Failed to execute command");
+        assert!(output.status.success());
+    }
+
+    let state = read_to_string(format!("{state_dir}/state.json")).unwrap();
+    assert!(state.contains("\"kind\": \"Upgrade\""));
+    assert!(state.contains("\"tests/resources/cluster-new.genin.yml\""));
+
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(&state_dir)
+        .arg("log")
+        .arg("--format=%B")
+        .output()
+        .expect("Failed to execute command");
+    let log = String::from_utf8(output.stdout).unwrap();
+
+    assert_eq!(log.matches("\nstate ").count(), 2);
+    assert!(log.starts_with("Upgrade: -s tests/resources/cluster-new.genin.yml"));
+    assert!(log.contains("Topology changes:\n  + router-3"));
+    assert!(log.contains("Build: -s tests/resources/cluster.genin.yml"));
+
+    // state commands read the history from the repository
+    let genin = |args: &[&str]| {
+        Command::new(GENIN_CMD)
+            .args(args)
+            .arg("--state-dir")
+            .arg(&state_dir)
+            .arg("--state-backend")
+            .arg("git")
+            .env("GIT_AUTHOR_NAME", "genin")
+            .env("GIT_AUTHOR_EMAIL", "genin@example.com")
+            .env("GIT_COMMITTER_NAME", "genin")
+            .env("GIT_COMMITTER_EMAIL", "genin@example.com")
+            .output()
+            .expect("Failed to execute command")
+    };
+
+    let output = genin(&["list-state"]);
+    assert!(output.status.success());
+    let states = build_result_from_output(output);
+    assert_eq!(states.matches("State file: ").count(), 2);
+    assert!(states.contains(":state.json"));
+
+    let output = genin(&["state", "log"]);
+    assert!(output.status.success());
+    assert_eq!(
+        build_result_from_output(output).matches("\nstate ").count(),
+        2
+    );
+
+    let output = genin(&["state", "diff"]);
+    assert!(output.status.success());
+    assert!(build_result_from_output(output).contains("+ router-3"));
+
+    let output = genin(&["state", "verify"]);
+    assert!(output.status.success());
+    assert!(!build_result_from_output(output).contains("broken"));
+
+    assert!(!genin(&["state", "prune", "--keep-last", "1"])
+        .status
+        .success());
+
+    let checkout_inventory = format!("{base_dir}/checkout_inventory.yml");
+    let output = genin(&[
+        "state",
+        "checkout",
+        "previous",
+        "-q",
+        "-o",
+        &checkout_inventory,
+    ]);
+    assert!(output.status.success());
+    let state = read_to_string(format!("{state_dir}/state.json")).unwrap();
+    assert!(state.contains("\"kind\": \"Build\""));
+    assert!(!read_to_string(&checkout_inventory)
+        .unwrap()
+        .contains("router-3"));
+}
+
+#[test]
+fn list_state_with_filters() {
+    let src = "tests/resources/cluster.genin.yml";
+    let upg_src = "tests/resources/cluster-new.genin.yml";
+    let base_dir = "tests/.list_state_with_filters";
+    let state_dir = format!("{base_dir}/.geninstate");
+    cleanup_test_dir(base_dir);
+
+    for (source, user) in [(src, "alice"), (upg_src, "bob")] {
+        Command::new(GENIN_CMD)
+            .arg("build")
+            .arg("-s")
+            .arg(source)
+            .arg("-o")
+            .arg(format!("{base_dir}/inventory.yml"))
+            .arg("--state-dir")
+            .arg(&state_dir)
+            .arg("-f")
+            .arg("-q")
+            .arg("-y")
+            .env("USER", user)
+            .output()
+            .expect("Failed to execute command");
+    }
+
+    let list_state = |filters: &[&str]| {
+        let output = Command::new(GENIN_CMD)
+            .arg("list-state")
+            .arg("--state-dir")
+            .arg(&state_dir)
+            .args(filters)
+            .output()
+            .expect("Failed to execute command");
+        build_result_from_output(output)
+    };
+
+    let all = list_state(&[]);
+    assert_eq!(all.matches("State file:").count(), 2);
+    // the most recent state goes first
+    assert!(all.find(" by bob@").unwrap() < all.find(" by alice@").unwrap());
+    assert!(all.contains(&format!("genin {}", env!("CARGO_PKG_VERSION"))));
+    assert!(all.contains("  tests/resources/cluster.genin.yml sha256:"));
+    assert!(all.contains("Inventory: sha256:"));
+
+    let alice = list_state(&["--user", "alice"]);
+    assert_eq!(alice.matches("State file:").count(), 1);
+    assert!(alice.contains("Build: -s tests/resources/cluster.genin.yml"));
+
+    assert_eq!(
+        list_state(&["--since", "1h"])
+            .matches("State file:")
+            .count(),
+        2
+    );
+    assert_eq!(
+        list_state(&["--until", "2020-01-01"])
+            .matches("State file:")
+            .count(),
+        0
+    );
+}
+
+#[test]
+fn build_check_and_verify_inventory() {
+    let src = "tests/resources/cluster.genin.yml";
+    let upg_src = "tests/resources/cluster-new.genin.yml";
+    let base_dir = "tests/.build_check_and_verify_inventory";
+    let state_dir = format!("{base_dir}/.geninstate");
+    let inventory = format!("{base_dir}/inventory.yml");
+    cleanup_test_dir(base_dir);
+
+    let build = |source: &str, check: bool| {
+        let mut command = Command::new(GENIN_CMD);
+        command
+            .arg("build")
+            .arg("-s")
+            .arg(source)
+            .arg("-o")
+            .arg(&inventory)
+            .arg("--state-dir")
+            .arg(&state_dir)
+            .arg("-f")
+            .arg("-q")
+            .arg("-y");
+        if check {
+            command.arg("--check");
+        }
+        command.output().expect("Failed to execute command")
+    };
+    let verify = || {
+        Command::new(GENIN_CMD)
+            .arg("verify")
+            .arg(&inventory)
+            .output()
+            .expect("Failed to execute command")
+    };
+
+    assert!(build(src, false).status.success());
+    assert!(build(src, true).status.success());
+    assert!(verify().status.success());
+
+    // check does not touch the state or the inventory
+    let output = build(upg_src, true);
+    assert!(!output.status.success());
+    assert!(build_result_from_output(output).contains("all.hosts.router-3"));
+    assert_eq!(read_dir(&state_dir).unwrap().count(), 2);
+    assert!(verify().status.success());
+
+    let edited = read_to_string(&inventory)
+        .unwrap()
+        .replace("http_port: 8081", "http_port: 9999");
+    std::fs::write(&inventory, edited).unwrap();
+
+    let output = build(src, true);
+    assert!(!output.status.success());
+    assert!(build_result_from_output(output)
+        .contains("all.hosts.router-1.config.http_port: 9999 -> 8081"));
+    assert!(!verify().status.success());
+}
+
+#[test]
+fn build_dry_run() {
+    let src = "tests/resources/cluster.genin.yml";
+    let upg_src = "tests/resources/cluster-new.genin.yml";
+    let base_dir = "tests/.build_dry_run";
+    let state_dir = format!("{base_dir}/.geninstate");
+    let inventory = format!("{base_dir}/inventory.yml");
+    cleanup_test_dir(base_dir);
+
+    let build = |source: &str, dry_run: bool| {
+        let mut command = Command::new(GENIN_CMD);
+        command
+            .arg("build")
+            .arg("-s")
+            .arg(source)
+            .arg("-o")
+            .arg(&inventory)
+            .arg("--state-dir")
+            .arg(&state_dir)
+            .arg("-f")
+            .arg("-q")
+            .arg("-y");
+        if dry_run {
+            command.arg("--dry-run");
+        }
+        command.output().expect("Failed to execute command")
+    };
+
+    let output = build(src, true);
+    assert!(output.status.success());
+    assert!(build_result_from_output(output).contains("a new one would be created"));
+    assert!(!std::path::Path::new(&state_dir).exists());
+    assert!(!std::path::Path::new(&inventory).exists());
+
+    assert!(build(src, false).status.success());
+    let states = read_dir(&state_dir).unwrap().count();
+    let generated = read_to_string(&inventory).unwrap();
+
+    let output = build(src, true);
+    assert!(output.status.success());
+    assert_eq!(build_result_from_output(output), "No changes\n");
+
+    let output = build(upg_src, true);
+    assert!(output.status.success());
+    let plan = build_result_from_output(output);
+    assert!(plan.contains("+ router-3"));
+    assert!(plan.contains("+ server-3"));
+    assert!(plan.contains("cartridge_app_name: myapp -> new-app"));
+    assert!(plan.contains("ansible_password: <changed> -> <changed>"));
+    assert!(!plan.contains("\"password\":\"password\""));
+
+    assert_eq!(read_dir(&state_dir).unwrap().count(), states);
+    assert_eq!(read_to_string(&inventory).unwrap(), generated);
+}
+
+#[test]
+fn explain_instance_placement() {
+    let src = "tests/resources/cluster.genin.yml";
+    let upg_src = "tests/resources/cluster-new.genin.yml";
+    let base_dir = "tests/.explain_instance_placement";
+    let state_dir = format!("{base_dir}/.geninstate");
+    cleanup_test_dir(base_dir);
+
+    let explain = |source: &str, instance: &str| {
+        Command::new(GENIN_CMD)
+            .arg("explain")
+            .arg(instance)
+            .arg("-s")
+            .arg(source)
+            .arg("--state-dir")
+            .arg(&state_dir)
+            .output()
+            .expect("Failed to execute command")
+    };
+
+    // without a state the placement of a fresh build is explained
+    let output = explain(src, "router-1");
+    assert!(output.status.success());
+    let explanation = build_result_from_output(output);
+    assert!(explanation.contains("chosen as least loaded host"));
+
+    assert!(Command::new(GENIN_CMD)
+        .arg("build")
+        .arg("-s")
+        .arg(src)
+        .arg("-o")
+        .arg(format!("{base_dir}/inventory.yml"))
+        .arg("--state-dir")
+        .arg(&state_dir)
+        .arg("-q")
+        .arg("-y")
+        .output()
+        .expect("Failed to execute command")
+        .status
+        .success());
+
+    // instances from the state keep their hosts, new ones are spread again
+    let output = explain(upg_src, "router-1");
+    assert!(output.status.success());
+    assert!(build_result_from_output(output).contains("kept its host from the previous state"));
+
+    let output = explain(upg_src, "router-3");
+    assert!(output.status.success());
+    assert!(build_result_from_output(output).contains("chosen as least loaded host"));
+
+    assert!(!explain(upg_src, "router-9").status.success());
+
+    // the state is merged the same way build merges it
+    let output = Command::new(GENIN_CMD)
+        .arg("explain")
+        .arg("router-1")
+        .arg("-s")
+        .arg(upg_src)
+        .arg("--state-dir")
+        .arg(&state_dir)
+        .arg("--reassign-ports")
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    assert!(build_result_from_output(output).contains("kept its host from the previous state"));
+}
+
+#[test]
+fn lint_cluster_config() {
+    let base_dir = "tests/.lint_cluster_config";
+    let broken = format!("{base_dir}/cluster.genin.yml");
+    cleanup_test_dir(base_dir);
+
+    let lint = |source: &str| {
+        Command::new(GENIN_CMD)
+            .arg("lint")
+            .arg("-s")
+            .arg(source)
+            .output()
+            .expect("Failed to execute command")
+    };
+
+    let output = lint("tests/resources/cluster.genin.yml");
+    assert!(output.status.success());
+    assert!(build_result_from_output(output).contains("0 errors, 3 warnings"));
+
+    std::fs::write(
+        &broken,
+        read_to_string("tests/resources/cluster.genin.yml")
+            .unwrap()
+            .replace("address: 192.168.16.12", "address: 192.168.16.11"),
+    )
+    .unwrap();
+    let output = lint(&broken);
+    assert!(!output.status.success());
+    let result = build_result_from_output(output);
+    assert!(result.contains("error[duplicate-host-address]"));
+    assert!(result.contains("1 errors, 3 warnings"));
+
+    let mut config = read_to_string(&broken).unwrap();
+    config.push_str("lint:\n  allow:\n    - duplicate-host-address\n");
+    std::fs::write(&broken, config).unwrap();
+    assert!(lint(&broken).status.success());
+}
+
+#[test]
+fn stable_ports_across_upgrades() {
+    let base_dir = "tests/.stable_ports_across_upgrades";
+    let state_dir = format!("{base_dir}/.geninstate");
+    let inventory = format!("{base_dir}/inventory.yml");
+    cleanup_test_dir(base_dir);
+
+    let config = read_to_string("tests/resources/cluster.genin.yml").unwrap();
+    let build = |replicasets: usize, reassign: bool| {
+        let source = format!("{base_dir}/cluster-{replicasets}.genin.yml");
+        std::fs::write(
+            &source,
+            config.replace(
+                "replicasets_count: 2",
+                &format!("replicasets_count: {replicasets}"),
+            ),
+        )
+        .unwrap();
+
+        let mut command = Command::new(GENIN_CMD);
+        command
+            .arg("build")
+            .arg("-s")
+            .arg(&source)
+            .arg("-o")
+            .arg(&inventory)
+            .arg("--state-dir")
+            .arg(&state_dir)
+            .arg("-f")
+            .arg("-q")
+            .arg("-y");
+        if reassign {
+            command.arg("--reassign-ports");
+        }
+        assert!(command.output().unwrap().status.success());
+
+        let inventory: serde_yaml::Value =
+            serde_yaml::from_str(&read_to_string(&inventory).unwrap()).unwrap();
+        let uri = |name: &str| {
+            inventory["all"]["hosts"][name]["config"]["advertise_uri"]
+                .as_str()
+                .map(String::from)
+        };
+        let state: serde_json::Value =
+            serde_json::from_str(&read_state(&format!("{state_dir}/latest.gz"))).unwrap();
+        let config = &find_in_state(&state["hosts"], "stateboard").unwrap()["config"];
+        let stateboard = (config["http_port"].as_u64(), config["binary_port"].as_u64());
+        (uri("storage-1-2"), uri("router-2"), stateboard)
+    };
+
+    let (storage, router, stateboard) = build(2, false);
+    assert_eq!(storage.as_deref(), Some("192.168.16.12:3032"));
+    assert_eq!(router.as_deref(), Some("192.168.16.12:3031"));
+    assert_eq!(stateboard, (Some(8084), Some(3034)));
+
+    // removing router-2 leaves storage-1-2 and the stateboard alone
+    assert_eq!(build(1, false), (storage.clone(), None, stateboard));
+
+    // router-2 comes back to the lowest free port instead of colliding, new
+    // replicasets do not take the ports of the stateboard
+    assert_eq!(
+        build(3, false),
+        (storage.clone(), router.clone(), stateboard)
+    );
+
+    let (storage, router, stateboard) = build(3, true);
+    assert_eq!(storage.as_deref(), Some("192.168.16.12:3031"));
+    assert_eq!(router.as_deref(), Some("192.168.16.12:3032"));
+    assert_eq!(stateboard, (Some(8084), Some(3034)));
+}
+
+#[test]
+fn host_config_edits_reach_the_state() {
+    let base_dir = "tests/.host_config_edits_reach_the_state";
+    let state_dir = format!("{base_dir}/.geninstate");
+    cleanup_test_dir(base_dir);
+
+    let config = read_to_string("tests/resources/cluster.genin.yml").unwrap();
+    let build = |config: &str| {
+        let source = format!("{base_dir}/cluster.genin.yml");
+        std::fs::write(&source, config).unwrap();
+        let output = Command::new(GENIN_CMD)
+            .arg("build")
+            .arg("-s")
+            .arg(&source)
+            .arg("-o")
+            .arg(format!("{base_dir}/inventory.yml"))
+            .arg("--state-dir")
+            .arg(&state_dir)
+            .arg("-q")
+            .arg("-y")
+            .output()
+            .expect("Failed to execute command");
+        assert!(
+            output.status.success(),
+            "{}",
+            build_result_from_output(output)
+        );
+    };
+    build(&config);
+    // the state is only saved again when the instances change, the new
+    // replicas make sure it is
+    build(
+        &config
+            .replace("replication_factor: 2", "replication_factor: 3")
+            .replace(
+                "          address: 192.168.16.12\n",
+                "          address: 192.168.16.12\n          \
+                   weight: 3\n          \
+                   http_port_range: 8081-8099\n          \
+                   binary_port_range: 3031-3099\n          \
+                   reserved_ports: [3099]\n          \
+                   labels:\n            rack: r1\n          \
+                   dedicated_to: [storage, router]\n          \
+                   denied_roles: [api]\n",
+            ),
+    );
+
+    let state: serde_json::Value =
+        serde_json::from_str(&read_state(&format!("{state_dir}/latest.gz"))).unwrap();
+    let config = &find_in_state(&state["hosts"], "server-2").unwrap()["config"];
+    assert_eq!(config["weight"], 3);
+    assert_eq!(config["http_port_range"], "8081-8099");
+    assert_eq!(config["binary_port_range"], "3031-3099");
+    assert_eq!(config["reserved_ports"], serde_json::json!([3099]));
+    assert_eq!(config["labels"]["rack"], "r1");
+    assert_eq!(
+        config["allowed_roles"],
+        serde_json::json!(["storage", "router"])
+    );
+    assert_eq!(config["denied_roles"], serde_json::json!(["api"]));
+}